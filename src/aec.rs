@@ -0,0 +1,586 @@
+//! Acoustic echo cancellation: a block frequency-domain NLMS filter learns the path from the
+//! playback reference to the microphone and subtracts the predicted echo, so speech over music
+//! or our own replies still reaches the recognizer.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cpal::{Host, Stream};
+
+use crate::capture;
+use crate::mfcc::{fft, ifft};
+use crate::wav::read_wav;
+use crate::{arg_value, match_input_device};
+
+const DEFAULT_TAIL_MS: u64 = 128;
+// Adaptation step; lower converges slower but is steadier during double talk.
+const STEP: f32 = 0.5;
+// Geigel double-talk detector: the mic louder than this fraction of the recent reference
+// peak means the near end is talking, so the filter holds still for a while.
+const DOUBLE_TALK: f32 = 0.6;
+const DOUBLE_TALK_HOLD: Duration = Duration::from_millis(30);
+// Reference older than the echo tail plus this is dropped while the microphone isn't reading.
+const MAX_LEAD: Duration = Duration::from_millis(500);
+const FILE_BLOCK: Duration = Duration::from_millis(20);
+
+pub enum ReferenceSource {
+    /// A second capture device, e.g. a PulseAudio/PipeWire monitor of the speakers.
+    Device(String),
+    /// A WAV file, or raw 16-bit mono PCM at the microphone rate from a file or named pipe.
+    File(PathBuf),
+}
+
+pub struct AecConfig {
+    pub source: ReferenceSource,
+    pub tail: Duration,
+}
+
+impl AecConfig {
+    /// `--aec-reference <device>` or `--aec-reference-file <path>`, with `--aec-tail-ms`
+    /// covering the playback delay plus the room's echo.
+    pub fn from_args(args: &[(String, String)]) -> Option<Result<Self, String>> {
+        let source = match (arg_value(args, "--aec-reference"), arg_value(args, "--aec-reference-file")) {
+            (None, None) => return None,
+            (Some(_), Some(_)) => {
+                return Some(Err("Use either --aec-reference or --aec-reference-file, not both".to_string()));
+            }
+            (Some(device), None) => ReferenceSource::Device(device.to_string()),
+            (None, Some(path)) => ReferenceSource::File(PathBuf::from(path)),
+        };
+        Some(tail_from_args(args).map(|tail| AecConfig { source, tail }))
+    }
+}
+
+pub fn tail_from_args(args: &[(String, String)]) -> Result<Duration, String> {
+    match arg_value(args, "--aec-tail-ms") {
+        Some(v) => v
+            .parse::<u64>()
+            .ok()
+            .filter(|ms| (8..=1000).contains(ms))
+            .map(Duration::from_millis)
+            .ok_or_else(|| format!("Invalid value for --aec-tail-ms: '{}' (expected 8 to 1000)", v)),
+        None => Ok(Duration::from_millis(DEFAULT_TAIL_MS)),
+    }
+}
+
+/// Playback audio waiting to be lined up with the microphone, resampled to its rate.
+///
+/// The two streams start at different times and run on different clocks, so the queue is
+/// trimmed to the newest audio when the microphone starts reading and whenever playback gets
+/// more than the echo tail ahead. Playback that arrives after the microphone already needed
+/// it is dropped rather than delayed, which would leave the filter behind for good.
+pub struct Reference {
+    queue: Mutex<Queue>,
+    resampler: Mutex<Resampler>,
+}
+
+struct Queue {
+    samples: VecDeque<f32>,
+    /// Samples the microphone read before playback delivered them; that much of the next
+    /// playback is already in the past.
+    lag: usize,
+    /// Set once the microphone reads delivered playback; cleared by a pause in playback.
+    synced: bool,
+    /// Whether playback delivered anything since it last paused.
+    delivered: bool,
+    /// Echo tail and queue bound, in samples.
+    tail: usize,
+    max_len: usize,
+}
+
+impl Queue {
+    fn new(sample_rate: u32, tail: Duration) -> Self {
+        let samples = |d: Duration| (d.as_secs_f64() * sample_rate as f64) as usize;
+        Queue {
+            samples: VecDeque::new(),
+            lag: 0,
+            synced: false,
+            delivered: false,
+            tail: samples(tail),
+            max_len: samples(tail + MAX_LEAD),
+        }
+    }
+}
+
+struct Resampler {
+    to: u32,
+    /// Position of the next output sample, in input samples after `last`.
+    pos: f64,
+    last: f32,
+}
+
+impl Reference {
+    fn new(sample_rate: u32, tail: Duration) -> Self {
+        Reference {
+            queue: Mutex::new(Queue::new(sample_rate, tail)),
+            resampler: Mutex::new(Resampler { to: sample_rate, pos: 0.0, last: 0.0 }),
+        }
+    }
+
+    pub fn push(&self, pcm: &[i16], sample_rate: u32) {
+        let mut rs = self.resampler.lock().unwrap();
+        let step = sample_rate as f64 / rs.to as f64;
+        let mut out = Vec::with_capacity((pcm.len() as f64 / step) as usize + 1);
+        for &s in pcm {
+            let s = s as f32 / 32768.0;
+            while rs.pos < 1.0 {
+                out.push(rs.last + (s - rs.last) * rs.pos as f32);
+                rs.pos += step;
+            }
+            rs.pos -= 1.0;
+            rs.last = s;
+        }
+        drop(rs);
+        let mut queue = self.queue.lock().unwrap();
+        let late = queue.lag.min(out.len());
+        queue.lag -= late;
+        queue.samples.extend(&out[late..]);
+        queue.delivered |= !out.is_empty();
+        if queue.samples.len() > queue.max_len {
+            let excess = queue.samples.len() - queue.max_len;
+            queue.samples.drain(..excess);
+        }
+    }
+
+    /// The `n` samples playing while the microphone recorded its next `n`; silence where
+    /// playback hasn't delivered them.
+    fn take(&self, n: usize) -> Vec<f32> {
+        let mut queue = self.queue.lock().unwrap();
+        // Playback ahead of the microphone: keep only what lines up with this block.
+        if !queue.synced || queue.samples.len() > n + queue.tail {
+            let excess = queue.samples.len().saturating_sub(n);
+            queue.samples.drain(..excess);
+            queue.lag = 0;
+            queue.synced = queue.delivered;
+        }
+        let available = n.min(queue.samples.len());
+        let mut out: Vec<f32> = queue.samples.drain(..available).collect();
+        out.resize(n, 0.0);
+        if queue.synced {
+            queue.lag += n - available;
+            // Behind by more than the tail is a pause in playback, not a late delivery.
+            if queue.lag > queue.tail {
+                queue.lag = 0;
+                queue.synced = false;
+                queue.delivered = false;
+            }
+        }
+        out
+    }
+
+    fn set_sample_rate(&self, sample_rate: u32, tail: Duration) {
+        *self.queue.lock().unwrap() = Queue::new(sample_rate, tail);
+        *self.resampler.lock().unwrap() = Resampler { to: sample_rate, pos: 0.0, last: 0.0 };
+    }
+}
+
+/// Partitioned-block frequency-domain NLMS: the taps are split into blocks that are filtered and
+/// adapted as products per FFT bin, so the cost per sample grows with the log of the block instead
+/// of with the tail. The output lags the microphone by one block.
+pub struct EchoCanceller {
+    block: usize,
+    taps: usize,
+    /// Weight spectra per partition, each 2 × `block` bins as (re, im).
+    weights: Vec<(Vec<f32>, Vec<f32>)>,
+    /// Reference spectra per partition, a ring with the newest at `newest`.
+    spectra: Vec<(Vec<f32>, Vec<f32>)>,
+    newest: usize,
+    /// The last two reference blocks, the overlap-save input.
+    window: Vec<f32>,
+    mic: Vec<f32>,
+    reference: Vec<f32>,
+    output: VecDeque<f32>,
+    /// Partition whose weights are cut back to `block` taps next, one per block in turn.
+    constrain: usize,
+    /// Sliding-window maximum of |reference| over the taps for the Geigel detector:
+    /// (sample index, level), levels decreasing from the front.
+    peaks: VecDeque<(u64, f32)>,
+    sample: u64,
+    hold: usize,
+    hold_left: usize,
+    /// The near end talked somewhere in the pending block, so it doesn't adapt.
+    talking: bool,
+}
+
+impl EchoCanceller {
+    pub fn new(sample_rate: u32, tail: Duration) -> Self {
+        // About 4 ms, a power of two for the FFT.
+        let block = (sample_rate as usize / 250).next_power_of_two().max(16);
+        let taps = ((tail.as_secs_f64() * sample_rate as f64) as usize).max(1);
+        let parts = taps.div_ceil(block);
+        let spectrum = || (vec![0.0; 2 * block], vec![0.0; 2 * block]);
+        EchoCanceller {
+            block,
+            taps: parts * block,
+            weights: (0..parts).map(|_| spectrum()).collect(),
+            spectra: (0..parts).map(|_| spectrum()).collect(),
+            newest: 0,
+            window: vec![0.0; 2 * block],
+            mic: Vec::with_capacity(block),
+            reference: Vec::with_capacity(block),
+            output: VecDeque::with_capacity(block),
+            constrain: 0,
+            peaks: VecDeque::new(),
+            sample: 0,
+            hold: (DOUBLE_TALK_HOLD.as_secs_f64() * sample_rate as f64) as usize,
+            hold_left: 0,
+            talking: false,
+        }
+    }
+
+    /// Removes the echo of `reference` from `mic`, delayed by one block less a sample.
+    pub fn process(&mut self, mic: &mut [f32], reference: &[f32]) {
+        for (d, &x) in mic.iter_mut().zip(reference) {
+            while self.peaks.back().is_some_and(|(_, p)| *p <= x.abs()) {
+                self.peaks.pop_back();
+            }
+            self.peaks.push_back((self.sample, x.abs()));
+            while self.peaks.front().is_some_and(|(i, _)| i + self.taps as u64 <= self.sample) {
+                self.peaks.pop_front();
+            }
+            self.sample += 1;
+            let peak = self.peaks.front().map_or(0.0, |(_, p)| *p);
+            if d.abs() >= DOUBLE_TALK * peak {
+                self.hold_left = self.hold;
+            } else {
+                self.hold_left = self.hold_left.saturating_sub(1);
+            }
+            self.talking |= self.hold_left > 0;
+
+            self.mic.push(*d);
+            self.reference.push(x);
+            if self.mic.len() == self.block {
+                self.run_block();
+            }
+            *d = self.output.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    fn run_block(&mut self) {
+        let (b, n, parts) = (self.block, 2 * self.block, self.weights.len());
+        self.window.copy_within(b.., 0);
+        self.window[b..].copy_from_slice(&self.reference);
+        self.newest = (self.newest + parts - 1) % parts;
+        let (re, im) = &mut self.spectra[self.newest];
+        re.copy_from_slice(&self.window);
+        im.fill(0.0);
+        fft(re, im);
+
+        let mut echo = (vec![0.0; n], vec![0.0; n]);
+        let mut power = vec![0.0; n];
+        for (p, (wr, wi)) in self.weights.iter().enumerate() {
+            let (xr, xi) = &self.spectra[(self.newest + p) % parts];
+            for k in 0..n {
+                echo.0[k] += wr[k] * xr[k] - wi[k] * xi[k];
+                echo.1[k] += wr[k] * xi[k] + wi[k] * xr[k];
+                power[k] += xr[k] * xr[k] + xi[k] * xi[k];
+            }
+        }
+        ifft(&mut echo.0, &mut echo.1);
+        // Overlap-save: only the second half is the linear convolution.
+        let mut error = (vec![0.0; n], vec![0.0; n]);
+        for i in 0..b {
+            error.0[b + i] = self.mic[i] - echo.0[b + i];
+        }
+        self.output.extend(&error.0[b..]);
+        self.mic.clear();
+        self.reference.clear();
+
+        let talking = std::mem::take(&mut self.talking);
+        if talking || power.iter().sum::<f32>() < 1e-6 * n as f32 {
+            return;
+        }
+        fft(&mut error.0, &mut error.1);
+        for (p, (wr, wi)) in self.weights.iter_mut().enumerate() {
+            let (xr, xi) = &self.spectra[(self.newest + p) % parts];
+            for k in 0..n {
+                let step = STEP / (power[k] + 1e-3);
+                wr[k] += step * (xr[k] * error.0[k] + xi[k] * error.1[k]);
+                wi[k] += step * (xr[k] * error.1[k] - xi[k] * error.0[k]);
+            }
+        }
+        // Per-bin updates leak into the second half of the impulse response, which overlap-save
+        // would wrap around; cutting one partition back per block keeps that in check cheaply.
+        let (wr, wi) = &mut self.weights[self.constrain];
+        ifft(wr, wi);
+        wr[b..].fill(0.0);
+        wi.fill(0.0);
+        fft(wr, wi);
+        self.constrain = (self.constrain + 1) % parts;
+    }
+}
+
+/// Echo cancellation for the live pipeline: the canceller plus the reference it reads.
+pub struct Aec {
+    pub reference: Arc<Reference>,
+    canceller: Mutex<EchoCanceller>,
+    tail: Duration,
+}
+
+impl Aec {
+    pub fn new(sample_rate: u32, tail: Duration) -> Self {
+        Aec {
+            reference: Arc::new(Reference::new(sample_rate, tail)),
+            canceller: Mutex::new(EchoCanceller::new(sample_rate, tail)),
+            tail,
+        }
+    }
+
+    pub fn process(&self, pcm: &[i16]) -> Vec<i16> {
+        let reference = self.reference.take(pcm.len());
+        let mut x: Vec<f32> = pcm.iter().map(|&s| s as f32 / 32768.0).collect();
+        self.canceller.lock().unwrap().process(&mut x, &reference);
+        x.iter().map(|&s| (s * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16).collect()
+    }
+
+    /// The echo path is learned per rate, so a new rate starts over.
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        self.reference.set_sample_rate(sample_rate, self.tail);
+        *self.canceller.lock().unwrap() = EchoCanceller::new(sample_rate, self.tail);
+    }
+}
+
+/// Starts feeding `reference`; a device source returns the stream, which must be kept alive.
+pub fn start_reference(
+    source: &ReferenceSource,
+    host: &Host,
+    sample_rate: u32,
+    reference: Arc<Reference>,
+) -> Result<Option<Stream>, String> {
+    match source {
+        ReferenceSource::Device(name) => {
+            let device = match_input_device(host, name)
+                .ok_or_else(|| format!("AEC reference device '{}' not found", name))?;
+            reference_stream(&device, reference).map(Some)
+        }
+        ReferenceSource::File(path) => {
+            spawn_reference_file(path, sample_rate, reference)?;
+            Ok(None)
+        }
+    }
+}
+
+fn reference_stream(device: &cpal::Device, reference: Arc<Reference>) -> Result<Stream, String> {
+    let (stream, _) = capture::mono_stream(device, "AEC reference stream", move |mono, rate| {
+        reference.push(mono, rate)
+    })?;
+    Ok(stream)
+}
+
+// A WAV file is played out in real time once; anything else is read as raw PCM, paced by
+// the clock for regular files and by the writer for pipes.
+fn spawn_reference_file(path: &Path, sample_rate: u32, reference: Arc<Reference>) -> Result<(), String> {
+    let is_wav = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+    let (rate, mut source): (u32, Box<dyn Read + Send>) = if is_wav {
+        let audio = read_wav(path)?;
+        let bytes: Vec<u8> = audio.samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        (audio.sample_rate, Box::new(std::io::Cursor::new(bytes)))
+    } else {
+        let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        (sample_rate, Box::new(file))
+    };
+    let paced = is_wav || std::fs::metadata(path).map(|m| m.is_file()).unwrap_or(false);
+    let display = path.display().to_string();
+    std::thread::spawn(move || {
+        let mut block = vec![0u8; (rate as f64 * FILE_BLOCK.as_secs_f64()) as usize * 2];
+        let started = Instant::now();
+        let mut sent = Duration::ZERO;
+        loop {
+            let n = match source.read(&mut block) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    eprintln!("AEC reference {}: {}[ERR]", display, e);
+                    break;
+                }
+            };
+            let pcm: Vec<i16> = block[..n - n % 2]
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            reference.push(&pcm, rate);
+            if paced {
+                sent += Duration::from_secs_f64(pcm.len() as f64 / rate as f64);
+                if let Some(wait) = sent.checked_sub(started.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+            }
+        }
+        eprintln!("AEC reference {} ended", display);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_signals::{noise, power};
+
+    const SR: u32 = 16000;
+
+    // Playback delayed by 30 ms with a decaying room response.
+    fn echo(reference: &[f32]) -> Vec<f32> {
+        let delay = 480;
+        let response: Vec<f32> = (0..600)
+            .map(|i| {
+                let sign = if i % 7 == 0 { -1.0 } else { 1.0 };
+                sign * 0.05 * (-(i as f32) / 80.0).exp()
+            })
+            .collect();
+        (0..reference.len())
+            .map(|n| {
+                response
+                    .iter()
+                    .enumerate()
+                    .filter(|(k, _)| n >= delay + k)
+                    .map(|(k, h)| h * reference[n - delay - k])
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cancels_echo_of_the_reference() {
+        let len = 4 * SR as usize;
+        let reference = noise(0.3, len, 0x1234_5678);
+        let mic = echo(&reference);
+        let mut out = mic.clone();
+        let mut aec = EchoCanceller::new(SR, Duration::from_millis(DEFAULT_TAIL_MS));
+        for (m, r) in out.chunks_mut(160).zip(reference.chunks(160)) {
+            aec.process(m, r);
+        }
+        let tail = len - SR as usize / 2;
+        let erle = 10.0 * (power(&mic[tail..]) / power(&out[tail..])).log10();
+        assert!(erle > 20.0, "echo return loss enhancement {} dB", erle);
+    }
+
+    #[test]
+    fn keeps_near_end_speech_during_playback() {
+        let len = 4 * SR as usize;
+        let reference = noise(0.3, len, 0x8765_4321);
+        let tone = |i: usize| 0.3 * (2.0 * std::f32::consts::PI * 400.0 * i as f32 / SR as f32).sin();
+        let near: Vec<f32> = (0..len).map(|i| if i > len / 2 { tone(i) } else { 0.0 }).collect();
+        let mic: Vec<f32> = echo(&reference).iter().zip(&near).map(|(e, s)| e + s).collect();
+        let mut out = mic.clone();
+        let mut aec = EchoCanceller::new(SR, Duration::from_millis(DEFAULT_TAIL_MS));
+        for (m, r) in out.chunks_mut(160).zip(reference.chunks(160)) {
+            aec.process(m, r);
+        }
+        let tail = len - SR as usize / 2;
+        let lag = aec.block - 1;
+        let residual: Vec<f32> = out[tail..].iter().zip(&near[tail - lag..]).map(|(o, s)| o - s).collect();
+        assert!(power(&residual) < 0.1 * power(&near[tail..]));
+    }
+
+    #[test]
+    fn cancels_echo_at_48_khz_over_the_full_tail() {
+        let rate = 48000;
+        let len = 3 * rate as usize;
+        let reference = noise(0.3, len, 0x4800_0001);
+        // A single reflection near the end of the 128 ms tail.
+        let delay = 5800;
+        let mic: Vec<f32> = (0..len).map(|n| if n >= delay { 0.4 * reference[n - delay] } else { 0.0 }).collect();
+        let mut out = mic.clone();
+        let mut aec = EchoCanceller::new(rate, Duration::from_millis(DEFAULT_TAIL_MS));
+        for (m, r) in out.chunks_mut(480).zip(reference.chunks(480)) {
+            aec.process(m, r);
+        }
+        let tail = len - rate as usize / 2;
+        let erle = 10.0 * (power(&mic[tail..]) / power(&out[tail..])).log10();
+        assert!(erle > 20.0, "echo return loss enhancement {} dB", erle);
+    }
+
+    #[test]
+    fn double_talk_peak_follows_the_tail_window() {
+        let mut aec = EchoCanceller::new(SR, Duration::from_millis(8));
+        let taps = aec.taps;
+        let mut reference = noise(0.5, 4 * taps, 0x0bad_cafe);
+        reference[taps] = 0.9;
+        for (n, x) in reference.iter().enumerate() {
+            aec.process(&mut [0.0], &[*x]);
+            let window = &reference[(n + 1).saturating_sub(taps)..=n];
+            let expected = window.iter().fold(0.0f32, |m, x| m.max(x.abs()));
+            assert_eq!(aec.peaks.front().unwrap().1, expected, "sample {}", n);
+        }
+    }
+
+    fn to_pcm(signal: &[f32]) -> Vec<i16> {
+        signal.iter().map(|s| (s * 32768.0) as i16).collect()
+    }
+
+    // Echo return loss enhancement over the last half second of `Aec` output.
+    fn erle(mic: &[i16], out: &[i16]) -> f32 {
+        let tail = mic.len() - SR as usize / 2;
+        let float = |pcm: &[i16]| pcm.iter().map(|&s| s as f32 / 32768.0).collect::<Vec<_>>();
+        10.0 * (power(&float(&mic[tail..])) / power(&float(&out[tail..]))).log10()
+    }
+
+    #[test]
+    fn lines_up_a_reference_that_starts_ahead_of_the_microphone() {
+        let aec = Aec::new(SR, Duration::from_millis(DEFAULT_TAIL_MS));
+        // Playback started well before the microphone; none of it is in the echo.
+        aec.reference.push(&to_pcm(&noise(0.3, 3 * SR as usize / 5, 0x5eed_0001)), SR);
+        let reference = to_pcm(&noise(0.3, 4 * SR as usize, 0x5eed_0002));
+        let mic = to_pcm(&echo(&reference.iter().map(|&s| s as f32 / 32768.0).collect::<Vec<_>>()));
+        let mut out = Vec::with_capacity(mic.len());
+        for (m, r) in mic.chunks(160).zip(reference.chunks(160)) {
+            aec.reference.push(r, SR);
+            out.extend(aec.process(m));
+        }
+        let erle = erle(&mic, &out);
+        assert!(erle > 20.0, "echo return loss enhancement {} dB", erle);
+    }
+
+    #[test]
+    fn keeps_alignment_when_the_reference_arrives_late() {
+        let aec = Aec::new(SR, Duration::from_millis(DEFAULT_TAIL_MS));
+        let reference = to_pcm(&noise(0.3, 4 * SR as usize, 0x5eed_0003));
+        let mic = to_pcm(&echo(&reference.iter().map(|&s| s as f32 / 32768.0).collect::<Vec<_>>()));
+        let blocks: Vec<(&[i16], &[i16])> = mic.chunks(160).zip(reference.chunks(160)).collect();
+        let mut out = Vec::with_capacity(mic.len());
+        let mut late = Vec::new();
+        for (n, (m, r)) in blocks.into_iter().enumerate() {
+            // After a second, playback stalls for 80 ms and then delivers the backlog at once.
+            if (100..108).contains(&n) {
+                late.extend_from_slice(r);
+            } else {
+                aec.reference.push(&late, SR);
+                late.clear();
+                aec.reference.push(r, SR);
+            }
+            out.extend(aec.process(m));
+        }
+        let erle = erle(&mic, &out);
+        assert!(erle > 20.0, "echo return loss enhancement {} dB", erle);
+    }
+
+    #[test]
+    fn a_pause_in_playback_is_not_taken_as_lag() {
+        let tail = Duration::from_millis(DEFAULT_TAIL_MS);
+        let reference = Reference::new(SR, tail);
+        reference.push(&[1000; 160], SR);
+        reference.take(160);
+        // Far longer than the tail without playback, then playback resumes.
+        for _ in 0..50 {
+            reference.take(160);
+        }
+        reference.push(&[2000; 160], SR);
+        let level = 2000.0 / 32768.0;
+        // The first sample is the resampler's held value from before the pause.
+        assert!(reference.take(160)[1..].iter().all(|s| (s - level).abs() < 1e-6));
+    }
+
+    #[test]
+    fn reference_is_resampled_and_padded() {
+        let reference = Reference::new(SR, Duration::from_millis(DEFAULT_TAIL_MS));
+        reference.push(&vec![1000; 4800], 48000);
+        let taken = reference.take(2000);
+        let level = 1000.0 / 32768.0;
+        assert!(taken[1..1600].iter().all(|s| (s - level).abs() < 1e-6));
+        assert!(taken[1600..].iter().all(|s| *s == 0.0));
+    }
+}
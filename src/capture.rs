@@ -0,0 +1,150 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, SampleFormat, Stream};
+
+use crate::wav::{WavAudio, read_wav};
+use crate::{arg_value, input_config, match_input_device};
+
+/// `--device` if given, else the default input device.
+pub fn input_device(args: &[(String, String)]) -> Result<Device, String> {
+    let host = cpal::default_host();
+    match arg_value(args, "--device") {
+        Some(name) => match_input_device(&host, name)
+            .ok_or_else(|| format!("Input device '{}' not found", name)),
+        None => host
+            .default_input_device()
+            .ok_or_else(|| "No default input device available".to_string()),
+    }
+}
+
+/// Enrollment audio: the given WAV files (directories are searched for `*.wav`), or else
+/// `--enroll-clips` recordings of `--enroll-secs` each from the input device.
+pub fn enrollment_clips(
+    positionals: &[String],
+    args: &[(String, String)],
+    default_count: u64,
+    default_secs: u64,
+    prompt: &str,
+) -> Result<Vec<(String, WavAudio)>, String> {
+    if !positionals.is_empty() {
+        return collect_clips(positionals)?
+            .iter()
+            .map(|p| read_wav(p).map(|audio| (p.display().to_string(), audio)))
+            .collect();
+    }
+    let number = |key: &str, default: u64| -> Result<u64, String> {
+        match arg_value(args, key) {
+            Some(v) => v
+                .parse::<u64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| format!("Invalid value for {}: '{}'", key, v)),
+            None => Ok(default),
+        }
+    };
+    let count = number("--enroll-clips", default_count)?;
+    let secs = number("--enroll-secs", default_secs)?;
+
+    let device = input_device(args)?;
+    println!("Recording from {}", device.name().unwrap_or_default());
+
+    let mut clips = Vec::new();
+    for i in 1..=count {
+        println!("Clip {}/{}: {} for {} seconds, starting now...", i, count, prompt, secs);
+        let audio = record(&device, Duration::from_secs(secs))?;
+        clips.push((format!("Clip {}", i), audio));
+    }
+    Ok(clips)
+}
+
+/// Records `length` of mono audio from `device` at its default rate.
+pub fn record(device: &Device, length: Duration) -> Result<WavAudio, String> {
+    let captured: Arc<Mutex<Vec<i16>>> = Arc::new(Mutex::new(Vec::new()));
+    let buf = captured.clone();
+    let (stream, sample_rate) = mono_stream(device, "input stream", move |mono, _| {
+        buf.lock().unwrap().extend_from_slice(mono)
+    })?;
+    std::thread::sleep(length);
+    drop(stream);
+
+    let samples = std::mem::take(&mut *captured.lock().unwrap());
+    Ok(WavAudio { sample_rate, samples })
+}
+
+/// Starts `device` at its default config, handing every callback's audio to `sink`
+/// downmixed to mono, along with its rate. Returns the stream, which must be kept alive,
+/// and the rate.
+pub fn mono_stream(
+    device: &Device,
+    name: &'static str,
+    sink: impl Fn(&[i16], u32) + Send + 'static,
+) -> Result<(Stream, u32), String> {
+    let (supported, config) = input_config(device)?;
+    let channels = config.channels as usize;
+    let rate = config.sample_rate.0;
+    let err_fn = move |e| eprintln!("Error on {}: {}[ERR]", name, e);
+
+    let stream = match supported.sample_format() {
+        SampleFormat::I16 => device.build_input_stream(
+            &config,
+            move |data: &[i16], _: &cpal::InputCallbackInfo| sink(&downmix(data, channels, |s| s as i32), rate),
+            err_fn,
+            None,
+        ),
+        SampleFormat::U16 => device.build_input_stream(
+            &config,
+            move |data: &[u16], _: &cpal::InputCallbackInfo| {
+                sink(&downmix(data, channels, |s| s as i32 - 32768), rate)
+            },
+            err_fn,
+            None,
+        ),
+        SampleFormat::F32 => device.build_input_stream(
+            &config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                sink(&downmix(data, channels, |s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i32), rate)
+            },
+            err_fn,
+            None,
+        ),
+        other => return Err(format!("Unsupported sample format {:?} for {}", other, name)),
+    }
+    .map_err(|e| format!("Failed to build {}: {}", name, e))?;
+    stream
+        .play()
+        .map_err(|e| format!("Failed to start {}: {}", name, e))?;
+    Ok((stream, rate))
+}
+
+// Averages each interleaved frame into one sample.
+fn downmix<T: Copy>(data: &[T], channels: usize, to_i16: impl Fn(T) -> i32) -> Vec<i16> {
+    data.chunks_exact(channels.max(1))
+        .map(|frame| {
+            let sum: i32 = frame.iter().map(|s| to_i16(*s)).sum();
+            (sum / frame.len() as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+        })
+        .collect()
+}
+
+fn collect_clips(positionals: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut out = Vec::new();
+    for p in positionals.iter().map(PathBuf::from) {
+        if p.is_dir() {
+            let mut wavs: Vec<PathBuf> = fs::read_dir(&p)
+                .map_err(|e| format!("{}: {}", p.display(), e))?
+                .flatten()
+                .map(|e| e.path())
+                .filter(|f| f.extension().is_some_and(|e| e.eq_ignore_ascii_case("wav")))
+                .collect();
+            wavs.sort();
+            out.extend(wavs);
+        } else {
+            out.push(p);
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,398 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Value, json};
+
+use crate::wake_vocab::{self, OovWord};
+use crate::{arg_value, resolve_model_dir, wake_words_from_args};
+
+// Kaldi's default when mfcc.conf doesn't set --sample-frequency.
+const KALDI_DEFAULT_RATE: u32 = 16000;
+const IVECTOR_FILES: &[&str] = &[
+    "final.dubm",
+    "final.ie",
+    "final.mat",
+    "global_cmvn.stats",
+    "online_cmvn.conf",
+    "splice.conf",
+];
+const RNNLM_FILES: &[&str] = &[
+    "final.raw",
+    "feat_embedding.final.mat",
+    "special_symbol_opts.conf",
+    "word_feats.txt",
+];
+
+/// What a model directory contains, as far as Vosk is concerned.
+pub struct ModelReport {
+    pub dir: PathBuf,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    /// `None` when mfcc.conf is missing or unreadable.
+    pub sample_rate: Option<u32>,
+    /// HCLr.fst + Gr.fst: recognizers can be given a grammar at runtime.
+    pub runtime_grammar: bool,
+    pub ivectors: bool,
+    /// "carpa", "rnnlm" or `None`.
+    pub rescoring: Option<&'static str>,
+    /// Lowercased entries of words.txt, without `<eps>`-style and `#0` symbols.
+    pub vocabulary: Option<HashSet<String>>,
+}
+
+/// Checks the files Vosk loads from `dir`, in either the current
+/// (`am/`, `conf/`, `graph/`) or the old flat layout.
+pub fn inspect(dir: &Path) -> ModelReport {
+    let mut report = ModelReport {
+        dir: dir.to_path_buf(),
+        errors: Vec::new(),
+        warnings: Vec::new(),
+        sample_rate: None,
+        runtime_grammar: false,
+        ivectors: false,
+        rescoring: None,
+        vocabulary: None,
+    };
+    if !dir.is_dir() {
+        report.errors.push(format!("{} is not a directory", dir.display()));
+        return report;
+    }
+    let flat = !dir.join("am").is_dir() && dir.join("final.mdl").is_file();
+    let (am, conf, graph) = if flat {
+        (dir.to_path_buf(), dir.to_path_buf(), dir.to_path_buf())
+    } else {
+        (dir.join("am"), dir.join("conf"), dir.join("graph"))
+    };
+
+    report.require(&am.join("final.mdl"));
+    // Decoder settings; only the old flat layout goes without them.
+    if !flat {
+        report.require(&conf.join("model.conf"));
+    }
+
+    let mfcc = conf.join("mfcc.conf");
+    if report.require(&mfcc) {
+        match fs::read_to_string(&mfcc) {
+            Ok(text) => match sample_frequency(&text) {
+                Some(Ok(rate)) => report.sample_rate = Some(rate),
+                Some(Err(value)) => report
+                    .errors
+                    .push(format!("{}: invalid --sample-frequency '{}'", mfcc.display(), value)),
+                None => report.sample_rate = Some(KALDI_DEFAULT_RATE),
+            },
+            Err(e) => report.errors.push(format!("Failed to read {}: {}", mfcc.display(), e)),
+        }
+    }
+
+    let hclg = graph.join("HCLG.fst");
+    let (hclr, gr) = (graph.join("HCLr.fst"), graph.join("Gr.fst"));
+    match (hclg.exists(), hclr.exists(), gr.exists()) {
+        (_, true, true) => {
+            report.runtime_grammar = report.require(&hclr) & report.require(&gr);
+        }
+        (true, false, false) => {
+            report.require(&hclg);
+        }
+        (false, false, false) => report.errors.push(format!(
+            "No decoding graph: expected {} or {} with {}",
+            hclg.display(),
+            hclr.display(),
+            gr.display()
+        )),
+        (_, has_hclr, _) => {
+            let (present, missing) = if has_hclr { (&hclr, &gr) } else { (&gr, &hclr) };
+            report.errors.push(format!(
+                "{} is present but {} is missing",
+                present.display(),
+                missing.display()
+            ));
+        }
+    }
+
+    let words = graph.join("words.txt");
+    if words.is_file() {
+        match read_vocabulary(&words) {
+            Ok(vocabulary) if vocabulary.is_empty() => {
+                report.errors.push(format!("{} lists no words", words.display()))
+            }
+            Ok(vocabulary) => report.vocabulary = Some(vocabulary),
+            Err(msg) => report.errors.push(msg),
+        }
+    } else {
+        report.warnings.push(format!(
+            "{} is missing; the vocabulary can't be checked",
+            words.display()
+        ));
+    }
+    if !flat && !graph.join("phones").join("word_boundary.int").is_file() {
+        report.warnings.push(format!(
+            "{} is missing; results will have no word timings or confidences",
+            graph.join("phones").join("word_boundary.int").display()
+        ));
+    }
+
+    // Vosk only turns i-vectors on when final.ie exists, then needs the whole set.
+    let ivector = dir.join("ivector");
+    if ivector.join("final.ie").exists() {
+        report.ivectors = IVECTOR_FILES
+            .iter()
+            .filter(|f| !report.require(&ivector.join(f)))
+            .count()
+            == 0;
+    } else if ivector.is_dir() {
+        report.warnings.push(format!(
+            "{} has no final.ie; i-vectors are disabled",
+            ivector.display()
+        ));
+    }
+
+    let rescore = dir.join("rescore");
+    let (carpa, g_fst) = (rescore.join("G.carpa"), rescore.join("G.fst"));
+    if (carpa.exists() || g_fst.exists()) && report.require(&carpa) & report.require(&g_fst) {
+        report.rescoring = Some("carpa");
+    }
+    let rnnlm = dir.join("rnnlm");
+    if rnnlm.join("final.raw").exists() {
+        let complete = RNNLM_FILES
+            .iter()
+            .filter(|f| !report.require(&rnnlm.join(f)))
+            .count()
+            == 0;
+        if !g_fst.exists() {
+            report
+                .errors
+                .push(format!("{} needs {}", rnnlm.display(), g_fst.display()));
+        } else if complete {
+            report.rescoring = Some("rnnlm");
+        }
+    }
+    report
+}
+
+impl ModelReport {
+    // Records an error unless `path` is a non-empty file; truncated downloads leave empty ones.
+    fn require(&mut self, path: &Path) -> bool {
+        match fs::metadata(path) {
+            Ok(m) if m.is_file() && m.len() > 0 => true,
+            Ok(m) if m.is_file() => {
+                self.errors.push(format!("{} is empty", path.display()));
+                false
+            }
+            _ => {
+                self.errors.push(format!("{} is missing", path.display()));
+                false
+            }
+        }
+    }
+}
+
+fn sample_frequency(conf: &str) -> Option<Result<u32, String>> {
+    conf.lines()
+        .rev()
+        .find_map(|l| l.trim().strip_prefix("--sample-frequency="))
+        .map(|v| {
+            let v = v.split('#').next().unwrap_or("").trim();
+            v.parse::<f64>()
+                .ok()
+                .filter(|r| *r > 0.0)
+                .map(|r| r as u32)
+                .ok_or_else(|| v.to_string())
+        })
+}
+
+/// Reads a Kaldi symbol table (`word id` per line).
+pub fn read_vocabulary(path: &Path) -> Result<HashSet<String>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(text
+        .lines()
+        .filter_map(|l| l.split_whitespace().next())
+        .filter(|w| !(w.starts_with('#') || w.starts_with('<') && w.ends_with('>')))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+/// `check-model [model dir] [--format text|json]`
+///
+/// Validates the model layout and checks the wake phrases (`--wake`, `--language`)
+/// against its vocabulary. Fails when anything Vosk needs is missing or a wake word
+/// is out of vocabulary.
+pub fn run(positionals: &[String], args: &[(String, String)]) -> Result<(), String> {
+    let dir = match positionals.first() {
+        Some(dir) => PathBuf::from(dir),
+        None => resolve_model_dir(args)?,
+    };
+    let format = arg_value(args, "--format").unwrap_or("text");
+    if format != "text" && format != "json" {
+        return Err(format!("Unknown --format '{}' (expected text or json)", format));
+    }
+    let language = arg_value(args, "--language").unwrap_or("en").to_lowercase();
+    let mut report = inspect(&dir);
+
+    let phrases = wake_words_from_args(args, &language);
+    let oov = report
+        .vocabulary
+        .as_ref()
+        .map(|v| wake_vocab::check(&phrases, v))
+        .unwrap_or_default();
+    for word in &oov {
+        report.errors.push(word.describe(&language));
+    }
+
+    if format == "json" {
+        let value = json!({
+            "model": report.dir.to_string_lossy(),
+            "ok": report.errors.is_empty(),
+            "sample_rate": report.sample_rate,
+            "runtime_grammar": report.runtime_grammar,
+            "ivectors": report.ivectors,
+            "rescoring": report.rescoring,
+            "vocabulary_size": report.vocabulary.as_ref().map(HashSet::len),
+            "wake": phrases
+                .iter()
+                .map(|phrase| json!({
+                    "phrase": phrase,
+                    "missing": oov
+                        .iter()
+                        .filter(|o| o.phrase == *phrase)
+                        .map(|o| json!({"word": o.word, "suggestions": o.suggestions}))
+                        .collect::<Vec<Value>>(),
+                }))
+                .collect::<Vec<Value>>(),
+            "errors": report.errors,
+            "warnings": report.warnings,
+        });
+        println!("{}", serde_json::to_string_pretty(&value).unwrap_or_default());
+    } else {
+        print_text(&report, &phrases, &oov);
+    }
+
+    if report.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Model {} failed {} check(s)", dir.display(), report.errors.len()))
+    }
+}
+
+fn print_text(report: &ModelReport, phrases: &[String], oov: &[OovWord]) {
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    println!("Model: {}", report.dir.display());
+    match report.sample_rate {
+        Some(rate) => println!("  Sample rate:      {} Hz", rate),
+        None => println!("  Sample rate:      unknown"),
+    }
+    println!("  Runtime grammars: {}", yes_no(report.runtime_grammar));
+    println!("  I-vectors:        {}", yes_no(report.ivectors));
+    println!("  Rescoring:        {}", report.rescoring.unwrap_or("none"));
+    match &report.vocabulary {
+        Some(v) => println!("  Vocabulary:       {} words", v.len()),
+        None => println!("  Vocabulary:       unknown"),
+    }
+    for phrase in phrases {
+        let missing: Vec<&str> = oov
+            .iter()
+            .filter(|o| o.phrase == *phrase)
+            .map(|o| o.word.as_str())
+            .collect();
+        if report.vocabulary.is_none() {
+            println!("  Wake '{}':  not checked", phrase);
+        } else if missing.is_empty() {
+            println!("  Wake '{}':  ok", phrase);
+        } else {
+            println!("  Wake '{}':  missing {}", phrase, missing.join(", "));
+        }
+    }
+    for w in &report.warnings {
+        println!("Warning: {}", w);
+    }
+    for e in &report.errors {
+        eprintln!("Error: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::scratch;
+    use crate::test_args::args;
+
+    fn write(dir: &Path, file: &str, contents: &str) {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    // Everything a current small model ships, at 8 kHz.
+    fn model(name: &str) -> PathBuf {
+        let dir = scratch("check-model", name);
+        write(&dir, "am/final.mdl", "mdl");
+        write(&dir, "conf/mfcc.conf", "--use-energy=false\n--sample-frequency=8000 # telephone\n");
+        write(&dir, "conf/model.conf", "--beam=10.0\n");
+        write(&dir, "graph/HCLG.fst", "fst");
+        write(&dir, "graph/words.txt", "<eps> 0\nHey 1\niris 2\nhello 3\n#0 4\n");
+        write(&dir, "graph/phones/word_boundary.int", "1 nonword\n");
+        dir
+    }
+
+    fn errors(report: &ModelReport) -> String {
+        report.errors.join("; ")
+    }
+
+    #[test]
+    fn accepts_a_complete_layout() {
+        let report = inspect(&model("complete"));
+        assert!(report.errors.is_empty() && report.warnings.is_empty(), "{} {:?}", errors(&report), report.warnings);
+        assert_eq!(report.sample_rate, Some(8000));
+        assert!(!report.runtime_grammar && !report.ivectors);
+        assert_eq!(report.rescoring, None);
+        let mut words: Vec<String> = report.vocabulary.unwrap().into_iter().collect();
+        words.sort();
+        assert_eq!(words, ["hello", "hey", "iris"]);
+    }
+
+    #[test]
+    fn reports_missing_model_files() {
+        for file in ["am/final.mdl", "conf/model.conf", "conf/mfcc.conf"] {
+            let dir = model("missing");
+            fs::remove_file(dir.join(file)).unwrap();
+            let report = inspect(&dir);
+            assert_eq!(report.errors.len(), 1, "{}", errors(&report));
+            assert!(errors(&report).ends_with(&format!("{} is missing", Path::new(file).display())), "{}", errors(&report));
+        }
+        let dir = model("empty");
+        write(&dir, "am/final.mdl", "");
+        assert!(errors(&inspect(&dir)).ends_with("final.mdl is empty"));
+        assert!(errors(&inspect(&dir.join("nowhere"))).ends_with("is not a directory"));
+    }
+
+    #[test]
+    fn needs_both_rescoring_files() {
+        let dir = model("rescore");
+        write(&dir, "rescore/G.carpa", "carpa");
+        let report = inspect(&dir);
+        assert!(errors(&report).ends_with("G.fst is missing"), "{}", errors(&report));
+        assert_eq!(report.rescoring, None);
+        write(&dir, "rescore/G.fst", "fst");
+        let report = inspect(&dir);
+        assert!(report.errors.is_empty(), "{}", errors(&report));
+        assert_eq!(report.rescoring, Some("carpa"));
+    }
+
+    #[test]
+    fn needs_both_runtime_grammar_graphs() {
+        let dir = model("grammar");
+        fs::remove_file(dir.join("graph/HCLG.fst")).unwrap();
+        write(&dir, "graph/HCLr.fst", "fst");
+        assert!(errors(&inspect(&dir)).contains("HCLr.fst is present but"));
+        write(&dir, "graph/Gr.fst", "fst");
+        assert!(inspect(&dir).runtime_grammar);
+    }
+
+    #[test]
+    fn fails_on_an_out_of_vocabulary_wake_phrase() {
+        let dir = model("oov").to_string_lossy().into_owned();
+        assert!(run(std::slice::from_ref(&dir), &args(&[("--wake", "hey iris")])).is_ok());
+        let err = run(std::slice::from_ref(&dir), &args(&[("--wake", "hey iris, hello irys")])).unwrap_err();
+        assert!(err.ends_with("failed 1 check(s)"), "{}", err);
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use serde_json::Value;
+
+/// Reads a JSON config file whose keys mirror the command line flags, e.g.
+/// `{"device": "USB Mic", "wake": ["hey iris", "ok iris"], "ws-port": 8765}`.
+/// Arrays become comma-separated values.
+pub fn load_config_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+    let v: Value = serde_json::from_str(&raw)
+        .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+    let obj = v
+        .as_object()
+        .ok_or_else(|| format!("Config {} must be a JSON object", path.display()))?;
+
+    let mut pairs = Vec::with_capacity(obj.len());
+    for (key, value) in obj {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Array(items) => items
+                .iter()
+                .map(|i| match i {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            Value::Null => continue,
+            Value::Object(_) => {
+                return Err(format!("Config key '{}' must not be an object", key));
+            }
+        };
+        pairs.push((format!("--{}", key.trim_start_matches("--")), value));
+    }
+    Ok(pairs)
+}
+
+/// Command line flags take precedence over the config file.
+pub fn merge_args(file: Vec<(String, String)>, cli: &[(String, String)]) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = cli.to_vec();
+    for (key, value) in file {
+        if !cli.iter().any(|(k, _)| *k == key) {
+            merged.push((key, value));
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_args::args;
+
+    fn load(name: &str, body: &str) -> Result<Vec<(String, String)>, String> {
+        let path = std::env::temp_dir().join(format!("irisva-config-{}-{}.json", std::process::id(), name));
+        std::fs::write(&path, body).unwrap();
+        let pairs = load_config_file(&path);
+        let _ = std::fs::remove_file(&path);
+        pairs
+    }
+
+    #[test]
+    fn keys_become_flags() {
+        let body = r#"{"device": "USB Mic", "--ws-port": 8765, "dsp": true, "wake": ["hey iris", "ok iris"], "mqtt-url": null}"#;
+        let mut loaded = load("flags", body).unwrap();
+        loaded.sort();
+        assert_eq!(
+            loaded,
+            args(&[("--device", "USB Mic"), ("--dsp", "true"), ("--wake", "hey iris,ok iris"), ("--ws-port", "8765")])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        for body in ["", "[1, 2]", "{\"device\": ", r#"{"rules": {"path": "x"}}"#] {
+            assert!(load("invalid", body).is_err(), "{}", body);
+        }
+        assert!(load_config_file(Path::new("/nonexistent/irisva.json")).is_err());
+    }
+
+    #[test]
+    fn command_line_wins_over_the_file() {
+        let file = args(&[("--device", "USB Mic"), ("--ws-port", "8765")]);
+        let cli = args(&[("--device", "Built-in"), ("--wake", "hey iris")]);
+        assert_eq!(
+            merge_args(file, &cli),
+            args(&[("--device", "Built-in"), ("--wake", "hey iris"), ("--ws-port", "8765")])
+        );
+    }
+}
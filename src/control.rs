@@ -0,0 +1,211 @@
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+
+use serde_json::{Value, json};
+
+// How long a client waits for the main loop to answer a status request.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Requests from external clients, handled by the main loop.
+#[derive(Debug, Clone)]
+pub enum Control {
+    Pause,
+    Resume,
+    /// Start listening for a command as if the wake phrase had been heard.
+    ListenNow,
+    /// New wake phrases for one language; `None` means the primary model.
+    SetWake(Vec<String>, Option<String>),
+    SwitchDevice(String),
+    ReloadConfig,
+    /// Load the model(s) again, optionally for one language or from another directory.
+    ReloadModel { language: Option<String>, path: Option<String> },
+    /// The main loop answers with a status object on the given channel.
+    Status(Sender<Value>),
+    /// Stop the stream, flush outputs and exit; sent on SIGINT/SIGTERM or a `shutdown` request.
+    Shutdown,
+}
+
+impl Control {
+    /// Parses `{"type": "pause"}`-style messages; `"cmd"` or `"command"` work in place of `"type"`.
+    pub fn from_json(v: &Value) -> Result<Self, String> {
+        let name = message_type(v).ok_or("Control message needs a \"type\" field")?;
+        match name.as_str() {
+            "pause" => Ok(Control::Pause),
+            "resume" => Ok(Control::Resume),
+            "listen-now" | "force-listen" => Ok(Control::ListenNow),
+            "set-wake" => {
+                let phrases = parse_phrases(v.get("phrases").or_else(|| v.get("wake")))
+                    .ok_or("set-wake needs \"phrases\": [\"hey iris\", ...]")?;
+                let language = v.get("language").and_then(|l| l.as_str()).map(|l| l.trim().to_lowercase());
+                Ok(Control::SetWake(phrases, language))
+            }
+            "switch-device" | "set-device" => {
+                let device = v
+                    .get("device")
+                    .and_then(|d| d.as_str())
+                    .ok_or("switch-device needs \"device\": \"<name>\"")?;
+                Ok(Control::SwitchDevice(device.to_string()))
+            }
+            "reload-config" => Ok(Control::ReloadConfig),
+            "shutdown" => Ok(Control::Shutdown),
+            "reload-model" => Ok(Control::ReloadModel {
+                language: v.get("language").and_then(|l| l.as_str()).map(|l| l.trim().to_lowercase()),
+                path: v.get("path").and_then(|p| p.as_str()).map(str::to_string),
+            }),
+            "status" => Err("status must be sent through control::dispatch".to_string()),
+            other => Err(format!("Unknown control command '{}'", other)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Control::Pause => "pause",
+            Control::Resume => "resume",
+            Control::ListenNow => "listen-now",
+            Control::SetWake(..) => "set-wake",
+            Control::SwitchDevice(_) => "switch-device",
+            Control::ReloadConfig => "reload-config",
+            Control::ReloadModel { .. } => "reload-model",
+            Control::Status(_) => "status",
+            Control::Shutdown => "shutdown",
+        }
+    }
+}
+
+/// The message type, normalized to kebab-case.
+pub fn message_type(v: &Value) -> Option<String> {
+    ["type", "cmd", "command"]
+        .iter()
+        .find_map(|k| v.get(*k).and_then(|x| x.as_str()))
+        .map(|s| s.trim().to_lowercase().replace('_', "-"))
+}
+
+/// Hands a client message to the main loop and builds the reply sent back to the client.
+pub fn dispatch(v: &Value, control: &Sender<Control>) -> Value {
+    if message_type(v).as_deref() == Some("status") {
+        let (tx, rx) = mpsc::channel();
+        if control.send(Control::Status(tx)).is_err() {
+            return json!({ "type": "error", "message": "IrisVA is shutting down" });
+        }
+        return match rx.recv_timeout(STATUS_TIMEOUT) {
+            Ok(status) => status,
+            Err(_) => json!({ "type": "error", "message": "Timed out waiting for status" }),
+        };
+    }
+    match Control::from_json(v) {
+        Ok(cmd) => {
+            let name = cmd.name();
+            if control.send(cmd).is_err() {
+                return json!({ "type": "error", "message": "IrisVA is shutting down" });
+            }
+            json!({ "type": "ack", "command": name })
+        }
+        Err(message) => json!({ "type": "error", "message": message }),
+    }
+}
+
+/// Accepts either a JSON array of phrases or a comma-separated string.
+pub fn parse_phrases(v: Option<&Value>) -> Option<Vec<String>> {
+    let raw: Vec<String> = match v? {
+        Value::Array(items) => items
+            .iter()
+            .filter_map(|x| x.as_str().map(str::to_string))
+            .collect(),
+        Value::String(s) => s.split(',').map(str::to_string).collect(),
+        _ => return None,
+    };
+    let phrases: Vec<String> = raw
+        .iter()
+        .map(|p| p.trim().to_lowercase())
+        .filter(|p| !p.is_empty())
+        .collect();
+    (!phrases.is_empty()).then_some(phrases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(v: Value) -> Result<Control, String> {
+        Control::from_json(&v)
+    }
+
+    #[test]
+    fn reads_the_type_from_any_of_its_keys() {
+        assert_eq!(message_type(&json!({"type": "Listen_Now"})).as_deref(), Some("listen-now"));
+        assert_eq!(message_type(&json!({"cmd": " pause "})).as_deref(), Some("pause"));
+        assert_eq!(message_type(&json!({"command": "reload_model"})).as_deref(), Some("reload-model"));
+        assert_eq!(message_type(&json!({"type": 3})), None);
+        assert_eq!(message_type(&json!(["pause"])), None);
+    }
+
+    #[test]
+    fn parses_commands_and_their_fields() {
+        assert!(matches!(parse(json!({"type": "force_listen"})), Ok(Control::ListenNow)));
+        assert!(matches!(parse(json!({"type": "resume"})), Ok(Control::Resume)));
+        assert!(matches!(parse(json!({"cmd": "shutdown"})), Ok(Control::Shutdown)));
+        match parse(json!({"type": "set-wake", "phrases": ["Hey Iris", " "], "language": " DE "})) {
+            Ok(Control::SetWake(phrases, language)) => {
+                assert_eq!(phrases, ["hey iris"]);
+                assert_eq!(language.as_deref(), Some("de"));
+            }
+            other => panic!("{:?}", other),
+        }
+        match parse(json!({"type": "set-device", "device": "USB Mic"})) {
+            Ok(Control::SwitchDevice(device)) => assert_eq!(device, "USB Mic"),
+            other => panic!("{:?}", other),
+        }
+        match parse(json!({"type": "reload-model", "path": "/models/en"})) {
+            Ok(Control::ReloadModel { language, path }) => {
+                assert_eq!(language, None);
+                assert_eq!(path.as_deref(), Some("/models/en"));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_commands_and_missing_fields() {
+        let error = |v: Value| parse(v).unwrap_err();
+        assert!(error(json!({"phrases": ["hey iris"]})).contains("\"type\""));
+        assert_eq!(error(json!({"type": "launch"})), "Unknown control command 'launch'");
+        assert!(error(json!({"type": "set-wake"})).starts_with("set-wake needs"));
+        assert!(error(json!({"type": "set-wake", "phrases": [" ", ""]})).starts_with("set-wake needs"));
+        assert!(error(json!({"type": "switch-device", "device": 1})).starts_with("switch-device needs"));
+    }
+
+    #[test]
+    fn phrases_come_as_a_list_or_comma_separated() {
+        assert_eq!(parse_phrases(Some(&json!("Hey Iris, ok iris,"))).unwrap(), ["hey iris", "ok iris"]);
+        assert_eq!(parse_phrases(Some(&json!(["Computer", 3]))).unwrap(), ["computer"]);
+        assert_eq!(parse_phrases(Some(&json!(""))), None);
+        assert_eq!(parse_phrases(Some(&json!(3))), None);
+        assert_eq!(parse_phrases(None), None);
+    }
+
+    #[test]
+    fn replies_with_an_ack_or_an_error() {
+        let (tx, rx) = mpsc::channel();
+        assert_eq!(dispatch(&json!({"type": "pause"}), &tx), json!({"type": "ack", "command": "pause"}));
+        assert!(matches!(rx.try_recv(), Ok(Control::Pause)));
+        assert_eq!(
+            dispatch(&json!({"type": "launch"}), &tx),
+            json!({"type": "error", "message": "Unknown control command 'launch'"})
+        );
+        assert!(rx.try_recv().is_err());
+        drop(rx);
+        assert_eq!(dispatch(&json!({"type": "pause"}), &tx)["message"], "IrisVA is shutting down");
+    }
+
+    #[test]
+    fn status_is_answered_by_the_main_loop() {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            if let Ok(Control::Status(reply)) = rx.recv() {
+                let _ = reply.send(json!({"type": "status", "state": "idle"}));
+            }
+        });
+        assert_eq!(dispatch(&json!({"cmd": "status"}), &tx), json!({"type": "status", "state": "idle"}));
+        assert!(Control::from_json(&json!({"type": "status"})).is_err());
+    }
+}
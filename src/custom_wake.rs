@@ -0,0 +1,547 @@
+//! Wake phrases the ASR model doesn't know ("Iridia"): a few recorded samples are kept as
+//! MFCC templates and live audio is matched against them with dynamic time warping.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+
+use crate::mfcc::{COEFFS, Frame, Mfcc};
+use crate::{arg_value, capture, models};
+
+type Features = [f32; COEFFS];
+
+// Frames quieter than this are never treated as speech.
+const SPEECH_DB: f32 = -55.0;
+// Enrollment keeps frames within this many dB of the loudest one.
+const TRIM_DB: f32 = 20.0;
+const MIN_TEMPLATE_FRAMES: usize = 20;
+const MIN_SAMPLES: usize = 3;
+// Live audio is checked every few 10 ms frames; a match may end anywhere in between.
+const CHECK_EVERY: usize = 3;
+// Frames ignored after a detection so one utterance fires once.
+const REFRACTORY_FRAMES: usize = 100;
+const THRESHOLD_MARGIN: f32 = 2.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// A match wakes IrisVA by itself, alongside the ASR wake phrases.
+    Standalone,
+    /// ASR wake phrases only count when the detector matched shortly before.
+    Confirm,
+}
+
+pub struct CustomWakeConfig {
+    pub path: PathBuf,
+    pub threshold: Option<f32>,
+    pub mode: Mode,
+}
+
+impl CustomWakeConfig {
+    /// `--custom-wake <name|file.json>`, `--custom-wake-threshold <distance>`,
+    /// `--custom-wake-mode standalone|confirm`.
+    pub fn from_args(args: &[(String, String)]) -> Option<Result<Self, String>> {
+        let target = arg_value(args, "--custom-wake")?;
+        Some(Self::parse(target, args))
+    }
+
+    fn parse(target: &str, args: &[(String, String)]) -> Result<Self, String> {
+        let threshold = match arg_value(args, "--custom-wake-threshold") {
+            Some(v) => Some(
+                v.parse::<f32>()
+                    .ok()
+                    .filter(|t| *t > 0.0)
+                    .ok_or_else(|| format!("Invalid value for --custom-wake-threshold: '{}'", v))?,
+            ),
+            None => None,
+        };
+        let mode = match arg_value(args, "--custom-wake-mode") {
+            None | Some("standalone") => Mode::Standalone,
+            Some("confirm") => Mode::Confirm,
+            Some(other) => {
+                return Err(format!(
+                    "Unknown --custom-wake-mode '{}' (expected standalone or confirm)",
+                    other
+                ));
+            }
+        };
+        Ok(CustomWakeConfig { path: template_path(target), threshold, mode })
+    }
+}
+
+/// A file path as given, or `<data dir>/wake/<name>.json` for a bare name.
+fn template_path(target: &str) -> PathBuf {
+    if target.ends_with(".json") || target.contains(['/', '\\']) {
+        PathBuf::from(target)
+    } else {
+        models::data_dir().join("wake").join(format!("{}.json", target))
+    }
+}
+
+/// Enrolled samples of one phrase, stored as
+/// `{"name": "iridia", "threshold": 4.2, "templates": [[[c1..c12], ...], ...]}`.
+pub struct Templates {
+    pub name: String,
+    /// Largest DTW distance that still counts as a match, chosen at enrollment.
+    pub threshold: f32,
+    templates: Vec<Vec<Features>>,
+}
+
+impl Templates {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let raw = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read custom wake {}: {}", path.display(), e))?;
+        let invalid = |what: &str| format!("Invalid custom wake {}: {}", path.display(), what);
+        let v: Value = serde_json::from_str(&raw).map_err(|e| invalid(&e.to_string()))?;
+        let frame = |f: &Value| -> Option<Features> {
+            let values = f.as_array()?;
+            let mut out = [0.0; COEFFS];
+            if values.len() != COEFFS {
+                return None;
+            }
+            for (o, x) in out.iter_mut().zip(values) {
+                *o = x.as_f64()? as f32;
+            }
+            Some(out)
+        };
+        let templates: Vec<Vec<Features>> = v
+            .get("templates")
+            .and_then(|t| t.as_array())
+            .ok_or_else(|| invalid("needs a \"templates\" array"))?
+            .iter()
+            .map(|t| t.as_array().and_then(|frames| frames.iter().map(frame).collect()))
+            .collect::<Option<_>>()
+            .ok_or_else(|| invalid(&format!("templates must be arrays of {}-value frames", COEFFS)))?;
+        if templates.is_empty() {
+            return Err(invalid("no templates"));
+        }
+        if templates.iter().any(|t| t.len() < MIN_TEMPLATE_FRAMES) {
+            return Err(invalid(&format!("templates need at least {} frames", MIN_TEMPLATE_FRAMES)));
+        }
+        Ok(Templates {
+            name: v.get("name").and_then(|n| n.as_str()).unwrap_or("custom").to_string(),
+            threshold: v
+                .get("threshold")
+                .and_then(|t| t.as_f64())
+                .ok_or_else(|| invalid("needs a \"threshold\""))? as f32,
+            templates,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let body = json!({"name": self.name, "threshold": self.threshold, "templates": self.templates});
+        fs::write(path, serde_json::to_string(&body).unwrap_or_default())
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// A loaded detector and how its matches are used.
+pub struct CustomWake {
+    pub mode: Mode,
+    /// The detector's name, readable without locking it.
+    pub name: String,
+    pub detector: Mutex<Detector>,
+    /// Audio for the detector's own thread, so MFCC and DTW stay out of the audio callback.
+    pub feed: SyncSender<Arc<[i16]>>,
+    /// Set while blocks are being dropped because the detector's queue is full.
+    pub lagging: AtomicBool,
+}
+
+/// Streaming matcher fed the same mono audio as the recognizers.
+pub struct Detector {
+    templates: Vec<Vec<Features>>,
+    threshold: f32,
+    mfcc: Mfcc,
+    /// Recent frames, un-normalized, with their levels.
+    history: VecDeque<(Features, f32)>,
+    longest: usize,
+    since_check: usize,
+    /// Best distance so far of a match that may still improve.
+    candidate: Option<f32>,
+    quiet_frames: usize,
+    last_hit: Option<Instant>,
+}
+
+impl Detector {
+    pub fn new(templates: Templates, threshold: Option<f32>, sample_rate: u32) -> Self {
+        let longest = templates.templates.iter().map(Vec::len).max().unwrap_or(0);
+        Detector {
+            threshold: threshold.unwrap_or(templates.threshold),
+            templates: templates.templates.into_iter().map(normalized).collect(),
+            mfcc: Mfcc::new(sample_rate),
+            history: VecDeque::with_capacity(2 * longest + 1),
+            longest,
+            since_check: 0,
+            candidate: None,
+            quiet_frames: 0,
+            last_hit: None,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mfcc = Mfcc::new(sample_rate);
+        self.history.clear();
+        self.candidate = None;
+    }
+
+    /// Feeds audio; returns the match distance when the phrase was just heard.
+    pub fn push(&mut self, pcm: &[i16]) -> Option<f32> {
+        let mut hit = None;
+        for Frame { coeffs, db } in self.mfcc.push(pcm) {
+            if self.history.len() >= 2 * self.longest {
+                self.history.pop_front();
+            }
+            self.history.push_back((coeffs, db));
+            if self.quiet_frames > 0 {
+                self.quiet_frames -= 1;
+                continue;
+            }
+            self.since_check += 1;
+            if self.since_check < CHECK_EVERY {
+                continue;
+            }
+            self.since_check = 0;
+            // Below the threshold, keep going while the match still improves so it fires
+            // at the end of the phrase rather than partway through.
+            let distance = self.check();
+            let fire = match (self.candidate, distance) {
+                (Some(best), Some(d)) if d < best => {
+                    self.candidate = Some(d);
+                    None
+                }
+                (None, Some(d)) => {
+                    self.candidate = Some(d);
+                    None
+                }
+                (best, _) => best,
+            };
+            if let Some(distance) = fire {
+                hit = Some(distance);
+                self.candidate = None;
+                self.last_hit = Some(Instant::now());
+                self.quiet_frames = REFRACTORY_FRAMES;
+                self.history.clear();
+            }
+        }
+        hit
+    }
+
+    fn check(&self) -> Option<f32> {
+        // Only bother when something loud enough ended recently.
+        let recent = self.history.iter().rev().take(self.longest);
+        if !recent.clone().any(|(_, db)| *db > SPEECH_DB) {
+            return None;
+        }
+        // Normalized by the mean of the loud frames, like the trimmed templates.
+        let loudest = self.history.iter().map(|(_, db)| *db).fold(f32::MIN, f32::max);
+        let speech: Vec<&Features> = self
+            .history
+            .iter()
+            .filter(|(_, db)| *db >= loudest - TRIM_DB)
+            .map(|(c, _)| c)
+            .collect();
+        let n = speech.len() as f32;
+        let mean: Features = std::array::from_fn(|k| speech.iter().map(|c| c[k]).sum::<f32>() / n);
+        let window: Vec<Features> = self
+            .history
+            .iter()
+            .map(|(c, _)| std::array::from_fn(|k| c[k] - mean[k]))
+            .collect();
+        self.templates
+            .iter()
+            .map(|t| dtw(t, &window, true, CHECK_EVERY))
+            .min_by(f32::total_cmp)
+            .filter(|d| *d <= self.threshold)
+    }
+
+    /// Whether the phrase was heard within `window`; consumes the match.
+    pub fn take_recent_hit(&mut self, window: Duration) -> bool {
+        self.last_hit.take().is_some_and(|t| t.elapsed() <= window)
+    }
+}
+
+// Subtracts the template's own mean, as live audio gets the mean of its loud frames.
+fn normalized(frames: Vec<Features>) -> Vec<Features> {
+    let n = frames.len().max(1) as f32;
+    let mean: Features = std::array::from_fn(|k| frames.iter().map(|f| f[k]).sum::<f32>() / n);
+    frames
+        .into_iter()
+        .map(|f| std::array::from_fn(|k| f[k] - mean[k]))
+        .collect()
+}
+
+fn distance(a: &Features, b: &Features) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+}
+
+/// Mean frame distance along the best alignment of `template` with `audio`. Steps are
+/// limited to slopes between 1/2 and 2 so speaking rate can vary but the template can't
+/// collapse onto a few frames. With `free_start` the match may begin anywhere in `audio`;
+/// it must end in its last `end_slack` frames.
+fn dtw(template: &[Features], audio: &[Features], free_start: bool, end_slack: usize) -> f32 {
+    let (n, m) = (template.len(), audio.len());
+    if n == 0 || m == 0 {
+        return f32::INFINITY;
+    }
+    // (accumulated cost, path length) per cell.
+    let mut cells = vec![(f32::INFINITY, 0u32); n * m];
+    let at = |i: usize, j: usize| i * m + j;
+    for j in 0..m {
+        for i in 0..n {
+            let d = distance(&template[i], &audio[j]);
+            if i == 0 && (free_start || j == 0) {
+                cells[at(0, j)] = (d, 1);
+                continue;
+            }
+            let mut best = (f32::INFINITY, 0);
+            let mut consider = |c: (f32, u32)| {
+                if c.0 < best.0 {
+                    best = c;
+                }
+            };
+            if i >= 1 && j >= 1 {
+                consider(cells[at(i - 1, j - 1)]);
+            }
+            if i >= 2 && j >= 1 {
+                consider(cells[at(i - 2, j - 1)]);
+            }
+            if i >= 1 && j >= 2 {
+                consider(cells[at(i - 1, j - 2)]);
+            }
+            if best.0.is_finite() {
+                cells[at(i, j)] = (best.0 + d, best.1 + 1);
+            }
+        }
+    }
+    (m.saturating_sub(end_slack.max(1))..m)
+        .map(|j| cells[at(n - 1, j)])
+        .filter(|(cost, _)| cost.is_finite())
+        .map(|(cost, len)| cost / len as f32)
+        .min_by(f32::total_cmp)
+        .unwrap_or(f32::INFINITY)
+}
+
+// The speech part of a clip: from the first to the last frame near the loudest one.
+fn trim(frames: Vec<Frame>) -> Vec<Features> {
+    let loudest = frames.iter().map(|f| f.db).fold(f32::MIN, f32::max);
+    let floor = (loudest - TRIM_DB).max(SPEECH_DB);
+    let first = frames.iter().position(|f| f.db >= floor);
+    let last = frames.iter().rposition(|f| f.db >= floor);
+    match (first, last) {
+        (Some(first), Some(last)) => frames[first..=last].iter().map(|f| f.coeffs).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `wake-enroll <name> [clip.wav|dir]... [--enroll-clips N] [--enroll-secs S] [--out file]`
+///
+/// Stores MFCC templates of the phrase for `--custom-wake <name>`; without clips, records
+/// `--enroll-clips` (default 4) samples of `--enroll-secs` (default 3) from the input device.
+pub fn enroll(positionals: &[String], args: &[(String, String)]) -> Result<(), String> {
+    const USAGE: &str = "Usage: wake-enroll <name> [clip.wav|dir]... [--enroll-clips N] [--enroll-secs S] [--out file]";
+    let name = positionals.first().ok_or(USAGE)?;
+    let out = arg_value(args, "--out")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| template_path(name));
+    let prompt = format!("say \"{}\" once", name);
+    let clips = capture::enrollment_clips(&positionals[1..], args, 4, 3, &prompt)?;
+
+    let mut templates = Vec::new();
+    let mut labels = Vec::new();
+    for (label, audio) in &clips {
+        let frames = trim(Mfcc::frames(audio.sample_rate, &audio.samples));
+        if frames.len() < MIN_TEMPLATE_FRAMES {
+            println!("{}: no clear speech, skipped", label);
+            continue;
+        }
+        println!("{}: {} ms of speech", label, frames.len() * 10);
+        templates.push(frames);
+        labels.push(label);
+    }
+    if templates.len() < MIN_SAMPLES {
+        return Err(format!(
+            "Only {} usable sample(s) of '{}'; at least {} are needed",
+            templates.len(),
+            name,
+            MIN_SAMPLES
+        ));
+    }
+
+    // How far apart the samples are from each other sets how far live audio may be.
+    let normalized_templates: Vec<Vec<Features>> = templates.iter().cloned().map(normalized).collect();
+    let mut pairwise = Vec::new();
+    let mut per_sample = vec![0.0f32; templates.len()];
+    for i in 0..templates.len() {
+        for j in i + 1..templates.len() {
+            let d = dtw(&normalized_templates[i], &normalized_templates[j], false, 1);
+            if d.is_finite() {
+                pairwise.push(d);
+                per_sample[i] += d;
+                per_sample[j] += d;
+            }
+        }
+    }
+    if pairwise.is_empty() {
+        return Err("The samples differ too much in length to compare; record them again".to_string());
+    }
+    pairwise.sort_by(f32::total_cmp);
+    let median = pairwise[pairwise.len() / 2];
+    let threshold = median * THRESHOLD_MARGIN;
+    let mut typical = per_sample.clone();
+    typical.sort_by(f32::total_cmp);
+    let typical = typical[typical.len() / 2];
+    for (i, total) in per_sample.iter().enumerate() {
+        if *total > typical * 1.5 {
+            println!("Warning: {} sounds unlike the others; consider recording it again", labels[i]);
+        }
+    }
+    println!(
+        "Sample distances: min {:.2}, median {:.2}, max {:.2}; threshold {:.2} (override with --custom-wake-threshold)",
+        pairwise[0],
+        median,
+        pairwise[pairwise.len() - 1],
+        threshold
+    );
+
+    Templates { name: name.clone(), threshold, templates }.save(&out)?;
+    println!("Saved {} template(s) for '{}' to {}", per_sample.len(), name, out.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_args::args;
+
+    const RECENT: Duration = Duration::from_secs(5);
+
+    fn frame(value: f32) -> Features {
+        [value; COEFFS]
+    }
+
+    // A made-up "phrase": tones of 200 ms each, at 16 kHz.
+    fn phrase(tones: &[f32]) -> Vec<i16> {
+        tones
+            .iter()
+            .flat_map(|hz| (0..3200).map(move |i| (12000.0 * (2.0 * std::f32::consts::PI * hz * i as f32 / 16000.0).sin()) as i16))
+            .collect()
+    }
+
+    fn detector(tones: &[f32]) -> Detector {
+        let template = trim(Mfcc::frames(16000, &phrase(tones)));
+        assert!(template.len() >= MIN_TEMPLATE_FRAMES);
+        let templates = Templates { name: "tones".to_string(), threshold: 3.0, templates: vec![template] };
+        Detector::new(templates, None, 16000)
+    }
+
+    #[test]
+    fn parses_mode_and_threshold() {
+        let config = CustomWakeConfig::from_args(&args(&[("--custom-wake", "iridia")])).unwrap().unwrap();
+        assert!(config.mode == Mode::Standalone && config.threshold.is_none());
+        assert!(config.path.ends_with("wake/iridia.json"));
+        let config = CustomWakeConfig::from_args(&args(&[
+            ("--custom-wake", "./mine.json"),
+            ("--custom-wake-mode", "confirm"),
+            ("--custom-wake-threshold", "4.5"),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert!(config.mode == Mode::Confirm && config.threshold == Some(4.5));
+        assert_eq!(config.path, PathBuf::from("./mine.json"));
+        for bad in [("--custom-wake-mode", "always"), ("--custom-wake-threshold", "-1")] {
+            assert!(CustomWakeConfig::from_args(&args(&[("--custom-wake", "x"), bad])).unwrap().is_err());
+        }
+        assert!(CustomWakeConfig::from_args(&args(&[])).is_none());
+    }
+
+    #[test]
+    fn dtw_allows_tempo_changes_within_limits() {
+        let template: Vec<Features> = (0..10).map(|i| frame(i as f32)).collect();
+        assert_eq!(dtw(&template, &template, false, 1), 0.0);
+        // Twice as slow is still an exact match; three times is out of reach.
+        let slow: Vec<Features> = template.iter().flat_map(|f| [*f, *f]).collect();
+        assert!(dtw(&template, &slow[..19], false, 1) < 1e-6);
+        let slower: Vec<Features> = template.iter().flat_map(|f| [*f, *f, *f]).collect();
+        assert_eq!(dtw(&template, &slower, false, 1), f32::INFINITY);
+        assert_eq!(dtw(&template, &[], false, 1), f32::INFINITY);
+    }
+
+    #[test]
+    fn dtw_with_free_start_finds_the_phrase_in_longer_audio() {
+        let template: Vec<Features> = (0..10).map(|i| frame(i as f32)).collect();
+        let mut audio: Vec<Features> = (0..15).map(|_| frame(-20.0)).collect();
+        audio.extend(template.iter().copied());
+        assert!(dtw(&template, &audio, true, 1) < 1e-6);
+        assert!(dtw(&template, &audio, false, 1) > 1.0);
+        // The match has to end near the end of the audio.
+        audio.extend((0..10).map(|_| frame(-20.0)));
+        assert!(dtw(&template, &audio, true, 3) > 1.0);
+    }
+
+    #[test]
+    fn trim_keeps_the_loud_part() {
+        let frames = [-100.0, -60.0, -10.0, -25.0, -5.0, -40.0, -100.0]
+            .iter()
+            .enumerate()
+            .map(|(i, db)| Frame { coeffs: frame(i as f32), db: *db })
+            .collect();
+        let kept: Vec<f32> = trim(frames).iter().map(|f| f[0]).collect();
+        assert_eq!(kept, vec![2.0, 3.0, 4.0]);
+        let silence = (0..5).map(|_| Frame { coeffs: frame(0.0), db: -100.0 }).collect();
+        assert!(trim(silence).is_empty());
+    }
+
+    #[test]
+    fn templates_survive_a_round_trip_and_bad_files_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("irisva-custom-wake-{}", std::process::id()));
+        let path = dir.join("tones.json");
+        let templates = Templates { name: "tones".to_string(), threshold: 2.5, templates: vec![vec![frame(1.0); MIN_TEMPLATE_FRAMES]] };
+        templates.save(&path).unwrap();
+        let loaded = Templates::load(&path).unwrap();
+        assert_eq!((loaded.name.as_str(), loaded.threshold), ("tones", 2.5));
+        assert_eq!(loaded.templates, templates.templates);
+        for bad in [
+            r#"{"threshold": 1.0}"#,
+            r#"{"threshold": 1.0, "templates": []}"#,
+            r#"{"threshold": 1.0, "templates": [[]]}"#,
+            r#"{"threshold": 1.0, "templates": [[[0,0,0,0,0,0,0,0,0,0,0,0]]]}"#,
+            r#"{"threshold": 1.0, "templates": [[[1.0, 2.0]]]}"#,
+            r#"{"templates": [[[0,0,0,0,0,0,0,0,0,0,0,0]]]}"#,
+        ] {
+            fs::write(&path, bad).unwrap();
+            assert!(Templates::load(&path).is_err(), "{}", bad);
+        }
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn detects_the_enrolled_phrase_once() {
+        let mut detector = detector(&[400.0, 1500.0, 3000.0]);
+        let mut audio = vec![0i16; 8000];
+        audio.extend(phrase(&[400.0, 1500.0, 3000.0]));
+        audio.extend(vec![0i16; 16000]);
+        let hits: Vec<f32> = audio.chunks(512).filter_map(|block| detector.push(block)).collect();
+        assert_eq!(hits.len(), 1, "{:?}", hits);
+        assert!(detector.take_recent_hit(RECENT));
+        // Consumed by the first look.
+        assert!(!detector.take_recent_hit(RECENT));
+    }
+
+    #[test]
+    fn ignores_other_sounds() {
+        let mut detector = detector(&[400.0, 1500.0, 3000.0]);
+        let mut audio = phrase(&[3000.0, 700.0, 2000.0]);
+        audio.extend(vec![0i16; 16000]);
+        assert!(audio.chunks(512).all(|block| detector.push(block).is_none()));
+        assert!(!detector.take_recent_hit(RECENT));
+    }
+}
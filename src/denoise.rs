@@ -0,0 +1,168 @@
+//! Stationary noise suppression: a Wiener gain per FFT bin with decision-directed SNR
+//! estimation, against a noise spectrum learned while nobody is speaking.
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use crate::mfcc::{fft, ifft};
+
+const FRAME_MS: u32 = 32;
+// Frames at the start that seed the noise spectrum, assumed to be room noise only.
+const INIT_FRAMES: usize = 8;
+// A frame this many times above the noise floor counts as speech.
+const SPEECH_RATIO: f32 = 3.0;
+// Noise update per frame outside speech, and the slow creep during speech so a fan that
+// starts mid-sentence is still learned.
+const NOISE_ADAPT: f32 = 0.1;
+const NOISE_CREEP: f32 = 0.002;
+// Weight of the previous frame in the a-priori SNR; higher means less musical noise.
+const DD_ALPHA: f32 = 0.98;
+
+pub struct Denoiser {
+    frame_len: usize,
+    hop: usize,
+    /// Square-root Hann, used for analysis and synthesis.
+    window: Vec<f32>,
+    floor: f32,
+    /// Samples of the next frame, starting with the overlap from the previous one.
+    input: Vec<f32>,
+    /// Second half of the last synthesized frame, still to be overlap-added.
+    overlap: Vec<f32>,
+    output: VecDeque<f32>,
+    noise: Vec<f32>,
+    clean: Vec<f32>,
+    frames: usize,
+}
+
+impl Denoiser {
+    /// `floor_db` is the most a bin is attenuated, e.g. -20.
+    pub fn new(sample_rate: f32, floor_db: f32) -> Self {
+        let frame_len = ((sample_rate * FRAME_MS as f32 / 1000.0) as usize).next_power_of_two();
+        let hop = frame_len / 2;
+        let window = (0..frame_len)
+            .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f32 / frame_len as f32).cos()).sqrt())
+            .collect();
+        let bins = frame_len / 2 + 1;
+        Denoiser {
+            frame_len,
+            hop,
+            window,
+            floor: 10f32.powf(floor_db / 20.0),
+            input: vec![0.0; frame_len - hop],
+            overlap: vec![0.0; frame_len - hop],
+            // Covers the partial hop still waiting in `input`, so output keeps pace with input.
+            output: std::iter::repeat_n(0.0, hop).collect(),
+            noise: vec![0.0; bins],
+            clean: vec![0.0; bins],
+            frames: 0,
+        }
+    }
+
+    /// Replaces `x` with the denoised signal, delayed by one frame.
+    pub fn process(&mut self, x: &mut [f32]) {
+        self.input.extend_from_slice(x);
+        while self.input.len() >= self.frame_len {
+            self.frame();
+            self.input.drain(..self.hop);
+        }
+        for s in x.iter_mut() {
+            *s = self.output.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    fn frame(&mut self) {
+        let n = self.frame_len;
+        let mut re: Vec<f32> = self.input[..n].iter().zip(&self.window).map(|(x, w)| x * w).collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+        let power: Vec<f32> = (0..=n / 2).map(|k| re[k] * re[k] + im[k] * im[k]).collect();
+
+        self.frames += 1;
+        if self.frames <= INIT_FRAMES {
+            let w = 1.0 / self.frames as f32;
+            for (noise, p) in self.noise.iter_mut().zip(&power) {
+                *noise += w * (p - *noise);
+            }
+        } else {
+            let speech = power.iter().sum::<f32>() > SPEECH_RATIO * self.noise.iter().sum::<f32>();
+            let rate = if speech { NOISE_CREEP } else { NOISE_ADAPT };
+            for (noise, p) in self.noise.iter_mut().zip(&power) {
+                *noise += rate * (p - *noise);
+            }
+        }
+
+        for (k, &p) in power.iter().enumerate() {
+            let noise = self.noise[k].max(1e-12);
+            let posterior = (p / noise - 1.0).max(0.0);
+            let prior = DD_ALPHA * self.clean[k] / noise + (1.0 - DD_ALPHA) * posterior;
+            let gain = (prior / (1.0 + prior)).max(self.floor);
+            self.clean[k] = gain * gain * p;
+            re[k] *= gain;
+            im[k] *= gain;
+            if k != 0 && k != n / 2 {
+                re[n - k] *= gain;
+                im[n - k] *= gain;
+            }
+        }
+
+        ifft(&mut re, &mut im);
+        let frame: Vec<f32> = re.iter().zip(&self.window).map(|(x, w)| x * w).collect();
+        let (head, tail) = frame.split_at(self.hop);
+        self.output.extend(head.iter().zip(&self.overlap).map(|(y, o)| y + o));
+        self.overlap.copy_from_slice(tail);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_signals::{noise, power};
+
+    const SR: f32 = 16000.0;
+    const SEED: u32 = 0x9e37_79b9;
+
+    fn tone(len: usize) -> Vec<f32> {
+        (0..len).map(|i| 0.3 * (2.0 * PI * 500.0 * i as f32 / SR).sin()).collect()
+    }
+
+    // Runs in uneven blocks, the way audio callbacks deliver it.
+    fn run(denoiser: &mut Denoiser, signal: &[f32]) -> Vec<f32> {
+        let mut out = signal.to_vec();
+        for chunk in out.chunks_mut(317) {
+            denoiser.process(chunk);
+        }
+        out
+    }
+
+    #[test]
+    fn passes_clean_signal_with_a_fixed_delay() {
+        let len = SR as usize;
+        let mut d = Denoiser::new(SR, -20.0);
+        let delay = d.frame_len;
+        let mut signal = noise(1e-4, len, SEED);
+        for (s, t) in signal.iter_mut().zip(tone(len)).skip(len / 4) {
+            *s += t;
+        }
+        let out = run(&mut d, &signal);
+        assert_eq!(out.len(), signal.len());
+        let (a, b) = (&signal[len / 2..len - delay], &out[len / 2 + delay..]);
+        let err: Vec<f32> = a.iter().zip(b).map(|(x, y)| x - y).collect();
+        assert!(power(&err) < power(a) * 0.01);
+    }
+
+    #[test]
+    fn suppresses_steady_noise_and_keeps_the_tone() {
+        let len = 2 * SR as usize;
+        let hiss = noise(0.05, len, SEED);
+        let mut signal = hiss.clone();
+        for (s, t) in signal.iter_mut().zip(tone(len)).skip(len / 2) {
+            *s += t;
+        }
+        let out = run(&mut Denoiser::new(SR, -20.0), &signal);
+        let quiet = &out[len / 4..len / 2 - 1000];
+        assert!(power(quiet) < power(&hiss[len / 4..len / 2]) * 0.1);
+        let loud = &out[len * 3 / 4..];
+        let tone_power = power(&tone(len)[len * 3 / 4..]);
+        assert!((power(loud) / tone_power - 1.0).abs() < 0.25);
+    }
+}
@@ -0,0 +1,507 @@
+//! Signal conditioning applied to the mono input before recognition, in this order:
+//! DC removal, high-pass, noise suppression, noise gate, automatic gain control, soft limiter.
+
+use std::f32::consts::PI;
+
+use crate::arg_value;
+use crate::denoise::Denoiser;
+
+// Corner of the DC blocker; well below any speech content.
+const DC_CUTOFF_HZ: f32 = 10.0;
+// AGC holds its gain below this input level instead of boosting room noise.
+const AGC_SILENCE_DB: f32 = -65.0;
+const AGC_LEVEL_MS: f32 = 100.0;
+const AGC_ATTACK_MS: f32 = 20.0;
+const AGC_RELEASE_MS: f32 = 500.0;
+// Knee of the soft limiter, relative to its ceiling.
+const LIMITER_KNEE_DB: f32 = -6.0;
+const GATE_HOLD_MS: f32 = 200.0;
+const GATE_OPEN_MS: f32 = 1.0;
+const GATE_CLOSE_MS: f32 = 50.0;
+// A closed gate attenuates rather than mutes, so the recognizer still sees a noise floor.
+const GATE_RANGE_DB: f32 = -40.0;
+/// Thresholds `--noise-gate` accepts.
+pub const GATE_THRESHOLDS_DB: std::ops::RangeInclusive<f32> = -90.0..=-10.0;
+
+#[derive(Clone)]
+pub struct DspConfig {
+    pub dc_block: bool,
+    pub highpass_hz: Option<f32>,
+    /// Deepest attenuation of the noise suppressor, when on.
+    pub denoise_floor_db: Option<f32>,
+    pub agc: Option<AgcConfig>,
+    pub limiter_ceiling_db: Option<f32>,
+    pub gate_threshold_db: Option<f32>,
+}
+
+#[derive(Clone)]
+pub struct AgcConfig {
+    pub target_db: f32,
+    pub max_gain_db: f32,
+    pub min_gain_db: f32,
+}
+
+impl DspConfig {
+    /// `--dc-block true`, `--highpass <Hz>`, `--denoise true` (with `--denoise-floor` in dB),
+    /// `--agc true` (with `--agc-target`, `--agc-max-gain`,
+    /// `--agc-min-gain` in dB), `--limiter true` (with `--limiter-ceiling` in dBFS) and
+    /// `--noise-gate <dBFS>`; `None` when every stage is off.
+    pub fn from_args(args: &[(String, String)]) -> Option<Result<Self, String>> {
+        match Self::parse(args) {
+            Ok(config) if !config.is_enabled() => None,
+            result => Some(result),
+        }
+    }
+
+    fn parse(args: &[(String, String)]) -> Result<Self, String> {
+        let flag = |key: &str| match arg_value(args, key) {
+            None | Some("false") => Ok(false),
+            Some("true") => Ok(true),
+            Some(v) => Err(format!("Invalid value for {}: '{}' (expected true or false)", key, v)),
+        };
+        let db = |key: &str, default: f32, range: std::ops::RangeInclusive<f32>| match arg_value(args, key) {
+            Some(v) => v
+                .parse::<f32>()
+                .ok()
+                .filter(|x| range.contains(x))
+                .ok_or_else(|| {
+                    format!("Invalid value for {}: '{}' (expected {} to {} dB)", key, v, range.start(), range.end())
+                }),
+            None => Ok(default),
+        };
+
+        let highpass_hz = match arg_value(args, "--highpass") {
+            None | Some("off") => None,
+            Some(v) => Some(
+                v.parse::<f32>()
+                    .ok()
+                    .filter(|hz| (20.0..=1000.0).contains(hz))
+                    .ok_or_else(|| format!("Invalid value for --highpass: '{}' (expected 20 to 1000 Hz)", v))?,
+            ),
+        };
+        let denoise_floor_db = if flag("--denoise")? {
+            Some(db("--denoise-floor", -20.0, -40.0..=-3.0)?)
+        } else {
+            None
+        };
+        let agc = if flag("--agc")? {
+            Some(AgcConfig {
+                target_db: db("--agc-target", -20.0, -40.0..=-3.0)?,
+                max_gain_db: db("--agc-max-gain", 30.0, 0.0..=60.0)?,
+                min_gain_db: db("--agc-min-gain", -20.0, -60.0..=0.0)?,
+            })
+        } else {
+            None
+        };
+        let limiter_ceiling_db = if flag("--limiter")? {
+            Some(db("--limiter-ceiling", -1.0, -20.0..=0.0)?)
+        } else {
+            None
+        };
+        let gate_threshold_db = match arg_value(args, "--noise-gate") {
+            None | Some("off") => None,
+            Some(_) => Some(db("--noise-gate", 0.0, GATE_THRESHOLDS_DB)?),
+        };
+        Ok(DspConfig {
+            dc_block: flag("--dc-block")?,
+            highpass_hz,
+            denoise_floor_db,
+            agc,
+            limiter_ceiling_db,
+            gate_threshold_db,
+        })
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.dc_block
+            || self.highpass_hz.is_some()
+            || self.denoise_floor_db.is_some()
+            || self.agc.is_some()
+            || self.limiter_ceiling_db.is_some()
+            || self.gate_threshold_db.is_some()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut stages = Vec::new();
+        if self.dc_block {
+            stages.push("DC removal".to_string());
+        }
+        if let Some(hz) = self.highpass_hz {
+            stages.push(format!("high-pass {} Hz", hz));
+        }
+        if let Some(db) = self.denoise_floor_db {
+            stages.push(format!("noise suppression down to {} dB", db));
+        }
+        if let Some(db) = self.gate_threshold_db {
+            stages.push(format!("noise gate {} dBFS", db));
+        }
+        if let Some(agc) = &self.agc {
+            stages.push(format!(
+                "AGC to {} dBFS ({:+} to {:+} dB)",
+                agc.target_db, agc.min_gain_db, agc.max_gain_db
+            ));
+        }
+        if let Some(db) = self.limiter_ceiling_db {
+            stages.push(format!("limiter at {} dBFS", db));
+        }
+        stages.join(", ")
+    }
+}
+
+/// The enabled stages with their running state.
+pub struct Chain {
+    config: DspConfig,
+    dc: Option<DcBlock>,
+    highpass: Option<Biquad>,
+    denoise: Option<Denoiser>,
+    gate: Option<NoiseGate>,
+    agc: Option<Agc>,
+    limiter: Option<Limiter>,
+}
+
+impl Chain {
+    pub fn new(config: DspConfig, sample_rate: u32) -> Self {
+        let sr = sample_rate as f32;
+        Chain {
+            dc: config.dc_block.then(|| DcBlock::new(sr)),
+            highpass: config.highpass_hz.map(|hz| Biquad::highpass(hz, sr)),
+            denoise: config.denoise_floor_db.map(|db| Denoiser::new(sr, db)),
+            gate: config.gate_threshold_db.map(|db| NoiseGate::new(db, sr)),
+            agc: config.agc.as_ref().map(|agc| Agc::new(agc, sr)),
+            limiter: config.limiter_ceiling_db.map(Limiter::new),
+            config,
+        }
+    }
+
+    /// Filters depend on the rate, so a new rate starts from a fresh state.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        *self = Chain::new(self.config.clone(), sample_rate);
+    }
+
+    pub fn process(&mut self, pcm: &[i16]) -> Vec<i16> {
+        let mut x: Vec<f32> = pcm.iter().map(|&s| s as f32 / 32768.0).collect();
+        self.process_f32(&mut x);
+        x.iter().map(|&s| (s * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16).collect()
+    }
+
+    fn process_f32(&mut self, x: &mut [f32]) {
+        if let Some(dc) = &mut self.dc {
+            dc.process(x);
+        }
+        if let Some(hp) = &mut self.highpass {
+            hp.process(x);
+        }
+        if let Some(denoise) = &mut self.denoise {
+            denoise.process(x);
+        }
+        if let Some(gate) = &mut self.gate {
+            gate.process(x);
+        }
+        if let Some(agc) = &mut self.agc {
+            agc.process(x);
+        }
+        if let Some(limiter) = &self.limiter {
+            limiter.process(x);
+        }
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// One-pole smoothing coefficient for a time constant.
+fn coef(ms: f32, sample_rate: f32) -> f32 {
+    1.0 - (-1000.0 / (ms * sample_rate)).exp()
+}
+
+/// One-pole/one-zero DC blocker.
+struct DcBlock {
+    r: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl DcBlock {
+    fn new(sample_rate: f32) -> Self {
+        DcBlock { r: (-2.0 * PI * DC_CUTOFF_HZ / sample_rate).exp(), x1: 0.0, y1: 0.0 }
+    }
+
+    fn process(&mut self, x: &mut [f32]) {
+        for s in x {
+            let y = *s - self.x1 + self.r * self.y1;
+            self.x1 = *s;
+            self.y1 = y;
+            *s = y;
+        }
+    }
+}
+
+/// Second-order Butterworth section (RBJ cookbook), direct form I.
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    fn highpass(cutoff: f32, sample_rate: f32) -> Self {
+        let w0 = 2.0 * PI * cutoff.min(sample_rate * 0.45) / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * std::f32::consts::FRAC_1_SQRT_2);
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b: [(1.0 + cos) / 2.0 / a0, -(1.0 + cos) / a0, (1.0 + cos) / 2.0 / a0],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: &mut [f32]) {
+        for s in x {
+            let y = self.b[0] * *s + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+                - self.a[0] * self.y[0]
+                - self.a[1] * self.y[1];
+            self.x = [*s, self.x[0]];
+            self.y = [y, self.y[0]];
+            *s = y;
+        }
+    }
+}
+
+/// Feed-forward AGC: tracks the input level and steers the gain towards the target,
+/// reducing quickly and raising slowly.
+struct Agc {
+    target_db: f32,
+    min_gain_db: f32,
+    max_gain_db: f32,
+    level_coef: f32,
+    attack: f32,
+    release: f32,
+    power: f32,
+    gain_db: f32,
+}
+
+impl Agc {
+    fn new(config: &AgcConfig, sample_rate: f32) -> Self {
+        Agc {
+            target_db: config.target_db,
+            min_gain_db: config.min_gain_db,
+            max_gain_db: config.max_gain_db,
+            level_coef: coef(AGC_LEVEL_MS, sample_rate),
+            attack: coef(AGC_ATTACK_MS, sample_rate),
+            release: coef(AGC_RELEASE_MS, sample_rate),
+            power: 0.0,
+            gain_db: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: &mut [f32]) {
+        for s in x {
+            self.power += self.level_coef * (*s * *s - self.power);
+            let level_db = 10.0 * self.power.max(1e-12).log10();
+            if level_db > AGC_SILENCE_DB {
+                let wanted = (self.target_db - level_db).clamp(self.min_gain_db, self.max_gain_db);
+                let rate = if wanted < self.gain_db { self.attack } else { self.release };
+                self.gain_db += rate * (wanted - self.gain_db);
+            }
+            *s *= db_to_gain(self.gain_db);
+        }
+    }
+}
+
+/// Soft limiter: linear below the knee, then a tanh curve that never reaches the ceiling.
+struct Limiter {
+    knee: f32,
+    ceiling: f32,
+}
+
+impl Limiter {
+    fn new(ceiling_db: f32) -> Self {
+        Limiter { knee: db_to_gain(ceiling_db + LIMITER_KNEE_DB), ceiling: db_to_gain(ceiling_db) }
+    }
+
+    fn process(&self, x: &mut [f32]) {
+        let span = self.ceiling - self.knee;
+        for s in x {
+            let a = s.abs();
+            if a > self.knee {
+                *s = (self.knee + span * ((a - self.knee) / span).tanh()).copysign(*s);
+            }
+        }
+    }
+}
+
+/// Attenuates by `GATE_RANGE_DB` while the peak envelope stays under the threshold
+/// for longer than the hold time.
+struct NoiseGate {
+    threshold: f32,
+    release: f32,
+    open: f32,
+    close: f32,
+    hold: u32,
+    hold_left: u32,
+    envelope: f32,
+    gain: f32,
+}
+
+impl NoiseGate {
+    fn new(threshold_db: f32, sample_rate: f32) -> Self {
+        NoiseGate {
+            threshold: db_to_gain(threshold_db),
+            release: 1.0 - coef(GATE_CLOSE_MS, sample_rate),
+            open: coef(GATE_OPEN_MS, sample_rate),
+            close: coef(GATE_CLOSE_MS, sample_rate),
+            hold: (GATE_HOLD_MS * sample_rate / 1000.0) as u32,
+            hold_left: 0,
+            envelope: 0.0,
+            gain: db_to_gain(GATE_RANGE_DB),
+        }
+    }
+
+    fn process(&mut self, x: &mut [f32]) {
+        let floor = db_to_gain(GATE_RANGE_DB);
+        for s in x {
+            self.envelope = s.abs().max(self.envelope * self.release);
+            if self.envelope >= self.threshold {
+                self.hold_left = self.hold;
+            } else {
+                self.hold_left = self.hold_left.saturating_sub(1);
+            }
+            let (target, rate) = if self.hold_left > 0 { (1.0, self.open) } else { (floor, self.close) };
+            self.gain += rate * (target - self.gain);
+            *s *= self.gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_args::args;
+    use crate::test_signals::{noise, power};
+
+    const SR: f32 = 16000.0;
+
+    fn sine(freq: f32, amplitude: f32, secs: f32) -> Vec<f32> {
+        (0..(SR * secs) as usize)
+            .map(|i| amplitude * (2.0 * PI * freq * i as f32 / SR).sin())
+            .collect()
+    }
+
+    fn rms_db(x: &[f32]) -> f32 {
+        10.0 * power(x).max(1e-12).log10()
+    }
+
+    fn white(amplitude: f32, secs: f32) -> Vec<f32> {
+        noise(amplitude, (SR * secs) as usize, 0x2545_f491)
+    }
+
+    fn tail(x: &[f32], secs: f32) -> &[f32] {
+        &x[x.len() - (SR * secs) as usize..]
+    }
+
+    #[test]
+    fn dc_block_removes_offset() {
+        let mut x: Vec<f32> = sine(440.0, 0.2, 2.0).iter().map(|s| s + 0.3).collect();
+        DcBlock::new(SR).process(&mut x);
+        let t = tail(&x, 0.5);
+        let mean = t.iter().sum::<f32>() / t.len() as f32;
+        assert!(mean.abs() < 0.005, "mean {}", mean);
+        assert!((rms_db(t) - rms_db(&sine(440.0, 0.2, 0.5))).abs() < 0.5);
+    }
+
+    #[test]
+    fn highpass_cuts_rumble_and_keeps_speech_band() {
+        let mut low = sine(20.0, 0.5, 2.0);
+        let mut high = sine(1000.0, 0.5, 2.0);
+        Biquad::highpass(100.0, SR).process(&mut low);
+        Biquad::highpass(100.0, SR).process(&mut high);
+        let reference = rms_db(&sine(1000.0, 0.5, 0.5));
+        assert!(reference - rms_db(tail(&low, 0.5)) > 24.0);
+        assert!((reference - rms_db(tail(&high, 0.5))).abs() < 0.5);
+    }
+
+    fn agc() -> Agc {
+        Agc::new(&AgcConfig { target_db: -20.0, max_gain_db: 30.0, min_gain_db: -20.0 }, SR)
+    }
+
+    #[test]
+    fn agc_raises_quiet_input_to_target() {
+        let mut x = sine(300.0, 0.01, 5.0); // about -43 dBFS
+        agc().process(&mut x);
+        assert!((rms_db(tail(&x, 0.5)) + 20.0).abs() < 1.5, "{}", rms_db(tail(&x, 0.5)));
+    }
+
+    #[test]
+    fn agc_lowers_hot_input_to_target() {
+        let mut x = sine(300.0, 0.9, 3.0); // about -4 dBFS
+        agc().process(&mut x);
+        assert!((rms_db(tail(&x, 0.5)) + 20.0).abs() < 1.5, "{}", rms_db(tail(&x, 0.5)));
+    }
+
+    #[test]
+    fn agc_gain_is_capped() {
+        let input = sine(300.0, 0.001, 5.0); // about -63 dBFS, needs 43 dB
+        let mut x = input.clone();
+        agc().process(&mut x);
+        let gain = rms_db(tail(&x, 0.5)) - rms_db(tail(&input, 0.5));
+        assert!((gain - 30.0).abs() < 1.0, "{}", gain);
+    }
+
+    #[test]
+    fn agc_does_not_boost_silence() {
+        let mut x = white(0.0001, 3.0); // about -85 dBFS
+        agc().process(&mut x);
+        assert!(rms_db(&x) < -80.0);
+    }
+
+    #[test]
+    fn limiter_stays_under_ceiling_and_leaves_quiet_audio_alone() {
+        let limiter = Limiter::new(-1.0);
+        let mut loud = sine(300.0, 2.0, 0.5);
+        limiter.process(&mut loud);
+        let ceiling = db_to_gain(-1.0);
+        assert!(loud.iter().all(|s| s.abs() < ceiling));
+
+        let quiet = sine(300.0, 0.3, 0.5);
+        let mut x = quiet.clone();
+        limiter.process(&mut x);
+        assert_eq!(x, quiet);
+    }
+
+    #[test]
+    fn noise_gate_attenuates_noise_and_passes_speech() {
+        let mut gate = NoiseGate::new(-40.0, SR);
+        let mut quiet = white(0.003, 1.0); // peaks around -50 dBFS
+        gate.process(&mut quiet);
+        assert!(rms_db(tail(&quiet, 0.5)) < -50.0 + GATE_RANGE_DB + 6.0);
+
+        let mut loud = sine(300.0, 0.3, 1.0);
+        gate.process(&mut loud);
+        assert!((rms_db(tail(&loud, 0.5)) - rms_db(&sine(300.0, 0.3, 0.5))).abs() < 0.5);
+    }
+
+    #[test]
+    fn stages_are_toggled_by_flags() {
+        assert!(DspConfig::from_args(&args(&[("--dc-block", "false")])).is_none());
+        let config = DspConfig::from_args(&args(&[("--highpass", "80"), ("--limiter", "true")]))
+            .unwrap()
+            .unwrap();
+        let chain = Chain::new(config, 16000);
+        assert!(chain.dc.is_none() && chain.agc.is_none() && chain.gate.is_none());
+        assert!(chain.highpass.is_some() && chain.limiter.is_some());
+        assert!(DspConfig::from_args(&args(&[("--highpass", "5")])).unwrap().is_err());
+        assert!(DspConfig::from_args(&args(&[("--agc", "yes")])).unwrap().is_err());
+    }
+
+    #[test]
+    fn chain_round_trips_i16() {
+        let config = DspConfig::from_args(&args(&[("--limiter", "true")])).unwrap().unwrap();
+        let mut chain = Chain::new(config, 16000);
+        let pcm: Vec<i16> = (0..1600).map(|i| ((i % 200) as i16 - 100) * 50).collect();
+        assert_eq!(chain.process(&pcm), pcm);
+    }
+}
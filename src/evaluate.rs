@@ -0,0 +1,583 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+use vosk::{Model, Recognizer};
+
+use crate::aec::{self, Aec};
+use crate::dsp::{Chain, DspConfig};
+use crate::itn::{self, LanguageRules};
+use crate::wake_verify::{WakeCheck, WakeVerifier, WakeVerifyConfig};
+use crate::wav::read_wav;
+use crate::{
+    COMMAND_TIMEOUT, ListeningState, MatchContext, MatchEvent, arg_value, configure_recognizer,
+    create_waveform_match, wake_words_from_args,
+};
+
+// Silence appended after each clip so the recognizer can endpoint the last utterance.
+const TRAILING_SILENCE: Duration = Duration::from_secs(2);
+// On/off options that `--ab` can turn on without being given a value.
+const AB_SWITCHES: &[&str] = &["--aec", "--agc", "--dc-block", "--denoise", "--limiter", "--wake-verify-grammar"];
+
+struct Expected {
+    file: PathBuf,
+    wake: bool,
+    command: Option<String>,
+    // Where speech ends inside the clip; defaults to the end of the audio.
+    speech_end_ms: Option<u64>,
+    // What the speakers played while the clip was recorded, for echo cancellation.
+    reference: Option<PathBuf>,
+}
+
+struct Outcome {
+    file: String,
+    expected_wake: bool,
+    expected_command: Option<String>,
+    woke: bool,
+    command: Option<String>,
+    word_errors: usize,
+    ref_words: usize,
+    latency_ms: Option<u64>,
+    audio_ms: u64,
+    decode_ms: u64,
+}
+
+/// `evaluate <clips dir> [--manifest file] [--format text|json]`
+///
+/// Wake phrases come from `--wake`/`--wake-<language>` and commands are normalized (`--itn`)
+/// as in live listening; expected commands are normalized the same way before scoring. The
+/// `--wake-verify-grammar`, `--wake-min-confidence` and `--wake-min-duration-ms` options
+/// apply as in live listening, and the report counts what each stage accepted. Input
+/// conditioning options (`--agc`, `--highpass`, ...) are applied to each clip as well.
+///
+/// `--ab <option>` (e.g. `--ab denoise`) runs the clips twice, without the option and with it
+/// (its value from the command line; on/off options default to `true`), and compares the two runs.
+///
+/// The manifest is a JSON array (or JSON lines) of
+/// `{"file": "a.wav", "wake": true, "command": "turn on the lights", "speech_end_ms": 2100}`;
+/// it defaults to `<clips dir>/manifest.json`. A `"reference": "a.playback.wav"` entry is the
+/// audio played during the clip, cancelled from it with `--aec true`. `--aec` exists only here,
+/// where each clip brings its own reference; live listening enables echo cancellation with
+/// `--aec-reference <device>` or `--aec-reference-file <file>` instead.
+pub fn run(model: &Arc<Model>, positionals: &[String], args: &[(String, String)]) -> Result<(), String> {
+    let dir = positionals
+        .first()
+        .map(PathBuf::from)
+        .or_else(|| arg_value(args, "--clips").map(PathBuf::from))
+        .ok_or(
+            "Usage: evaluate <clips dir> [--manifest file] [--format text|json] [--ab <option>] [--aec true]\n  \
+             --aec applies only to evaluate (manifest references); live mode uses --aec-reference[-file]",
+        )?;
+    let manifest = arg_value(args, "--manifest")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dir.join("manifest.json"));
+    let format = arg_value(args, "--format").unwrap_or("text");
+    if format != "text" && format != "json" {
+        return Err(format!("Unknown --format '{}' (expected text or json)", format));
+    }
+
+    let expected = load_manifest(&manifest, &dir)?;
+    if expected.is_empty() {
+        return Err(format!("Manifest {} lists no clips", manifest.display()));
+    }
+
+    if let Some(option) = arg_value(args, "--ab") {
+        let (key, without, with) = ab_variants(args, option)?;
+        let (a, a_outcomes) = evaluate_all(model, &expected, &without)?;
+        let (b, b_outcomes) = evaluate_all(model, &expected, &with)?;
+        if format == "json" {
+            let report = json!({
+                "option": key,
+                "a": with_results(a, &a_outcomes),
+                "b": with_results(b, &b_outcomes),
+            });
+            println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+        } else {
+            let label = format!("{} {}", key, with.last().map(|(_, v)| v.as_str()).unwrap_or_default());
+            print_ab_report(&label, &a, &b, &a_outcomes, &b_outcomes);
+        }
+        return Ok(());
+    }
+
+    let (report, outcomes) = evaluate_all(model, &expected, args)?;
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&with_results(report, &outcomes)).unwrap_or_default());
+    } else {
+        print_text_report(&report, &outcomes);
+    }
+    Ok(())
+}
+
+// The option's name and the arguments for the runs without and with it.
+type Variants = (String, Vec<(String, String)>, Vec<(String, String)>);
+
+fn ab_variants(args: &[(String, String)], option: &str) -> Result<Variants, String> {
+    let key = format!("--{}", option.trim_start_matches("--"));
+    let value = match arg_value(args, &key) {
+        Some(value) => value,
+        None if AB_SWITCHES.contains(&key.as_str()) => "true",
+        None => return Err(format!("--ab {} needs a value to compare against: pass {} <value> as well", option, key)),
+    };
+    let without: Vec<(String, String)> = args.iter().filter(|(k, _)| *k != key).cloned().collect();
+    let mut with = without.clone();
+    with.push((key.clone(), value.to_string()));
+    Ok((key, without, with))
+}
+
+// One pass over every clip with the options in `args`.
+fn evaluate_all(
+    model: &Arc<Model>,
+    expected: &[Expected],
+    args: &[(String, String)],
+) -> Result<(Value, Vec<Outcome>), String> {
+    let verifier = WakeVerifyConfig::from_args(args).transpose()?.map(|cfg| Arc::new(WakeVerifier::new(cfg)));
+    let dsp = DspConfig::from_args(args).transpose()?;
+    let language = arg_value(args, "--language").unwrap_or("en").to_lowercase();
+    let wake_words = wake_words_from_args(args, &language);
+    let wake_words: Vec<&str> = wake_words.iter().map(String::as_str).collect();
+    let itn = itn::rules_from_args(args)?;
+    let aec_tail = match arg_value(args, "--aec") {
+        None | Some("false") => None,
+        Some("true") => Some(aec::tail_from_args(args)?),
+        Some(v) => return Err(format!("Invalid value for --aec: '{}' (expected true or false)", v)),
+    };
+
+    let mut outcomes = Vec::with_capacity(expected.len());
+    for exp in expected {
+        outcomes.push(evaluate_clip(
+            model,
+            exp,
+            &wake_words,
+            verifier.as_ref(),
+            dsp.as_ref(),
+            aec_tail,
+            itn.as_deref(),
+        )?);
+    }
+
+    let mut report = summarize(&outcomes);
+    if let Some(verifier) = &verifier {
+        report["wake_verification"] = verifier.stats_json();
+    }
+    Ok((report, outcomes))
+}
+
+fn with_results(mut report: Value, outcomes: &[Outcome]) -> Value {
+    report["results"] = Value::Array(outcomes.iter().map(outcome_json).collect());
+    report
+}
+
+fn load_manifest(path: &Path, dir: &Path) -> Result<Vec<Expected>, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read manifest {}: {}", path.display(), e))?;
+    let entries: Vec<Value> = match serde_json::from_str::<Value>(&raw) {
+        Ok(Value::Array(items)) => items,
+        _ => raw
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Invalid manifest {}: {}", path.display(), e))?,
+    };
+
+    let mut out = Vec::with_capacity(entries.len());
+    for (i, e) in entries.iter().enumerate() {
+        let file = e
+            .get("file")
+            .and_then(|f| f.as_str())
+            .ok_or_else(|| format!("Manifest entry {} has no \"file\"", i + 1))?;
+        let command = e
+            .get("command")
+            .and_then(|c| c.as_str())
+            .map(|c| c.trim().to_lowercase())
+            .filter(|c| !c.is_empty());
+        let wake = e
+            .get("wake")
+            .and_then(|w| w.as_bool())
+            .unwrap_or(command.is_some());
+        out.push(Expected {
+            file: dir.join(file),
+            wake,
+            command,
+            speech_end_ms: e.get("speech_end_ms").and_then(|v| v.as_u64()),
+            reference: e.get("reference").and_then(|r| r.as_str()).map(|r| dir.join(r)),
+        });
+    }
+    Ok(out)
+}
+
+fn evaluate_clip(
+    model: &Arc<Model>,
+    exp: &Expected,
+    wake_words: &[&str],
+    verifier: Option<&Arc<WakeVerifier>>,
+    dsp: Option<&DspConfig>,
+    aec_tail: Option<Duration>,
+    itn: Option<&dyn LanguageRules>,
+) -> Result<Outcome, String> {
+    let audio = read_wav(&exp.file)?;
+    let sample_rate = audio.sample_rate;
+    let playback = match (aec_tail, &exp.reference) {
+        (Some(tail), Some(path)) => Some((Aec::new(sample_rate, tail), read_wav(path)?)),
+        _ => None,
+    };
+    let mut recognizer = Recognizer::new(model, sample_rate as f32)
+        .ok_or_else(|| format!("Failed to create recognizer at {} Hz", sample_rate))?;
+    configure_recognizer(&mut recognizer, verifier.is_some_and(|v| v.uses_word_timing()));
+    // Every clip starts its own cleanup count, so a clip decodes the same whatever ran before
+    // it, and the two passes of --ab stay comparable.
+    let mut calls = 0;
+
+    let triggered = Arc::new(Mutex::new(false));
+    let state = Arc::new(Mutex::new(ListeningState::Idle));
+
+    let chunk = (sample_rate as usize / 10).max(1);
+    let silence_len = (TRAILING_SILENCE.as_millis() as u64 * sample_rate as u64 / 1000) as usize;
+    let mut padded = audio.samples.clone();
+    padded.resize(audio.samples.len() + silence_len, 0);
+
+    let to_ms = |n: usize| n as u64 * 1000 / sample_rate as u64;
+    let speech_end = exp
+        .speech_end_ms
+        .unwrap_or_else(|| to_ms(audio.samples.len()));
+
+    let mut woke = false;
+    let mut wake_at: Option<usize> = None;
+    let mut command: Option<String> = None;
+    let mut event_at: Option<usize> = None;
+    let mut fed = 0usize;
+    let mut utterance = Vec::new();
+    let mut chain = dsp.map(|cfg| Chain::new(cfg.clone(), sample_rate));
+    let mut check = verifier.map(|verifier| WakeCheck {
+        verifier,
+        model,
+        sample_rate: sample_rate as f32,
+        utterance: &mut utterance,
+        // Offline there is no callback to keep free, and results must be deterministic.
+        background: false,
+    });
+    let ctx = MatchContext {
+        wake_words,
+        triggered: &triggered,
+        state: &state,
+        recorder: None,
+        lane: 0,
+        hold: false,
+        confirm: None,
+        quiet: true,
+        itn,
+    };
+    let started = Instant::now();
+    for pcm in padded.chunks(chunk) {
+        let cancelled = playback.as_ref().map(|(aec, reference)| {
+            // Hand over the matching stretch of playback first, as the live reference stream would.
+            let at = |n: usize| (n as u64 * reference.sample_rate as u64 / sample_rate as u64) as usize;
+            let span = at(fed).min(reference.samples.len())..at(fed + pcm.len()).min(reference.samples.len());
+            aec.reference.push(&reference.samples[span], reference.sample_rate);
+            aec.process(pcm)
+        });
+        let pcm = cancelled.as_deref().unwrap_or(pcm);
+        fed += pcm.len();
+        let conditioned = chain.as_mut().map(|c| c.process(pcm));
+        let pcm = conditioned.as_deref().unwrap_or(pcm);
+        let event = create_waveform_match(&mut recognizer, &mut calls, pcm, &ctx, check.as_mut());
+        match event {
+            Some(MatchEvent::Wake(_)) => {
+                woke = true;
+                wake_at = Some(fed);
+                if exp.command.is_none() {
+                    event_at = Some(fed);
+                }
+            }
+            Some(MatchEvent::Command(text, normalized, _)) => {
+                woke = true;
+                command = Some(normalized.unwrap_or(text));
+                event_at = Some(fed);
+                break;
+            }
+            Some(MatchEvent::SegmentEnd | MatchEvent::Verifying(_) | MatchEvent::Held(_)) | None => {}
+        }
+        // Mirror the live loop: give up on the command after the timeout.
+        if let Some(at) = wake_at
+            && to_ms(fed - at) > COMMAND_TIMEOUT.as_millis() as u64
+        {
+            *state.lock().unwrap() = ListeningState::Idle;
+            recognizer.reset();
+            if let Some(check) = check.as_mut() {
+                check.utterance.clear();
+            }
+            wake_at = None;
+        }
+    }
+    let decode_ms = started.elapsed().as_millis() as u64;
+
+    let (word_errors, ref_words) = match &exp.command {
+        Some(reference) => {
+            let reference = itn.map(|rules| itn::normalize(rules, reference)).unwrap_or_else(|| reference.clone());
+            let r: Vec<&str> = reference.split_whitespace().collect();
+            let h: Vec<&str> = command
+                .as_deref()
+                .map(|c| c.split_whitespace().collect())
+                .unwrap_or_default();
+            (word_edit_distance(&r, &h), r.len())
+        }
+        None => (0, 0),
+    };
+
+    Ok(Outcome {
+        file: exp.file.display().to_string(),
+        expected_wake: exp.wake,
+        expected_command: exp.command.clone(),
+        woke,
+        command,
+        word_errors,
+        ref_words,
+        latency_ms: event_at.map(|at| to_ms(at).saturating_sub(speech_end)),
+        audio_ms: to_ms(audio.samples.len()),
+        decode_ms,
+    })
+}
+
+fn word_edit_distance(reference: &[&str], hypothesis: &[&str]) -> usize {
+    let mut prev: Vec<usize> = (0..=hypothesis.len()).collect();
+    for (i, r) in reference.iter().enumerate() {
+        let mut cur = vec![i + 1; hypothesis.len() + 1];
+        for (j, h) in hypothesis.iter().enumerate() {
+            let sub = prev[j] + usize::from(r != h);
+            cur[j + 1] = sub.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[hypothesis.len()]
+}
+
+// Nearest-rank percentile over a sorted slice.
+fn percentile(sorted: &[u64], p: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn summarize(outcomes: &[Outcome]) -> Value {
+    let positives = outcomes.iter().filter(|o| o.expected_wake).count();
+    let negatives = outcomes.len() - positives;
+    let false_rejects = outcomes.iter().filter(|o| o.expected_wake && !o.woke).count();
+    let false_accepts = outcomes.iter().filter(|o| !o.expected_wake && o.woke).count();
+    let word_errors: usize = outcomes.iter().map(|o| o.word_errors).sum();
+    let ref_words: usize = outcomes.iter().map(|o| o.ref_words).sum();
+    let commands = outcomes.iter().filter(|o| o.expected_command.is_some()).count();
+
+    let mut latencies: Vec<u64> = outcomes
+        .iter()
+        .filter(|o| o.expected_wake)
+        .filter_map(|o| o.latency_ms)
+        .collect();
+    latencies.sort_unstable();
+
+    let audio_ms: u64 = outcomes.iter().map(|o| o.audio_ms).sum();
+    let decode_ms: u64 = outcomes.iter().map(|o| o.decode_ms).sum();
+    let rate = |n: usize, d: usize| if d == 0 { None } else { Some(n as f64 / d as f64) };
+
+    json!({
+        "clips": outcomes.len(),
+        "wake_clips": positives,
+        "non_wake_clips": negatives,
+        "false_rejects": false_rejects,
+        "false_reject_rate": rate(false_rejects, positives),
+        "false_accepts": false_accepts,
+        "false_accept_rate": rate(false_accepts, negatives),
+        "commands": commands,
+        "command_word_errors": word_errors,
+        "command_ref_words": ref_words,
+        "command_wer": rate(word_errors, ref_words),
+        "latency_ms": {
+            "p50": percentile(&latencies, 50.0),
+            "p90": percentile(&latencies, 90.0),
+            "p99": percentile(&latencies, 99.0),
+            "max": latencies.last(),
+        },
+        "real_time_factor": if audio_ms == 0 { None } else { Some(decode_ms as f64 / audio_ms as f64) },
+    })
+}
+
+fn outcome_json(o: &Outcome) -> Value {
+    json!({
+        "file": o.file,
+        "expected_wake": o.expected_wake,
+        "expected_command": o.expected_command,
+        "woke": o.woke,
+        "command": o.command,
+        "word_errors": o.word_errors,
+        "latency_ms": o.latency_ms,
+    })
+}
+
+fn print_text_report(report: &Value, outcomes: &[Outcome]) {
+    for o in outcomes {
+        let tag = match (o.expected_wake, o.woke) {
+            (true, false) => "FR ",
+            (false, true) => "FA ",
+            _ if o.word_errors > 0 => "WER",
+            _ => continue,
+        };
+        println!(
+            "{} {} expected={:?} got={:?}",
+            tag,
+            o.file,
+            o.expected_command.as_deref().unwrap_or(if o.expected_wake { "<wake>" } else { "<none>" }),
+            o.command.as_deref().unwrap_or(if o.woke { "<wake>" } else { "<none>" }),
+        );
+    }
+
+    let pct = |v: &Value| match v.as_f64() {
+        Some(f) => format!("{:.1}%", f * 100.0),
+        None => "n/a".to_string(),
+    };
+    let ms = |v: &Value| match v.as_u64() {
+        Some(n) => n.to_string(),
+        None => "-".to_string(),
+    };
+    println!();
+    println!(
+        "Clips:         {} ({} wake, {} non-wake)",
+        report["clips"], report["wake_clips"], report["non_wake_clips"]
+    );
+    println!(
+        "False rejects: {}/{} ({})",
+        report["false_rejects"],
+        report["wake_clips"],
+        pct(&report["false_reject_rate"])
+    );
+    println!(
+        "False accepts: {}/{} ({})",
+        report["false_accepts"],
+        report["non_wake_clips"],
+        pct(&report["false_accept_rate"])
+    );
+    println!(
+        "Command WER:   {} ({} errors / {} words over {} commands)",
+        pct(&report["command_wer"]),
+        report["command_word_errors"],
+        report["command_ref_words"],
+        report["commands"]
+    );
+    let lat = &report["latency_ms"];
+    println!(
+        "Latency (ms):  p50 {}  p90 {}  p99 {}  max {}",
+        ms(&lat["p50"]),
+        ms(&lat["p90"]),
+        ms(&lat["p99"]),
+        ms(&lat["max"])
+    );
+    let stages = &report["wake_verification"];
+    if stages.is_object() {
+        println!(
+            "Wake stages:   {} candidates, {} verified, {} unverified, {} rejected ({} low confidence, {} too short, {} grammar)",
+            stages["candidates"],
+            stages["accepted"],
+            stages["unverified"],
+            stages["rejected"],
+            stages["rejected_low_confidence"],
+            stages["rejected_too_short"],
+            stages["rejected_grammar"]
+        );
+    }
+    if let Some(rtf) = report["real_time_factor"].as_f64() {
+        println!("Real-time factor: {:.3}", rtf);
+    }
+}
+
+fn print_ab_report(label: &str, a: &Value, b: &Value, a_outcomes: &[Outcome], b_outcomes: &[Outcome]) {
+    let correct = |o: &Outcome| o.expected_wake == o.woke && o.word_errors == 0;
+    for (oa, ob) in a_outcomes.iter().zip(b_outcomes) {
+        match (correct(oa), correct(ob)) {
+            (false, true) => println!("fixed  {}", oa.file),
+            (true, false) => println!(
+                "broken {} got={:?}",
+                ob.file,
+                ob.command.as_deref().unwrap_or(if ob.woke { "<wake>" } else { "<none>" })
+            ),
+            _ => {}
+        }
+    }
+
+    let pct = |v: &Value| match v.as_f64() {
+        Some(f) => format!("{:.1}%", f * 100.0),
+        None => "n/a".to_string(),
+    };
+    let ms = |v: &Value| match v.as_u64() {
+        Some(n) => n.to_string(),
+        None => "-".to_string(),
+    };
+    let row = |name: &str, a: String, b: String| println!("{:<18} {:<16} {}", name, a, b);
+    println!();
+    row("", "A (without)".to_string(), format!("B ({})", label));
+    row(
+        "False rejects:",
+        format!("{} ({})", a["false_rejects"], pct(&a["false_reject_rate"])),
+        format!("{} ({})", b["false_rejects"], pct(&b["false_reject_rate"])),
+    );
+    row(
+        "False accepts:",
+        format!("{} ({})", a["false_accepts"], pct(&a["false_accept_rate"])),
+        format!("{} ({})", b["false_accepts"], pct(&b["false_accept_rate"])),
+    );
+    row("Command WER:", pct(&a["command_wer"]), pct(&b["command_wer"]));
+    row("Latency p50 (ms):", ms(&a["latency_ms"]["p50"]), ms(&b["latency_ms"]["p50"]));
+    row("Latency p90 (ms):", ms(&a["latency_ms"]["p90"]), ms(&b["latency_ms"]["p90"]));
+    let rtf = |v: &Value| v.as_f64().map(|f| format!("{:.3}", f)).unwrap_or_else(|| "-".to_string());
+    row("Real-time factor:", rtf(&a["real_time_factor"]), rtf(&b["real_time_factor"]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_args::args;
+
+    fn words(s: &str) -> Vec<&str> {
+        s.split_whitespace().collect()
+    }
+
+    #[test]
+    fn counts_word_substitutions_insertions_and_deletions() {
+        let cases = [
+            ("turn on the lights", "turn on the lights", 0),
+            ("turn on the lights", "turn off the lights", 1),
+            ("turn on the lights", "turn on lights", 1),
+            ("turn on the lights", "please turn on the lights now", 2),
+            ("turn on the lights", "", 4),
+            ("", "turn on", 2),
+            ("set a timer", "timer a set", 2),
+        ];
+        for (reference, hypothesis, expected) in cases {
+            assert_eq!(word_edit_distance(&words(reference), &words(hypothesis)), expected, "{:?}", hypothesis);
+        }
+    }
+
+    #[test]
+    fn ab_needs_a_value_for_options_that_are_not_switches() {
+        let args = args(&[("--ab", "highpass"), ("--agc", "true"), ("--highpass", "120")]);
+        let (key, without, with) = ab_variants(&args, "highpass").unwrap();
+        assert_eq!(key, "--highpass");
+        assert_eq!(arg_value(&without, "--highpass"), None);
+        assert_eq!(arg_value(&with, "--highpass"), Some("120"));
+        assert_eq!(arg_value(&with, "--agc"), Some("true"));
+        let (_, _, with) = ab_variants(&args, "--denoise").unwrap();
+        assert_eq!(arg_value(&with, "--denoise"), Some("true"));
+        assert!(ab_variants(&args, "noise-gate").unwrap_err().contains("--noise-gate <value>"));
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let sorted: Vec<u64> = (1..=10).map(|n| n * 100).collect();
+        assert_eq!(percentile(&sorted, 50.0), Some(500));
+        assert_eq!(percentile(&sorted, 90.0), Some(900));
+        assert_eq!(percentile(&sorted, 99.0), Some(1000));
+        assert_eq!(percentile(&sorted, 0.0), Some(100));
+        assert_eq!(percentile(&[42], 50.0), Some(42));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+}
@@ -0,0 +1,261 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+
+/// Everything IrisVA reports to external consumers. Mirrors the stdout tags.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// `language` is the model that heard the phrase; `None` for a forced or custom wake.
+    Wake { phrase: String, forced: bool, language: Option<String> },
+    Partial { text: String, language: String },
+    /// `text` is the raw recognizer output, `normalized` its written form ("25%", "07:30");
+    /// `intent` is set when the text matched an `--intents` definition, `speaker` (the closest
+    /// enrolled voice) when a speaker model is loaded.
+    Command {
+        text: String,
+        normalized: Option<String>,
+        intent: Option<Value>,
+        speaker: Option<Value>,
+        language: String,
+    },
+    /// A rule mapped a command to a named intent.
+    Intent { name: String, slots: Vec<(String, String)>, text: String },
+    State { state: &'static str },
+    Error { message: String },
+    Device { name: String },
+    /// A reloaded model took over for `language`.
+    Model { language: String, path: String },
+    /// The active recognizer was replaced by a fresh one.
+    Swap,
+    /// Input levels over the last `--level-interval-ms`, in dBFS.
+    Level { rms_db: f32, peak_db: f32, noise_floor_db: f32, snr_db: f32, clipped: usize },
+    /// Clipping, silence or low SNR started (`active`) or cleared.
+    AudioWarning { warning: &'static str, message: String, active: bool },
+    /// No audio callbacks from `device` for `secs` seconds.
+    MicStalled { device: String, secs: u64 },
+    /// Callbacks keep arriving from `device` but the signal has been flat for `secs` seconds.
+    MicDead { device: String, secs: u64 },
+    /// The `problem` ("stalled" or "dead") reported earlier has cleared.
+    MicRecovered { device: String, problem: &'static str },
+}
+
+impl Event {
+    /// Every [`Event::kind`], as clients name them when subscribing.
+    pub const KINDS: &'static [&'static str] = &[
+        "wake",
+        "partial",
+        "command",
+        "intent",
+        "state",
+        "error",
+        "device",
+        "model",
+        "swap",
+        "level",
+        "audio-warning",
+        "mic-stalled",
+        "mic-dead",
+        "mic-recovered",
+    ];
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Wake { .. } => "wake",
+            Event::Partial { .. } => "partial",
+            Event::Command { .. } => "command",
+            Event::Intent { .. } => "intent",
+            Event::State { .. } => "state",
+            Event::Error { .. } => "error",
+            Event::Device { .. } => "device",
+            Event::Model { .. } => "model",
+            Event::Swap => "swap",
+            Event::Level { .. } => "level",
+            Event::AudioWarning { .. } => "audio-warning",
+            Event::MicStalled { .. } => "mic-stalled",
+            Event::MicDead { .. } => "mic-dead",
+            Event::MicRecovered { .. } => "mic-recovered",
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut v = match self {
+            Event::Wake { phrase, forced, language } => {
+                json!({ "phrase": phrase, "forced": forced, "language": language })
+            }
+            Event::Partial { text, language } => json!({ "text": text, "language": language }),
+            Event::Command { text, normalized, intent, speaker, language } => {
+                let mut v = json!({ "text": text, "language": language });
+                if let Some(normalized) = normalized {
+                    v["normalized"] = json!(normalized);
+                }
+                if let Some(intent) = intent {
+                    v["intent"] = intent.clone();
+                }
+                if let Some(speaker) = speaker {
+                    v["speaker"] = speaker.clone();
+                }
+                v
+            }
+            Event::Intent { name, slots, text } => {
+                let slots: serde_json::Map<String, Value> =
+                    slots.iter().map(|(k, v)| (k.clone(), json!(v))).collect();
+                json!({ "name": name, "slots": slots, "text": text })
+            }
+            Event::State { state } => json!({ "state": state }),
+            Event::Error { message } => json!({ "message": message }),
+            Event::Device { name } => json!({ "name": name }),
+            Event::Model { language, path } => json!({ "language": language, "path": path }),
+            Event::Swap => json!({}),
+            Event::Level { rms_db, peak_db, noise_floor_db, snr_db, clipped } => json!({
+                "rms_db": rms_db,
+                "peak_db": peak_db,
+                "noise_floor_db": noise_floor_db,
+                "snr_db": snr_db,
+                "clipped": clipped,
+            }),
+            Event::AudioWarning { warning, message, active } => {
+                json!({ "warning": warning, "message": message, "active": active })
+            }
+            Event::MicStalled { device, secs } | Event::MicDead { device, secs } => {
+                json!({ "device": device, "secs": secs })
+            }
+            Event::MicRecovered { device, problem } => json!({ "device": device, "problem": problem }),
+        };
+        v["type"] = json!(self.kind());
+        v["ts"] = json!(ts);
+        v
+    }
+}
+
+/// Fan-out of serialized events to any number of subscribers (websocket clients, etc.).
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<Arc<Value>>>>,
+    /// Threads delivering events elsewhere (webhooks, MQTT, ...), waited for by `close`.
+    outputs: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> Receiver<Arc<Value>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Registers a thread that exits once its subscription is disconnected.
+    pub fn track(&self, output: JoinHandle<()>) {
+        let mut outputs = self.outputs.lock().unwrap();
+        outputs.retain(|o| !o.is_finished());
+        outputs.push(output);
+    }
+
+    /// Disconnects every subscriber, so output threads deliver what they already have and
+    /// exit, and waits up to `timeout` for them. Returns how many are still running.
+    pub fn close(&self, timeout: Duration) -> usize {
+        self.subscribers.lock().unwrap().clear();
+        let outputs = std::mem::take(&mut *self.outputs.lock().unwrap());
+        let deadline = Instant::now() + timeout;
+        while outputs.iter().any(|o| !o.is_finished()) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let (finished, running): (Vec<_>, Vec<_>) = outputs.into_iter().partition(|o| o.is_finished());
+        for output in finished {
+            let _ = output.join();
+        }
+        running.len()
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.lock().unwrap().is_empty()
+    }
+
+    pub fn emit(&self, event: Event) {
+        let mut subs = self.subscribers.lock().unwrap();
+        if subs.is_empty() {
+            return;
+        }
+        let payload = Arc::new(event.to_json());
+        // Drop subscribers whose receiving end has gone away.
+        subs.retain(|tx| tx.send(payload.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_lets_outputs_finish_what_they_have() {
+        let bus = EventBus::default();
+        let rx = bus.subscribe();
+        let (seen_tx, seen) = mpsc::channel();
+        bus.track(std::thread::spawn(move || {
+            for event in rx {
+                std::thread::sleep(Duration::from_millis(20));
+                let _ = seen_tx.send(event["state"].as_str().unwrap_or_default().to_string());
+            }
+        }));
+        bus.emit(Event::State { state: "stopping" });
+        bus.emit(Event::State { state: "stopped" });
+        assert_eq!(bus.close(Duration::from_secs(2)), 0);
+        assert_eq!(seen.try_iter().collect::<Vec<_>>(), ["stopping", "stopped"]);
+        assert!(!bus.has_subscribers());
+    }
+
+    #[test]
+    fn warning_and_microphone_events_use_the_documented_names() {
+        let device = || "USB Mic".to_string();
+        let events = [
+            (Event::AudioWarning { warning: "clipping", message: String::new(), active: true }, "audio-warning"),
+            (Event::MicStalled { device: device(), secs: 3 }, "mic-stalled"),
+            (Event::MicDead { device: device(), secs: 30 }, "mic-dead"),
+            (Event::MicRecovered { device: device(), problem: "dead" }, "mic-recovered"),
+        ];
+        for (event, kind) in events {
+            assert_eq!(event.kind(), kind);
+            assert_eq!(event.to_json()["type"], kind);
+        }
+    }
+
+    #[test]
+    fn kinds_lists_every_variant() {
+        let text = || "x".to_string();
+        let every = [
+            Event::Wake { phrase: text(), forced: false, language: None },
+            Event::Partial { text: text(), language: text() },
+            Event::Command { text: text(), normalized: None, intent: None, speaker: None, language: text() },
+            Event::Intent { name: text(), slots: Vec::new(), text: text() },
+            Event::State { state: "listening" },
+            Event::Error { message: text() },
+            Event::Device { name: text() },
+            Event::Model { language: text(), path: text() },
+            Event::Swap,
+            Event::Level { rms_db: 0.0, peak_db: 0.0, noise_floor_db: 0.0, snr_db: 0.0, clipped: 0 },
+            Event::AudioWarning { warning: "clipping", message: text(), active: true },
+            Event::MicStalled { device: text(), secs: 3 },
+            Event::MicDead { device: text(), secs: 30 },
+            Event::MicRecovered { device: text(), problem: "dead" },
+        ];
+        let kinds: Vec<&str> = every.iter().map(Event::kind).collect();
+        assert_eq!(kinds, Event::KINDS);
+    }
+
+    #[test]
+    fn close_gives_up_after_the_timeout() {
+        let bus = EventBus::default();
+        let (_hold, stuck) = mpsc::channel::<()>();
+        bus.track(std::thread::spawn(move || {
+            let _ = stuck.recv_timeout(Duration::from_secs(5));
+        }));
+        let started = Instant::now();
+        assert_eq!(bus.close(Duration::from_millis(50)), 1);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::numbers::{parse_duration, parse_number, parse_time};
+use crate::rules::normalize;
+
+/// Intent definitions, loaded from a TOML file such as
+/// ```toml
+/// [entities]
+/// room = ["kitchen", "bedroom", "living room"]
+///
+/// [[intent]]
+/// name = "timer.set"
+/// patterns = ["set a timer for {length:duration}", "start a {length:duration} timer"]
+///
+/// [[intent]]
+/// name = "light.power"
+/// patterns = ["turn {state:onoff} [the] {room:room} lights", "turn [the] {room:room} lights {state:onoff}"]
+/// ```
+/// Slot types are `number`, `duration`, `time`, `onoff`, `text` or an entity list name;
+/// `[words]` are optional. Intents are tried in file order.
+pub struct Intents {
+    intents: Vec<IntentDef>,
+    entities: HashMap<String, Vec<(String, Vec<String>)>>,
+}
+
+struct IntentDef {
+    name: String,
+    patterns: Vec<Vec<Token>>,
+}
+
+enum Token {
+    Word(String),
+    Optional(Vec<String>),
+    Slot { name: String, kind: SlotKind },
+}
+
+#[derive(Clone)]
+enum SlotKind {
+    Text,
+    Number,
+    Duration,
+    Time,
+    OnOff,
+    Entity(String),
+}
+
+impl SlotKind {
+    fn name(&self) -> &str {
+        match self {
+            SlotKind::Text => "text",
+            SlotKind::Number => "number",
+            SlotKind::Duration => "duration",
+            SlotKind::Time => "time",
+            SlotKind::OnOff => "onoff",
+            SlotKind::Entity(name) => name,
+        }
+    }
+}
+
+impl Intents {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read intents {}: {}", path.display(), e))?;
+        let table: toml::Table = raw
+            .parse()
+            .map_err(|e| format!("Invalid intents {}: {}", path.display(), e))?;
+        Self::from_table(&table).map_err(|e| format!("Intents {}: {}", path.display(), e))
+    }
+
+    fn from_table(table: &toml::Table) -> Result<Self, String> {
+        let mut entities = HashMap::new();
+        if let Some(defs) = table.get("entities") {
+            let defs = defs.as_table().ok_or("[entities] must be a table")?;
+            for (name, values) in defs {
+                entities.insert(name.clone(), parse_entity(name, values)?);
+            }
+        }
+
+        let mut intents = Vec::new();
+        let defs = table
+            .get("intent")
+            .and_then(|i| i.as_array())
+            .ok_or("needs at least one [[intent]]")?;
+        for def in defs {
+            let name = def
+                .get("name")
+                .and_then(|n| n.as_str())
+                .ok_or("every [[intent]] needs a name")?;
+            let patterns = def
+                .get("patterns")
+                .and_then(|p| p.as_array())
+                .ok_or_else(|| format!("intent '{}' needs a patterns list", name))?
+                .iter()
+                .map(|p| {
+                    let p = p.as_str().ok_or_else(|| format!("intent '{}': patterns must be strings", name))?;
+                    parse_pattern(p, &entities).map_err(|e| format!("intent '{}': {}", name, e))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            intents.push(IntentDef { name: name.to_string(), patterns });
+        }
+        Ok(Intents { intents, entities })
+    }
+
+    pub fn count(&self) -> usize {
+        self.intents.len()
+    }
+
+    /// The first intent with a pattern matching all of `text`, as
+    /// `{"name": ..., "slots": {"<slot>": {"type", "value", "text"}}}`.
+    pub fn parse(&self, text: &str) -> Option<Value> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        for intent in &self.intents {
+            for pattern in &intent.patterns {
+                let mut slots = Vec::new();
+                if self.match_tokens(pattern, &words, &mut slots) {
+                    let slots: serde_json::Map<String, Value> = slots.into_iter().collect();
+                    return Some(json!({ "name": intent.name, "slots": slots }));
+                }
+            }
+        }
+        None
+    }
+
+    // Backtracking match; typed slots try the longest span first, free text the shortest.
+    fn match_tokens(&self, tokens: &[Token], words: &[&str], slots: &mut Vec<(String, Value)>) -> bool {
+        let Some((token, rest)) = tokens.split_first() else {
+            return words.is_empty();
+        };
+        match token {
+            Token::Word(w) => words.first() == Some(&w.as_str()) && self.match_tokens(rest, &words[1..], slots),
+            Token::Optional(opt) => {
+                let n = opt.len();
+                (words.len() >= n
+                    && words[..n].iter().zip(opt).all(|(a, b)| *a == b)
+                    && self.match_tokens(rest, &words[n..], slots))
+                    || self.match_tokens(rest, words, slots)
+            }
+            Token::Slot { name, kind } => {
+                let ends: Vec<usize> = match kind {
+                    SlotKind::Text => (1..=words.len()).collect(),
+                    _ => (1..=words.len()).rev().collect(),
+                };
+                for end in ends {
+                    let span = &words[..end];
+                    let Some(value) = self.slot_value(kind, span) else {
+                        continue;
+                    };
+                    slots.push((
+                        name.clone(),
+                        json!({ "type": kind.name(), "value": value, "text": span.join(" ") }),
+                    ));
+                    if self.match_tokens(rest, &words[end..], slots) {
+                        return true;
+                    }
+                    slots.pop();
+                }
+                false
+            }
+        }
+    }
+
+    fn slot_value(&self, kind: &SlotKind, span: &[&str]) -> Option<Value> {
+        match kind {
+            SlotKind::Text => Some(json!(span.join(" "))),
+            SlotKind::Number => parse_number(span).map(number_json),
+            SlotKind::Duration => parse_duration(span).map(number_json),
+            SlotKind::Time => parse_time(span).map(|(h, m)| json!(format!("{:02}:{:02}", h, m))),
+            SlotKind::OnOff => match span {
+                ["on"] => Some(json!(true)),
+                ["off"] => Some(json!(false)),
+                _ => None,
+            },
+            SlotKind::Entity(list) => {
+                let phrase = span.join(" ");
+                self.entities.get(list)?.iter().find_map(|(value, synonyms)| {
+                    synonyms.contains(&phrase).then(|| json!(value))
+                })
+            }
+        }
+    }
+}
+
+// An entity is either a list of values or a table of `value = [synonyms]`.
+fn parse_entity(name: &str, values: &toml::Value) -> Result<Vec<(String, Vec<String>)>, String> {
+    let phrase = |v: &toml::Value| {
+        v.as_str()
+            .map(normalize)
+            .ok_or_else(|| format!("entity '{}' values must be strings", name))
+    };
+    match values {
+        toml::Value::Array(items) => items
+            .iter()
+            .map(|v| phrase(v).map(|p| (p.clone(), vec![p])))
+            .collect(),
+        toml::Value::Table(table) => table
+            .iter()
+            .map(|(value, synonyms)| {
+                let mut all = vec![normalize(value)];
+                for s in synonyms.as_array().ok_or_else(|| format!("entity '{}.{}' must be a list", name, value))? {
+                    all.push(phrase(s)?);
+                }
+                Ok((value.clone(), all))
+            })
+            .collect(),
+        _ => Err(format!("entity '{}' must be a list or table", name)),
+    }
+}
+
+fn parse_pattern(pattern: &str, entities: &HashMap<String, Vec<(String, Vec<String>)>>) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern.trim();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('{') {
+            let end = after.find('}').ok_or("unclosed '{'")?;
+            let (name, kind) = match after[..end].split_once(':') {
+                Some((n, k)) => (n.trim(), k.trim()),
+                None => (after[..end].trim(), "text"),
+            };
+            let kind = match kind {
+                "text" => SlotKind::Text,
+                "number" => SlotKind::Number,
+                "duration" => SlotKind::Duration,
+                "time" => SlotKind::Time,
+                "onoff" => SlotKind::OnOff,
+                other if entities.contains_key(other) => SlotKind::Entity(other.to_string()),
+                other => return Err(format!("unknown slot type '{}'", other)),
+            };
+            tokens.push(Token::Slot { name: name.to_string(), kind });
+            rest = &after[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or("unclosed '['")?;
+            tokens.push(Token::Optional(after[..end].split_whitespace().map(str::to_lowercase).collect()));
+            rest = &after[end + 1..];
+        } else {
+            let end = rest.find(|c: char| c.is_whitespace() || c == '{' || c == '[').unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..end].to_lowercase()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn number_json(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        json!(n as i64)
+    } else {
+        json!(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intents() -> Intents {
+        let table: toml::Table = r#"
+            [entities]
+            room = ["kitchen", "living room"]
+            colour = { red = ["crimson"], blue = [] }
+
+            [[intent]]
+            name = "timer.set"
+            patterns = ["set a timer for {length:duration}", "start a {length:duration} timer"]
+
+            [[intent]]
+            name = "light.power"
+            patterns = ["turn {state:onoff} [the] {room:room} lights"]
+
+            [[intent]]
+            name = "light.colour"
+            patterns = ["make it {colour:colour}"]
+
+            [[intent]]
+            name = "alarm.set"
+            patterns = ["wake me at {at:time}"]
+
+            [[intent]]
+            name = "volume.set"
+            patterns = ["volume {level:number}"]
+
+            [[intent]]
+            name = "note"
+            patterns = ["note {body} please"]
+        "#
+        .parse()
+        .unwrap();
+        Intents::from_table(&table).unwrap()
+    }
+
+    #[test]
+    fn slots() {
+        let intents = intents();
+        for (text, name, slot, value) in [
+            ("set a timer for five minutes", "timer.set", "length", json!(300)),
+            ("start a two point five minutes timer", "timer.set", "length", json!(150)),
+            ("turn on the living room lights", "light.power", "room", json!("living room")),
+            ("turn off kitchen lights", "light.power", "state", json!(false)),
+            ("make it crimson", "light.colour", "colour", json!("red")),
+            ("make it blue", "light.colour", "colour", json!("blue")),
+            ("wake me at half past six", "alarm.set", "at", json!("06:30")),
+            ("volume two point five", "volume.set", "level", json!(2.5)),
+            ("volume forty", "volume.set", "level", json!(40)),
+            ("note buy milk please", "note", "body", json!("buy milk")),
+        ] {
+            let parsed = intents.parse(text).unwrap_or_else(|| panic!("no intent for {}", text));
+            assert_eq!(parsed["name"], name, "{}", text);
+            assert_eq!(parsed["slots"][slot]["value"], value, "{}", text);
+        }
+        let parsed = intents.parse("turn on the living room lights").unwrap();
+        assert_eq!(parsed["slots"]["room"]["text"], "living room");
+        assert_eq!(parsed["slots"]["state"]["type"], "onoff");
+    }
+
+    #[test]
+    fn no_match() {
+        let intents = intents();
+        for text in [
+            "",
+            "set a timer for",
+            "set a timer for lunch",
+            "turn on the garage lights",
+            "make it green",
+            "wake me at thirteen pm",
+            "volume two thousand one million",
+            "note please",
+        ] {
+            assert!(intents.parse(text).is_none(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn bad_definitions() {
+        for (raw, error) in [
+            ("", "needs at least one [[intent]]"),
+            ("[[intent]]\npatterns = []", "every [[intent]] needs a name"),
+            ("[[intent]]\nname = \"a\"\npatterns = [\"x {y:colour}\"]", "unknown slot type 'colour'"),
+            ("[[intent]]\nname = \"a\"\npatterns = [\"x {y\"]", "unclosed '{'"),
+        ] {
+            let table: toml::Table = raw.parse().unwrap();
+            let err = Intents::from_table(&table).err().unwrap();
+            assert!(err.contains(error), "{}: {}", raw, err);
+        }
+    }
+}
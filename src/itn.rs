@@ -0,0 +1,233 @@
+//! Inverse text normalization: "twenty five percent" -> "25%", "half past seven" -> "07:30".
+
+use crate::arg_value;
+use crate::numbers::{parse_decimal, parse_integer, parse_time, unit_value};
+
+/// Per-language rewrite rules. The driver walks the words left to right and asks
+/// for the longest span starting at each position that the rules can rewrite.
+pub trait LanguageRules: Send + Sync {
+    /// Written form of `words` if the whole span is one normalizable unit.
+    fn rewrite(&self, words: &[&str]) -> Option<String>;
+}
+
+/// Rules for a language code such as `en` or `en-us`.
+pub fn rules_for(language: &str) -> Option<Box<dyn LanguageRules>> {
+    let base = language.split(['-', '_']).next().unwrap_or("").to_lowercase();
+    match base.as_str() {
+        "en" => Some(Box::new(English)),
+        _ => None,
+    }
+}
+
+/// Rules for the primary model: `--itn <language>` picks them explicitly, `--itn off`
+/// disables normalization, and by default `--language` is used when it has rules.
+pub fn rules_from_args(args: &[(String, String)]) -> Result<Option<Box<dyn LanguageRules>>, String> {
+    let language = arg_value(args, "--language").unwrap_or("en").to_lowercase();
+    match arg_value(args, "--itn") {
+        Some("off" | "none") => Ok(None),
+        Some(explicit) => rules_for(explicit)
+            .map(Some)
+            .ok_or_else(|| format!("No text normalization rules for language '{}' (use --itn off)", explicit)),
+        None => Ok(rules_for(&language)),
+    }
+}
+
+pub fn normalize(rules: &dyn LanguageRules, text: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut out: Vec<String> = Vec::with_capacity(words.len());
+    let mut i = 0;
+    while i < words.len() {
+        let rewritten = (i + 1..=words.len())
+            .rev()
+            .find_map(|end| rules.rewrite(&words[i..end]).map(|w| (end, w)));
+        match rewritten {
+            Some((end, written)) => {
+                out.push(written);
+                i = end;
+            }
+            None => {
+                out.push(words[i].to_string());
+                i += 1;
+            }
+        }
+    }
+    out.join(" ")
+}
+
+pub struct English;
+
+impl LanguageRules for English {
+    fn rewrite(&self, words: &[&str]) -> Option<String> {
+        if let Some(time) = english_time(words) {
+            return Some(time);
+        }
+        if let [number @ .., "percent"] = words {
+            return parse_decimal(number).map(|n| format!("{}%", n));
+        }
+        if let Some(ordinal) = english_ordinal(words) {
+            return Some(ordinal);
+        }
+        if let Some(digits) = english_digits(words) {
+            return Some(digits);
+        }
+        match words {
+            // "one" and "a" are more often words than numbers when they stand alone.
+            ["one"] | ["a"] | ["an"] => None,
+            _ => parse_decimal(words),
+        }
+    }
+}
+
+// Numbers read digit by digit ("one two three" -> "123", "four oh one" -> "401"), which
+// don't parse as one number.
+fn english_digits(words: &[&str]) -> Option<String> {
+    if words.len() < 2 || words[0] == "oh" {
+        return None;
+    }
+    words
+        .iter()
+        .map(|w| match unit_value(w) {
+            Some(d) if d < 10 => Some(char::from(b'0' + d as u8)),
+            None if *w == "oh" => Some('0'),
+            _ => None,
+        })
+        .collect()
+}
+
+// Only spans that are unmistakably clock times; bare "seven thirty" stays as numbers.
+fn english_time(words: &[&str]) -> Option<String> {
+    let marked = words.iter().any(|w| {
+        matches!(*w, "past" | "o'clock" | "clock" | "am" | "pm" | "a.m." | "p.m.")
+    }) || matches!(words, [.., "a" | "p", "m"])
+        || (words.contains(&"to") && words.iter().any(|w| matches!(*w, "quarter" | "minutes" | "minute")));
+    if !marked {
+        return None;
+    }
+    let (h, m) = parse_time(words)?;
+    Some(format!("{:02}:{:02}", h, m))
+}
+
+const ORDINALS: &[(&str, &str)] = &[
+    ("first", "one"),
+    ("second", "two"),
+    ("third", "three"),
+    ("fourth", "four"),
+    ("fifth", "five"),
+    ("sixth", "six"),
+    ("seventh", "seven"),
+    ("eighth", "eight"),
+    ("ninth", "nine"),
+    ("tenth", "ten"),
+    ("eleventh", "eleven"),
+    ("twelfth", "twelve"),
+    ("thirteenth", "thirteen"),
+    ("fourteenth", "fourteen"),
+    ("fifteenth", "fifteen"),
+    ("sixteenth", "sixteen"),
+    ("seventeenth", "seventeen"),
+    ("eighteenth", "eighteen"),
+    ("nineteenth", "nineteen"),
+    ("twentieth", "twenty"),
+    ("thirtieth", "thirty"),
+    ("fortieth", "forty"),
+    ("fiftieth", "fifty"),
+    ("sixtieth", "sixty"),
+    ("seventieth", "seventy"),
+    ("eightieth", "eighty"),
+    ("ninetieth", "ninety"),
+    ("hundredth", "hundred"),
+    ("thousandth", "thousand"),
+];
+
+// "twenty first" -> "21st": swap the last word for its cardinal and parse the rest.
+fn english_ordinal(words: &[&str]) -> Option<String> {
+    let (last, rest) = words.split_last()?;
+    let cardinal = ORDINALS.iter().find(|(o, _)| o == last)?.1;
+    // A lone "second" is usually the unit of time.
+    if *last == "second" && rest.is_empty() {
+        return None;
+    }
+    let mut cardinal_words = rest.to_vec();
+    if rest.is_empty() && matches!(cardinal, "hundred" | "thousand") {
+        cardinal_words.push("one");
+    }
+    cardinal_words.push(cardinal);
+    let n = parse_integer(&cardinal_words)?;
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    Some(format!("{}{}", n, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_args::args;
+
+    fn english(text: &str) -> String {
+        normalize(&English, text)
+    }
+
+    #[test]
+    fn writes_numbers_percentages_and_ordinals() {
+        let cases = [
+            ("set the volume to twenty five percent", "set the volume to 25%"),
+            ("two point five percent", "2.5%"),
+            ("add two hundred and five grams", "add 205 grams"),
+            ("the twenty first of may", "the 21st of may"),
+            ("wake me on the third", "wake me on the 3rd"),
+            ("the eleventh floor", "the 11th floor"),
+            ("wait two seconds", "wait 2 seconds"),
+            ("give me a second", "give me a second"),
+            ("turn on the lights", "turn on the lights"),
+        ];
+        for (spoken, written) in cases {
+            assert_eq!(english(spoken), written, "{}", spoken);
+        }
+    }
+
+    #[test]
+    fn writes_clock_times() {
+        let cases = [
+            ("half past seven", "07:30"),
+            ("seven thirty pm", "19:30"),
+            ("wake me at seven thirty a m", "wake me at 07:30"),
+            ("quarter to eight", "07:45"),
+            ("ten o'clock", "10:00"),
+            // No marker: could be anything.
+            ("seven thirty", "7 30"),
+        ];
+        for (spoken, written) in cases {
+            assert_eq!(english(spoken), written, "{}", spoken);
+        }
+    }
+
+    #[test]
+    fn reads_digit_sequences_as_one_number() {
+        let cases = [
+            ("one two three", "123"),
+            ("call five five five one two one two", "call 5551212"),
+            ("room four oh one", "room 401"),
+            // A lone "one" is a word, not a digit.
+            ("just one thing", "just one thing"),
+            ("one hundred", "100"),
+        ];
+        for (spoken, written) in cases {
+            assert_eq!(english(spoken), written, "{}", spoken);
+        }
+    }
+
+    #[test]
+    fn picks_rules_from_the_options() {
+        assert!(rules_from_args(&args(&[])).unwrap().is_some());
+        assert!(rules_from_args(&args(&[("--language", "en-us")])).unwrap().is_some());
+        assert!(rules_from_args(&args(&[("--language", "de")])).unwrap().is_none());
+        assert!(rules_from_args(&args(&[("--itn", "off")])).unwrap().is_none());
+        assert!(rules_from_args(&args(&[("--language", "de"), ("--itn", "en")])).unwrap().is_some());
+        assert!(rules_from_args(&args(&[("--itn", "fr")])).is_err());
+    }
+}
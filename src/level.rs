@@ -0,0 +1,428 @@
+//! Input level metering on the raw microphone signal: RMS and peak per report interval, a
+//! noise floor estimate, and warnings for clipping, silence and a poor signal-to-noise ratio.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::dsp::GATE_THRESHOLDS_DB;
+use crate::{arg_value, capture};
+
+const WINDOW_MS: usize = 100;
+// Level history the noise floor and speech level are taken from.
+const HISTORY_WINDOWS: usize = 100;
+const CLIP_LEVEL: i32 = 32700;
+// Clipped samples that make a 100 ms window count as clipping.
+const CLIP_SAMPLES: usize = 3;
+// Clipping in this many of the last ten seconds is reported.
+const CLIP_SECONDS: usize = 3;
+const SILENCE_DB: f32 = -80.0;
+const SILENCE_AFTER: Duration = Duration::from_secs(10);
+// Background this loud, with speech this little above it, for this long, is reported.
+const NOISY_FLOOR_DB: f32 = -50.0;
+const LOW_SNR_DB: f32 = 6.0;
+const LOW_SNR_AFTER: Duration = Duration::from_secs(30);
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+
+pub enum Reading {
+    Level {
+        rms_db: f32,
+        peak_db: f32,
+        noise_floor_db: f32,
+        snr_db: f32,
+        clipped: usize,
+    },
+    /// `active` is false once the condition has cleared.
+    Warning {
+        kind: &'static str,
+        message: String,
+        active: bool,
+    },
+}
+
+/// `--level-interval-ms` between level events (default 1000; 0 turns them off, warnings stay).
+pub fn interval_from_args(args: &[(String, String)]) -> Result<Duration, String> {
+    match arg_value(args, "--level-interval-ms") {
+        Some(v) => v
+            .parse::<u64>()
+            .map(Duration::from_millis)
+            .map_err(|_| format!("Invalid value for --level-interval-ms: '{}'", v)),
+        None => Ok(Duration::from_millis(DEFAULT_INTERVAL_MS)),
+    }
+}
+
+pub struct Meter {
+    window_len: usize,
+    interval_windows: usize,
+    window: Accumulator,
+    interval: Accumulator,
+    interval_count: usize,
+    levels: VecDeque<f32>,
+    /// Windows into the current second.
+    second_windows: usize,
+    /// Whether each of the last ten seconds had clipping.
+    clipped_seconds: VecDeque<bool>,
+    second_clipped: bool,
+    silent_windows: usize,
+    noisy_windows: usize,
+    active: HashSet<&'static str>,
+}
+
+#[derive(Default)]
+struct Accumulator {
+    sum_sq: f64,
+    n: usize,
+    peak: i32,
+    clipped: usize,
+}
+
+impl Accumulator {
+    fn add(&mut self, s: i16) {
+        let a = (s as i32).abs();
+        self.sum_sq += (s as f64) * (s as f64);
+        self.n += 1;
+        self.peak = self.peak.max(a);
+        if a >= CLIP_LEVEL {
+            self.clipped += 1;
+        }
+    }
+
+    fn rms_db(&self) -> f32 {
+        let power = self.sum_sq / self.n.max(1) as f64 / (32768.0 * 32768.0);
+        (10.0 * power.max(1e-12).log10()) as f32
+    }
+
+    fn peak_db(&self) -> f32 {
+        20.0 * (self.peak.max(1) as f32 / 32768.0).log10()
+    }
+}
+
+impl Meter {
+    pub fn new(sample_rate: u32, interval: Duration) -> Self {
+        let window_len = (sample_rate as usize * WINDOW_MS / 1000).max(1);
+        Meter {
+            window_len,
+            interval_windows: (interval.as_millis() as usize).div_ceil(WINDOW_MS),
+            window: Accumulator::default(),
+            interval: Accumulator::default(),
+            interval_count: 0,
+            levels: VecDeque::with_capacity(HISTORY_WINDOWS),
+            second_windows: 0,
+            clipped_seconds: VecDeque::with_capacity(10),
+            second_clipped: false,
+            silent_windows: 0,
+            noisy_windows: 0,
+            active: HashSet::new(),
+        }
+    }
+
+    pub fn push(&mut self, pcm: &[i16]) -> Vec<Reading> {
+        let mut out = Vec::new();
+        for &s in pcm {
+            self.window.add(s);
+            self.interval.add(s);
+            if self.window.n >= self.window_len {
+                self.end_window(&mut out);
+            }
+        }
+        out
+    }
+
+    /// Window boundaries follow the rate; the level history carries over.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.window_len = (sample_rate as usize * WINDOW_MS / 1000).max(1);
+        self.window = Accumulator::default();
+    }
+
+    pub fn active_warnings(&self) -> Vec<&'static str> {
+        let mut active: Vec<&'static str> = self.active.iter().copied().collect();
+        active.sort_unstable();
+        active
+    }
+
+    /// 10th percentile of recent window levels.
+    pub fn noise_floor_db(&self) -> f32 {
+        percentile(self.levels.iter().copied().collect(), 10.0)
+    }
+
+    fn end_window(&mut self, out: &mut Vec<Reading>) {
+        let window = std::mem::take(&mut self.window);
+        let db = window.rms_db();
+        if self.levels.len() == HISTORY_WINDOWS {
+            self.levels.pop_front();
+        }
+        self.levels.push_back(db);
+        let floor = self.noise_floor_db();
+        let snr = percentile(self.levels.iter().copied().collect(), 95.0) - floor;
+
+        self.silent_windows = if db < SILENCE_DB { self.silent_windows + 1 } else { 0 };
+        let silent = self.silent_windows * WINDOW_MS >= SILENCE_AFTER.as_millis() as usize;
+        let message = if window.peak == 0 {
+            "Input is digital silence; the microphone may be muted or disconnected".to_string()
+        } else {
+            format!("Input has been below {} dBFS for {} s; is the microphone muted?", SILENCE_DB, SILENCE_AFTER.as_secs())
+        };
+        self.update("silence", silent, message, out);
+
+        self.second_clipped |= window.clipped >= CLIP_SAMPLES;
+        self.second_windows += 1;
+        if self.second_windows == 1000 / WINDOW_MS {
+            self.second_windows = 0;
+            if self.clipped_seconds.len() == 10 {
+                self.clipped_seconds.pop_front();
+            }
+            self.clipped_seconds.push_back(std::mem::take(&mut self.second_clipped));
+            let clipping = self.clipped_seconds.iter().filter(|c| **c).count();
+            if clipping >= CLIP_SECONDS || clipping == 0 {
+                let message = format!("Input clipped in {} of the last 10 s; lower the microphone gain", clipping);
+                self.update("clipping", clipping >= CLIP_SECONDS, message, out);
+            }
+        }
+
+        let noisy = self.levels.len() == HISTORY_WINDOWS && floor > NOISY_FLOOR_DB && snr < LOW_SNR_DB;
+        self.noisy_windows = if noisy { self.noisy_windows + 1 } else { 0 };
+        let message = format!(
+            "Background noise at {:.0} dBFS leaves speech only {:.0} dB above it; move the microphone closer or try --denoise true",
+            floor, snr
+        );
+        self.update(
+            "low_snr",
+            self.noisy_windows * WINDOW_MS >= LOW_SNR_AFTER.as_millis() as usize,
+            message,
+            out,
+        );
+
+        self.interval_count += 1;
+        if self.interval_windows > 0 && self.interval_count >= self.interval_windows {
+            let interval = std::mem::take(&mut self.interval);
+            self.interval_count = 0;
+            out.push(Reading::Level {
+                rms_db: round(interval.rms_db()),
+                peak_db: round(interval.peak_db()),
+                noise_floor_db: round(floor),
+                snr_db: round(snr),
+                clipped: interval.clipped,
+            });
+        }
+    }
+
+    // Reports a condition when it starts and again when it clears.
+    fn update(&mut self, kind: &'static str, on: bool, message: String, out: &mut Vec<Reading>) {
+        if on && self.active.insert(kind) {
+            out.push(Reading::Warning { kind, message, active: true });
+        } else if !on && self.active.remove(kind) {
+            out.push(Reading::Warning { kind, message: format!("{} cleared", kind), active: false });
+        }
+    }
+}
+
+fn round(db: f32) -> f32 {
+    (db * 10.0).round() / 10.0
+}
+
+fn percentile(mut levels: Vec<f32>, p: f32) -> f32 {
+    if levels.is_empty() {
+        return -120.0;
+    }
+    levels.sort_by(f32::total_cmp);
+    let rank = ((p / 100.0) * (levels.len() - 1) as f32).round() as usize;
+    levels[rank]
+}
+
+fn window_levels(pcm: &[i16], sample_rate: u32) -> Vec<f32> {
+    let len = (sample_rate as usize * WINDOW_MS / 1000).max(1);
+    pcm.chunks(len)
+        .filter(|c| c.len() == len)
+        .map(|c| {
+            let mut acc = Accumulator::default();
+            c.iter().for_each(|&s| acc.add(s));
+            acc.rms_db()
+        })
+        .collect()
+}
+
+/// `calibrate [--calibrate-quiet-secs N] [--calibrate-secs N] [--format text|json]`
+///
+/// Records the room while quiet and then while speaking, and recommends gain changes and
+/// conditioning options from the noise floor, speech level and peaks. Prompts go to stderr so
+/// stdout only carries the report.
+pub fn calibrate(args: &[(String, String)]) -> Result<(), String> {
+    let secs = |key: &str, default: u64| -> Result<Duration, String> {
+        match arg_value(args, key) {
+            Some(v) => v
+                .parse::<u64>()
+                .ok()
+                .filter(|s| (1..=120).contains(s))
+                .map(Duration::from_secs)
+                .ok_or_else(|| format!("Invalid value for {}: '{}' (expected 1 to 120 seconds)", key, v)),
+            None => Ok(Duration::from_secs(default)),
+        }
+    };
+    let quiet_len = secs("--calibrate-quiet-secs", 5)?;
+    let speech_len = secs("--calibrate-secs", 8)?;
+    let format = arg_value(args, "--format").unwrap_or("text");
+    if format != "text" && format != "json" {
+        return Err(format!("Unknown --format '{}' (expected text or json)", format));
+    }
+
+    let device = capture::input_device(args)?;
+    eprintln!("Calibrating {}", cpal::traits::DeviceTrait::name(&device).unwrap_or_default());
+    eprintln!("Stay quiet for {} s...", quiet_len.as_secs());
+    let quiet = capture::record(&device, quiet_len)?;
+    eprintln!("Now speak a few commands at your usual distance for {} s...", speech_len.as_secs());
+    let speech = capture::record(&device, speech_len)?;
+
+    let quiet_peak = quiet.samples.iter().map(|s| (*s as i32).abs()).max().unwrap_or(0);
+    let speech_peak = speech.samples.iter().map(|s| (*s as i32).abs()).max().unwrap_or(0);
+    if quiet_peak == 0 && speech_peak == 0 {
+        return Err("The input is digital silence: the microphone is muted or the wrong --device is selected".to_string());
+    }
+    let floor = percentile(window_levels(&quiet.samples, quiet.sample_rate), 50.0);
+    let speech_db = percentile(window_levels(&speech.samples, speech.sample_rate), 90.0);
+    let peak_db = 20.0 * (speech_peak.max(1) as f32 / 32768.0).log10();
+    let clipped = speech.samples.iter().filter(|s| (**s as i32).abs() >= CLIP_LEVEL).count();
+    let dc_offset = quiet.samples.iter().map(|s| *s as f64).sum::<f64>() / quiet.samples.len().max(1) as f64 / 32768.0;
+    let snr = speech_db - floor;
+
+    let mut advice = Vec::new();
+    let mut flags = Vec::new();
+    if clipped > 0 || peak_db > -1.0 {
+        advice.push(format!(
+            "Speech peaks at {:.1} dBFS ({} clipped samples): lower the input gain by about {:.0} dB",
+            peak_db, clipped, peak_db + 6.0
+        ));
+    } else if speech_db < -40.0 {
+        advice.push(format!(
+            "Speech is quiet at {:.0} dBFS: raise the input gain by about {:.0} dB, or use AGC",
+            speech_db,
+            -20.0 - speech_db
+        ));
+        flags.push("--agc true".to_string());
+    } else {
+        advice.push(format!("Input gain looks fine (speech around {:.0} dBFS, peaks {:.1} dBFS)", speech_db, peak_db));
+    }
+    if snr < 10.0 {
+        advice.push(format!(
+            "Speech is only {:.0} dB above the background: move the microphone closer or reduce noise",
+            snr
+        ));
+    }
+    if floor > -55.0 {
+        advice.push(format!("The background is noisy ({:.0} dBFS): noise suppression should help", floor));
+        flags.push("--denoise true".to_string());
+    }
+    // A silent room still gets the lowest threshold the gate takes; a very loud one gets none.
+    let gate = (floor + 6.0).round().max(*GATE_THRESHOLDS_DB.start());
+    if snr >= 15.0 && GATE_THRESHOLDS_DB.contains(&gate) {
+        advice.push(format!("A noise gate just above the background ({:.0} dBFS) is safe", gate));
+        flags.push(format!("--noise-gate {}", gate));
+    }
+    if dc_offset.abs() > 0.005 {
+        advice.push(format!("The input has a DC offset of {:.3}; remove it before recognition", dc_offset));
+        flags.push("--dc-block true".to_string());
+    }
+
+    if format == "json" {
+        let report = json!({
+            "noise_floor_db": round(floor),
+            "speech_db": round(speech_db),
+            "peak_db": round(peak_db),
+            "snr_db": round(snr),
+            "clipped": clipped,
+            "dc_offset": dc_offset,
+            "recommendations": advice,
+            "flags": flags,
+        });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+        return Ok(());
+    }
+    println!();
+    println!("Noise floor:   {:.1} dBFS", floor);
+    println!("Speech level:  {:.1} dBFS (peak {:.1} dBFS)", speech_db, peak_db);
+    println!("SNR:           {:.1} dB", snr);
+    println!();
+    for line in &advice {
+        println!("- {}", line);
+    }
+    if !flags.is_empty() {
+        println!();
+        println!("Suggested flags: {}", flags.join(" "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 100 samples per 100 ms window keeps the tests quick.
+    const RATE: u32 = 1000;
+
+    fn square(amplitude: i16, secs: usize) -> Vec<i16> {
+        (0..secs * RATE as usize).map(|i| if i % 2 == 0 { amplitude } else { -amplitude }).collect()
+    }
+
+    // Warnings raised (`true`) and cleared (`false`) while feeding `secs` of a square wave.
+    fn warnings(meter: &mut Meter, amplitude: i16, secs: usize) -> Vec<(&'static str, bool)> {
+        meter
+            .push(&square(amplitude, secs))
+            .into_iter()
+            .filter_map(|r| match r {
+                Reading::Warning { kind, active, .. } => Some((kind, active)),
+                Reading::Level { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_levels_every_interval() {
+        let mut meter = Meter::new(RATE, Duration::from_millis(500));
+        let levels: Vec<(f32, f32)> = meter
+            .push(&square(16384, 2))
+            .into_iter()
+            .filter_map(|r| match r {
+                Reading::Level { rms_db, peak_db, .. } => Some((rms_db, peak_db)),
+                Reading::Warning { .. } => None,
+            })
+            .collect();
+        assert_eq!(levels, vec![(-6.0, -6.0); 4]);
+        let mut quiet = Meter::new(RATE, Duration::ZERO);
+        assert!(quiet.push(&square(16384, 2)).is_empty());
+    }
+
+    #[test]
+    fn warns_about_silence_after_ten_seconds_and_clears_on_sound() {
+        let mut meter = Meter::new(RATE, Duration::ZERO);
+        assert!(warnings(&mut meter, 0, 9).is_empty());
+        let mut readings = meter.push(&square(0, 1));
+        assert!(matches!(
+            readings.pop(),
+            Some(Reading::Warning { kind: "silence", active: true, message }) if message.contains("digital silence")
+        ));
+        assert_eq!(meter.active_warnings(), vec!["silence"]);
+        assert!(warnings(&mut meter, 0, 5).is_empty());
+        assert_eq!(warnings(&mut meter, 3000, 1), vec![("silence", false)]);
+        assert!(meter.active_warnings().is_empty());
+    }
+
+    #[test]
+    fn clipping_needs_three_seconds_to_start_and_ten_clean_ones_to_clear() {
+        let mut meter = Meter::new(RATE, Duration::ZERO);
+        assert!(warnings(&mut meter, i16::MAX, 2).is_empty());
+        assert_eq!(warnings(&mut meter, i16::MAX, 1), vec![("clipping", true)]);
+        // One clipped second is still in the last ten; that is neither on nor clear.
+        assert!(warnings(&mut meter, 8000, 9).is_empty());
+        assert_eq!(warnings(&mut meter, 8000, 1), vec![("clipping", false)]);
+    }
+
+    #[test]
+    fn low_snr_needs_thirty_seconds_of_loud_flat_background() {
+        let mut meter = Meter::new(RATE, Duration::ZERO);
+        // About -40 dBFS with nothing above it; the history fills in the first ten seconds.
+        assert!(warnings(&mut meter, 328, 39).is_empty());
+        assert_eq!(warnings(&mut meter, 328, 1), vec![("low_snr", true)]);
+        assert!(meter.noise_floor_db() > NOISY_FLOOR_DB);
+        assert_eq!(warnings(&mut meter, 0, 2), vec![("low_snr", false)]);
+    }
+}
@@ -1,18 +1,118 @@
+mod aec;
+mod capture;
+mod check_model;
+mod config;
+mod control;
+mod custom_wake;
+mod denoise;
+mod dsp;
+mod evaluate;
+mod events;
+mod intents;
+mod itn;
+mod level;
+mod mfcc;
+mod model_watch;
+mod models;
+mod mqtt;
+mod numbers;
+mod recorder;
+mod rules;
+#[cfg(unix)]
+mod signals;
+mod speaker;
+#[cfg(unix)]
+mod systemd;
+#[cfg(test)]
+mod test_args;
+#[cfg(test)]
+mod test_dirs;
+#[cfg(test)]
+mod test_signals;
+mod transcribe;
+#[cfg(unix)]
+mod unix_socket;
+mod wake_verify;
+mod wake_vocab;
+mod watchdog;
+mod wav;
+mod webhook;
+mod websocket;
+
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Host, SampleFormat, Stream, StreamConfig};
+use cpal::{Device, Host, SampleFormat, Stream, StreamConfig, SupportedStreamConfig};
 use std::env;
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError, TryRecvError};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use vosk::{DecodingState, Model, Recognizer};
+use vosk::{DecodingState, Model, Recognizer, SpeakerModel};
+
+use aec::{Aec, AecConfig};
+use control::Control;
+use custom_wake::{CustomWake, CustomWakeConfig, Detector, Templates};
+use dsp::{Chain, DspConfig};
+use events::{Event, EventBus};
+use intents::Intents;
+use level::{Meter, Reading};
+use mqtt::MqttConfig;
+use recorder::{Recorder, RecorderConfig};
+use speaker::{SpeakerConfig, Speakers};
+use wake_verify::{Verdict, WakeCheck, WakeVerifier, WakeVerifyConfig};
+use watchdog::{Activity, Check, Problem, Watchdog, WatchdogConfig};
+use wake_vocab::OovPolicy;
+use webhook::WebhookConfig;
 
 const DEFAULT_WAKE: &[&str] = &["hey iris"];
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+// How long webhook/MQTT/socket workers get to deliver what they have queued before exiting.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+// Exit codes: 0 after a requested shutdown (SIGINT/SIGTERM or a `shutdown` request), 1 when a
+// second signal cuts the shutdown short, 2 for invalid options or a model that won't load,
+// 3 when the input device can't be opened or is lost.
+const EXIT_DEVICE: i32 = 3;
+// How long a custom wake match may precede the recognizer's wake phrase in confirm mode;
+// a one-shot command is only finalized once the whole sentence has been spoken.
+const CONFIRM_WINDOW: Duration = Duration::from_secs(8);
+// Audio blocks a lane may fall behind before its blocks are dropped (a few seconds at typical sizes).
+const LANE_BACKLOG: usize = 256;
+// A reloaded model waits for a pause in speech at most this long before it's switched in anyway.
+const RELOAD_SWAP_TIMEOUT: Duration = Duration::from_secs(30);
+const SUBCOMMANDS: &[&str] =
+    &["calibrate", "check-model", "enroll", "evaluate", "model", "transcribe", "wake-enroll"];
 
 #[derive(Clone)]
 enum ListeningState {
     Idle,
-    WakeDetected { time: Instant },
+    /// `lane` is the language that heard the wake phrase and will take the command;
+    /// `None` (listen-now) lets whichever language hears speech first take it.
+    WakeDetected { time: Instant, lane: Option<usize> },
+}
+
+enum MatchEvent {
+    /// Wake phrase heard on its own; a command is expected next.
+    Wake(String),
+    /// Command text with the wake phrase stripped, its normalized form when the lane has
+    /// ITN rules, and the utterance's x-vector when a speaker model is loaded.
+    Command(String, Option<String>, Option<Vec<f32>>),
+    /// The recognizer finalized something that was neither; a safe point to switch recognizers.
+    SegmentEnd,
+    /// A wake candidate waits for its grammar re-decode on a worker thread.
+    Verifying(PendingWake),
+    /// A final result that arrived while a candidate was being verified.
+    Held(String),
+}
+
+/// A wake candidate whose state change is held back until verification answers.
+struct PendingWake {
+    /// The recognizer result that named the wake phrase.
+    json: String,
+    verdict: Receiver<Result<(), String>>,
+    /// Final results the lane produced meanwhile, replayed once the verdict is in.
+    held: Vec<String>,
 }
 
 fn looks_like_vosk_model_dir(dir: &Path) -> bool {
@@ -55,12 +155,17 @@ fn resolve_model_dir(args: &[(String, String)]) -> Result<PathBuf, String> {
     if let Ok(p) = env::var("VOSK_MODEL") {
         candidates.push(PathBuf::from(p));
     }
-    if let Some(arg1) = env::args().skip(1).next() {
+    if let Some(arg1) = env::args().nth(1)
+        && !SUBCOMMANDS.contains(&arg1.as_str())
+    {
         candidates.push(PathBuf::from(arg1));
     }
     if let Some((_, model_path)) = args.iter().find(|(key, _)| key == "--model") {
         candidates.push(PathBuf::from(model_path));
     }
+    if let Some(default) = models::default_model(args) {
+        candidates.push(default);
+    }
 
 
     let manifest_dir =
@@ -133,23 +238,83 @@ fn resolve_model_dir(args: &[(String, String)]) -> Result<PathBuf, String> {
                 }
             }
         } else if p.is_file() {
-            msg.push_str("   Path is a file, expected a directory (did you provide a .zip/.tar.gz archive?).\n");
+            msg.push_str("   Path is a file, expected a directory (did you provide a .zip/.tar.gz archive? `IrisVA model install <archive>` extracts it).\n");
         }
     }
     msg.push_str("\nPlease download and extract a Vosk model (e.g., 'vosk-model-small-en-us-0.15') so that the folder contains subfolders like 'am', 'graph', and 'conf'.\n");
     msg.push_str(
         "You can set VOSK_MODEL=/path/to/model_dir or pass it as the first CLI argument.\n",
     );
+    msg.push_str("Or install one with `IrisVA model install <name|url|archive>` (see `IrisVA model available`).\n");
     Err(msg)
 }
 
 fn match_input_device(host: &Host, device_name: &str) -> Option<Device> {
-    for device in host.input_devices().unwrap() {
-        if device.name().unwrap() == device_name {
-            return Some(device);
-        }
+    host.input_devices().unwrap().find(|device| device.name().unwrap() == device_name)
+}
+
+fn arg_value<'a>(args: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    args.iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+// `--wake-<language>` (e.g. `--wake-de`) takes precedence over the shared `--wake` list.
+fn wake_words_from_args(args: &[(String, String)], language: &str) -> Vec<String> {
+    configured_wake_words(args, language).unwrap_or_else(|| DEFAULT_WAKE.iter().map(|w| w.to_string()).collect())
+}
+
+// Only what the flags say; the English default is no use to a model for another language.
+fn configured_wake_words(args: &[(String, String)], language: &str) -> Option<Vec<String>> {
+    let value = arg_value(args, &format!("--wake-{}", language))
+        .or_else(|| arg_value(args, "--wake"))
+        .map(|w| serde_json::Value::String(w.to_string()));
+    control::parse_phrases(value.as_ref())
+}
+
+// `--models de=/models/vosk-de,fr=/models/vosk-fr`: models run alongside the primary one.
+fn extra_models_from_args(args: &[(String, String)]) -> Result<Vec<(String, String)>, String> {
+    let Some(value) = arg_value(args, "--models") else {
+        return Ok(Vec::new());
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(|m| match m.split_once('=') {
+            Some((code, path)) if !code.trim().is_empty() && !path.trim().is_empty() => {
+                Ok((code.trim().to_lowercase(), path.trim().to_string()))
+            }
+            _ => Err(format!("Invalid --models entry '{}' (expected <language>=<model dir>)", m)),
+        })
+        .collect()
+}
+
+// Command line flags merged over the optional `--config` file.
+fn effective_args(cli_args: &[(String, String)]) -> Result<Vec<(String, String)>, String> {
+    match arg_value(cli_args, "--config") {
+        Some(path) => Ok(config::merge_args(
+            config::load_config_file(Path::new(path))?,
+            cli_args,
+        )),
+        None => Ok(cli_args.to_vec()),
     }
-    None
+}
+
+/// `words` adds per-word confidence and timing to final results, for recordings and wake checks.
+fn configure_recognizer(rec: &mut Recognizer, words: bool) {
+    rec.set_max_alternatives(0);
+    rec.set_words(words);
+    rec.set_partial_words(false);
+    rec.set_nlsml(false);
+}
+
+// Positional arguments before the first `--flag`, e.g. `evaluate ./clips`.
+fn leading_positionals() -> Vec<String> {
+    env::args()
+        .skip(1)
+        .take_while(|a| !a.starts_with("--"))
+        .collect()
 }
 
 fn collect_launch_args() -> Option<Vec<(String, String)>> {
@@ -175,6 +340,14 @@ fn collect_launch_args() -> Option<Vec<(String, String)>> {
     Some(pairs)
 }
 fn main() {
+    // Listeners passed in by systemd socket activation take the place of --ws-port/--control-socket.
+    // Taken first: it unsets LISTEN_*, which is only sound before any thread, audio stream or
+    // model could read the environment.
+    #[cfg(unix)]
+    let (ws_activated, control_activated) = systemd::listen_fds();
+    #[cfg(not(unix))]
+    let ws_activated: Option<std::net::TcpListener> = None;
+
     if let Ok(lib_dir) = env::var("VOSK_LIB_DIR") {
         let paths = env::var_os("LD_LIBRARY_PATH")
             .map(PathBuf::from)
@@ -191,7 +364,51 @@ fn main() {
         }
     }
 
-    let args = collect_launch_args().unwrap_or_default();
+    let cli_args = collect_launch_args().unwrap_or_default();
+    let args = match effective_args(&cli_args) {
+        Ok(a) => a,
+        Err(msg) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+    let positionals = leading_positionals();
+    let subcommand = positionals
+        .first()
+        .filter(|c| SUBCOMMANDS.contains(&c.as_str()))
+        .cloned();
+    // Managing models must work before any model is installed.
+    if subcommand.as_deref() == Some("model") {
+        if let Err(msg) = models::run(&positionals[1..], &args) {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+        return;
+    }
+    // Templates are plain audio features; no model is involved.
+    if subcommand.as_deref() == Some("wake-enroll") {
+        if let Err(msg) = custom_wake::enroll(&positionals[1..], &args) {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+        return;
+    }
+    // Only the microphone is measured.
+    if subcommand.as_deref() == Some("calibrate") {
+        if let Err(msg) = level::calibrate(&args) {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+        return;
+    }
+    // Checked without loading, so a broken model gets a report instead of a Vosk failure.
+    if subcommand.as_deref() == Some("check-model") {
+        if let Err(msg) = check_model::run(&positionals[1..], &args) {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+        return;
+    }
     let model_dir = match resolve_model_dir(&args) {
         Ok(p) => p,
         Err(msg) => {
@@ -202,7 +419,7 @@ fn main() {
 
     let model_path_str: String = model_dir.to_string_lossy().into_owned();
     let model = match Model::new(&model_path_str) {
-        Some(m) => m,
+        Some(m) => Arc::new(m),
         None => {
             eprintln!(
                 "Failed to load Vosk model at '{}'.\n- Ensure you have extracted a Vosk acoustic model directory there (not just libvosk.so).\n- You can set env VOSK_MODEL=/path/to/model or pass it as the first CLI arg.\n- Place libvosk.so somewhere in your loader path or set VOSK_LIB_DIR. [ERR]",
@@ -213,6 +430,20 @@ fn main() {
     };
 
 
+    if let Some(cmd) = subcommand.as_deref() {
+        let result = match cmd {
+            "enroll" => speaker::enroll(&model, &positionals[1..], &args),
+            "evaluate" => evaluate::run(&model, &positionals[1..], &args),
+            "transcribe" => transcribe::run(&model, &positionals[1..], &args),
+            _ => unreachable!(),
+        };
+        if let Err(msg) = result {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+        return;
+    }
+
     let host = cpal::default_host();
     println!("Available input devices:");
     for device in host.input_devices().unwrap() {
@@ -221,7 +452,7 @@ fn main() {
     // println!("{:?}", args);
     let selected_device = args.iter().find(|(key, _)| key == "--device");
 
-    let device = if let Some((_, value)) = selected_device {
+    let mut device = if let Some((_, value)) = selected_device {
         match_input_device(&host, value).unwrap_or_else(|| host.default_input_device().expect("No default input device available[ERR]"))
     } else {
         host.default_input_device().expect("No default input device available")
@@ -229,123 +460,677 @@ fn main() {
 
     println!("Using input device: {device:?}\n[DEVICE]({device:?})", device=device.name());
 
-    let supported_config = match device.default_input_config() {
+    let (_, config) = match input_config(&device) {
         Ok(cfg) => cfg,
         Err(e) => {
-            eprintln!("Failed to get default input config: {:?}[ERR]", e);
+            eprintln!("{}[ERR]", e);
             std::process::exit(3);
         }
     };
 
-    let mut config: StreamConfig = supported_config.clone().into();
+    let sample_rate_hz = config.sample_rate.0 as f32;
 
-    if config.channels == 0 {
-        config.channels = 1;
+    let recorder = match RecorderConfig::from_args(&args) {
+        None => None,
+        Some(Ok(cfg)) => {
+            let dir = cfg.dir.clone();
+            let device_name = device.name().unwrap_or_default();
+            match Recorder::new(cfg, config.sample_rate.0, device_name) {
+                Ok(r) => {
+                    println!("Recording wake/command audio to {}", dir.display());
+                    Some(Arc::new(Mutex::new(r)))
+                }
+                Err(msg) => {
+                    eprintln!("{}\n[ERR]", msg);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(Err(msg)) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+
+    let intents = match arg_value(&args, "--intents") {
+        None => None,
+        Some(path) => match Intents::load(Path::new(path)) {
+            Ok(intents) => {
+                println!("Loaded {} intent(s) from {}", intents.count(), path);
+                Some(Arc::new(intents))
+            }
+            Err(msg) => {
+                eprintln!("{}\n[ERR]", msg);
+                std::process::exit(2);
+            }
+        },
+    };
+
+    let language = arg_value(&args, "--language").unwrap_or("en").to_lowercase();
+    let itn_enabled = !matches!(arg_value(&args, "--itn"), Some("off" | "none"));
+    let primary_itn = match itn::rules_from_args(&args) {
+        Ok(rules) => rules,
+        Err(msg) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+
+    let (speaker_model, speakers) = match SpeakerConfig::from_args(&args) {
+        None => (None, None),
+        Some(Ok(cfg)) => {
+            let loaded = cfg
+                .load_model()
+                .and_then(|m| Ok((m, Speakers::load(&cfg.store, cfg.threshold)?)));
+            match loaded {
+                Ok((model, speakers)) => {
+                    if speakers.count() == 0 {
+                        println!("No speakers enrolled in {}; use `enroll <name>` to add one", cfg.store.display());
+                    } else {
+                        println!("Loaded {} enrolled speaker(s) from {}", speakers.count(), cfg.store.display());
+                    }
+                    (Some(Arc::new(model)), Some(Arc::new(speakers)))
+                }
+                Err(msg) => {
+                    eprintln!("{}\n[ERR]", msg);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(Err(msg)) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+
+    let setup = RecognizerSetup {
+        speaker_model,
+        // Per-word results are only parsed for recording and wake confidence/duration checks.
+        word_timing: recorder.is_some()
+            || arg_value(&args, "--wake-min-confidence").is_some()
+            || arg_value(&args, "--wake-min-duration-ms").is_some(),
+    };
+    let mut lanes = vec![Lane::new(
+        language.clone(),
+        model,
+        model_path_str.clone(),
+        sample_rate_hz,
+        wake_words_from_args(&args, &language),
+        primary_itn,
+        setup.clone(),
+    )];
+    let extra_models = match extra_models_from_args(&args) {
+        Ok(m) => m,
+        Err(msg) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+    for (code, path) in extra_models {
+        let Some(model) = Model::new(path.as_str()) else {
+            eprintln!("Failed to load Vosk model for '{}' at '{}'.\n[ERR]", code, path);
+            std::process::exit(2);
+        };
+        let Some(wake_words) = configured_wake_words(&args, &code) else {
+            eprintln!("No wake phrases for the '{}' model: set --wake-{} (or a shared --wake)\n[ERR]", code, code);
+            std::process::exit(2);
+        };
+        let itn = if itn_enabled { itn::rules_for(&code) } else { None };
+        println!("Loaded {} model from {}", code, path);
+        lanes.push(Lane::new(
+            code.clone(),
+            Arc::new(model),
+            path.clone(),
+            sample_rate_hz,
+            wake_words,
+            itn,
+            setup.clone(),
+        ));
     }
 
-    let sample_rate_hz = config.sample_rate.0 as f32;
+    let wake_oov = match OovPolicy::from_args(&args) {
+        Ok(policy) => policy,
+        Err(msg) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+    if wake_oov != OovPolicy::Off {
+        let oov: usize = lanes
+            .iter()
+            .map(|lane| lane.warn_out_of_vocabulary(&lane.wake_words.lock().unwrap()))
+            .sum();
+        if oov > 0 && wake_oov == OovPolicy::Fail {
+            eprintln!("Wake phrases contain words the model can't recognize (--wake-oov fail)\n[ERR]");
+            std::process::exit(2);
+        }
+    }
 
-    let mut recognizer1 =
-        Recognizer::new(&model, sample_rate_hz).expect("Failed to create recognizer");
-    let mut recognizer2 =
-        Recognizer::new(&model, sample_rate_hz).expect("Failed to create recognizer");
+    let (custom_wake, custom_wake_queue) = match CustomWakeConfig::from_args(&args) {
+        None => (None, None),
+        Some(Ok(cfg)) => match Templates::load(&cfg.path) {
+            Ok(templates) => {
+                println!(
+                    "Custom wake '{}' loaded from {} ({})",
+                    templates.name,
+                    cfg.path.display(),
+                    if cfg.mode == custom_wake::Mode::Confirm { "confirms wake phrases" } else { "standalone" }
+                );
+                let name = templates.name.clone();
+                let detector = Mutex::new(Detector::new(templates, cfg.threshold, sample_rate_hz as u32));
+                let (feed, queue) = mpsc::sync_channel(LANE_BACKLOG);
+                let lagging = AtomicBool::new(false);
+                (Some(Arc::new(CustomWake { mode: cfg.mode, name, detector, feed, lagging })), Some(queue))
+            }
+            Err(msg) => {
+                eprintln!("{}\n[ERR]", msg);
+                std::process::exit(2);
+            }
+        },
+        Some(Err(msg)) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
 
-    for rec in [&mut recognizer1, &mut recognizer2] {
-        let _ = rec.set_max_alternatives(0);
-        let _ = rec.set_words(false);
-        let _ = rec.set_partial_words(false);
-        let _ = rec.set_nlsml(false);
+    let wake_verify = match WakeVerifyConfig::from_args(&args) {
+        None => None,
+        Some(Ok(cfg)) => {
+            let verifier = WakeVerifier::new(cfg);
+            println!("Wake verification: {}", verifier.describe());
+            if verifier.uses_grammar() {
+                for lane in lanes.iter() {
+                    let path = lane.model_path.lock().unwrap().clone();
+                    if !check_model::inspect(Path::new(&path)).runtime_grammar {
+                        println!(
+                            "Warning: {} has a static graph; the wake-only grammar re-decode will use the full vocabulary",
+                            path
+                        );
+                    }
+                }
+            }
+            Some(Arc::new(verifier))
+        }
+        Some(Err(msg)) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+
+    let dsp = match DspConfig::from_args(&args) {
+        None => None,
+        Some(Ok(cfg)) => {
+            println!("Input conditioning: {}", cfg.describe());
+            Some(Arc::new(Mutex::new(Chain::new(cfg, sample_rate_hz as u32))))
+        }
+        Some(Err(msg)) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+
+    let level_interval = match level::interval_from_args(&args) {
+        Ok(interval) => interval,
+        Err(msg) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+
+    let mut watchdog = match WatchdogConfig::from_args(&args) {
+        Ok(cfg) => Watchdog::new(cfg),
+        Err(msg) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+
+    // The reference stream has to outlive the main loop.
+    let (aec, _aec_reference) = match AecConfig::from_args(&args) {
+        None => (None, None),
+        Some(Ok(cfg)) => {
+            let aec = Aec::new(sample_rate_hz as u32, cfg.tail);
+            match aec::start_reference(&cfg.source, &host, sample_rate_hz as u32, aec.reference.clone()) {
+                Ok(stream) => {
+                    println!("Echo cancellation on ({} ms tail)", cfg.tail.as_millis());
+                    (Some(Arc::new(aec)), stream)
+                }
+                Err(msg) => {
+                    eprintln!("{}[ERR]", msg);
+                    std::process::exit(3);
+                }
+            }
+        }
+        Some(Err(msg)) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+
+    #[cfg(unix)]
+    let notifier = match systemd::Notifier::from_env() {
+        Ok(notifier) => notifier.map(Arc::new),
+        Err(msg) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    };
+    #[cfg(unix)]
+    if let Some(interval) = notifier.as_ref().and_then(|n| n.watchdog()) {
+        println!("systemd watchdog enabled ({} ms), pinged from the audio path", interval.as_millis());
     }
 
-    let active_recognizer = Arc::new(Mutex::new(0u8)); // 0 or 1
-    let recognizers = Arc::new(Mutex::new([recognizer1, recognizer2]));
+    let events = Arc::new(EventBus::default());
+    let (feeds, queues): (Vec<_>, Vec<_>) = lanes
+        .iter()
+        .map(|_| {
+            let (tx, rx) = mpsc::sync_channel(LANE_BACKLOG);
+            (LaneFeed { tx, lagging: AtomicBool::new(false), resets: AtomicU64::new(0) }, rx)
+        })
+        .unzip();
+    let pipeline = Pipeline {
+        lanes: Arc::new(lanes),
+        feeds: Arc::new(feeds),
+        sample_rate: Arc::new(Mutex::new(sample_rate_hz)),
+        triggered: Arc::new(Mutex::new(false)),
+        state: Arc::new(Mutex::new(ListeningState::Idle)),
+        err_flag: Arc::new(Mutex::new(None::<String>)),
+        recorder,
+        events: events.clone(),
+        paused: Arc::new(AtomicBool::new(false)),
+        intents,
+        reloading: Arc::new(AtomicBool::new(false)),
+        wake_oov,
+        speakers,
+        custom_wake,
+        wake_verify,
+        meter: Arc::new(Mutex::new(Meter::new(sample_rate_hz as u32, level_interval))),
+        aec,
+        dsp,
+        activity: Arc::new(Activity::default()),
+        #[cfg(unix)]
+        notifier: notifier.clone(),
+    };
+    for (index, queue) in queues.into_iter().enumerate() {
+        pipeline.spawn_lane_worker(index, queue);
+    }
+    if let Some(queue) = custom_wake_queue {
+        pipeline.spawn_custom_wake_worker(queue);
+    }
+    // Subscribed before the first "listening" event, which is what reports readiness.
+    #[cfg(unix)]
+    if let Some(notifier) = notifier {
+        notifier.follow(&events);
+    }
 
-    let recognizers_clone = recognizers.clone();
-    let active_clone = active_recognizer.clone();
+    let swap_pipeline = pipeline.clone();
     // Replace the swap thread with this version:
     std::thread::spawn(move || {
         loop {
             std::thread::sleep(Duration::from_secs(600)); // 10 seconds for testing, 600 seconds in prod
 
-            let active = *active_clone.lock().unwrap();
-            let inactive = 1 - active;
+            let sample_rate_hz = *swap_pipeline.sample_rate.lock().unwrap();
+            for lane in swap_pipeline.lanes.iter() {
+                let inactive = 1 - *lane.active.lock().unwrap();
 
-            // Recreate inactive recognizer using existing model
-            {
-                let mut recs = recognizers_clone.lock().unwrap();
-                // Drop old recognizer explicitly
-                drop(std::mem::replace(
-                    &mut recs[inactive as usize],
-                    Recognizer::new(&model, sample_rate_hz).unwrap(),
-                ));
-
-                let _ = recs[inactive as usize].set_max_alternatives(0);
-                let _ = recs[inactive as usize].set_words(false);
-                let _ = recs[inactive as usize].set_partial_words(false);
-                let _ = recs[inactive as usize].set_nlsml(false);
-            } // Release lock before swap
-
-            *active_clone.lock().unwrap() = inactive;
+                // Recreate inactive recognizer using the lane's current model
+                let fresh = lane.fresh_recognizer(&lane.model(), sample_rate_hz);
+                {
+                    let mut recs = lane.recognizers.lock().unwrap();
+                    // Drop old recognizer explicitly
+                    drop(std::mem::replace(&mut recs[inactive as usize], fresh));
+                    lane.calls.lock().unwrap()[inactive as usize] = 0;
+                } // Release lock before swap
+
+                *lane.active.lock().unwrap() = inactive;
+            }
             println!("Swapped to fresh recognizer\n[SWAP]");
+            swap_pipeline.events.emit(Event::Swap);
         }
     });
 
+    let (control_tx, control_rx) = mpsc::channel::<Control>();
+    #[cfg(unix)]
+    if let Err(msg) = signals::spawn(control_tx.clone()) {
+        eprintln!("{}\n[ERR]", msg);
+        std::process::exit(2);
+    }
+    if let Some(secs) = arg_value(&args, "--model-watch-secs") {
+        match secs.parse::<u64>() {
+            Ok(0) => {}
+            Ok(secs) => {
+                let dirs = pipeline
+                    .lanes
+                    .iter()
+                    .map(|lane| (lane.language.clone(), PathBuf::from(&*lane.model_path.lock().unwrap())))
+                    .collect();
+                model_watch::spawn(dirs, Duration::from_secs(secs), control_tx.clone());
+                println!("Watching model directories for changes every {}s", secs);
+            }
+            Err(_) => {
+                eprintln!("Invalid value for --model-watch-secs: '{}'\n[ERR]", secs);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(listener) = ws_activated {
+        let addr = listener.local_addr().map(|a| a.to_string()).unwrap_or_default();
+        websocket::serve(listener, events.clone(), control_tx.clone());
+        println!("WebSocket server listening on ws://{} (socket-activated)", addr);
+    } else if let Some(port) = arg_value(&args, "--ws-port") {
+        let result = port
+            .parse::<u16>()
+            .map_err(|_| format!("Invalid value for --ws-port: '{}'", port))
+            .and_then(|port| websocket::spawn(port, events.clone(), control_tx.clone()));
+        match result {
+            Ok(()) => println!("WebSocket server listening on ws://127.0.0.1:{}", port),
+            Err(msg) => {
+                eprintln!("{}\n[ERR]", msg);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    match WebhookConfig::from_args(&args) {
+        None => {}
+        Some(Ok(cfg)) => {
+            let urls = cfg.urls.join(", ");
+            if let Err(msg) = webhook::spawn(cfg, &events) {
+                eprintln!("{}\n[ERR]", msg);
+                std::process::exit(2);
+            }
+            println!("Posting commands to webhook(s): {}", urls);
+        }
+        Some(Err(msg)) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    }
+
+    match MqttConfig::from_args(&args) {
+        None => {}
+        Some(Ok(cfg)) => {
+            let prefix = cfg.prefix.clone();
+            if let Err(msg) = mqtt::spawn(cfg, &events, control_tx.clone()) {
+                eprintln!("{}\n[ERR]", msg);
+                std::process::exit(2);
+            }
+            println!("Publishing events to MQTT topics {}/#", prefix);
+        }
+        Some(Err(msg)) => {
+            eprintln!("{}\n[ERR]", msg);
+            std::process::exit(2);
+        }
+    }
+
+    if let Some(path) = arg_value(&args, "--rules") {
+        let dry_run = arg_value(&args, "--rules-dry-run") == Some("true");
+        match rules::Rules::load(Path::new(path), dry_run) {
+            Ok(rules) => {
+                println!(
+                    "Loaded {} rule(s) from {}{}",
+                    rules.count(),
+                    path,
+                    if dry_run { " (dry run)" } else { "" }
+                );
+                rules::spawn(rules, &events);
+            }
+            Err(msg) => {
+                eprintln!("{}\n[ERR]", msg);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    let control_path = match control_activated {
+        Some(listener) => {
+            unix_socket::serve(listener, events.clone(), control_tx.clone());
+            println!("Control socket listening on the socket-activated descriptor");
+            None
+        }
+        None => arg_value(&args, "--control-socket"),
+    };
+    #[cfg(not(unix))]
+    let control_path = arg_value(&args, "--control-socket");
+    if let Some(path) = control_path {
+        #[cfg(unix)]
+        match unix_socket::spawn(Path::new(path), events.clone(), control_tx.clone()) {
+            Ok(()) => println!("Control socket listening on {}", path),
+            Err(msg) => {
+                eprintln!("{}\n[ERR]", msg);
+                std::process::exit(2);
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            eprintln!("--control-socket {} is only supported on Unix\n[ERR]", path);
+            std::process::exit(2);
+        }
+    }
+
     println!(
         "Listening for wake words: {} (sample rate: {} Hz, channels: {}) [LISTENING]",
-        DEFAULT_WAKE.join(", "),
+        pipeline.describe_wake_words(),
         sample_rate_hz,
         config.channels
     );
 
-    let triggered = Arc::new(Mutex::new(false));
-    let triggered_clone = triggered.clone();
-    let state = Arc::new(Mutex::new(ListeningState::Idle));
-    let state_clone = state.clone();
-
-    let err_flag = Arc::new(Mutex::new(None::<String>));
-    let err_flag_clone = err_flag.clone();
-
-    let stream: Stream = match supported_config.sample_format() {
-        SampleFormat::I16 => build_input_stream_i16(
-            &device,
-            &config,
-            recognizers.clone(),
-            active_recognizer.clone(),
-            triggered_clone,
-            DEFAULT_WAKE,
-            state_clone,
-            err_flag_clone,
-        ),
-        SampleFormat::U16 => build_input_stream_u16(
-            &device,
-            &config,
-            recognizers.clone(),
-            active_recognizer.clone(),
-            triggered_clone,
-            DEFAULT_WAKE,
-            state_clone,
-            err_flag_clone,
-        ),
-        SampleFormat::F32 => build_input_stream_f32(
-            &device,
-            &config,
-            recognizers.clone(),
-            active_recognizer.clone(),
-            triggered_clone,
-            DEFAULT_WAKE,
-            state_clone,
-            err_flag_clone,
-        ),
-        _ => panic!("Unsupported sample format"),
+    // Empty only while a watchdog restart is waiting to be retried.
+    let mut stream: Option<Stream> = match open_stream(&device, &pipeline) {
+        Ok((stream, _)) => Some(stream),
+        Err(e) => {
+            eprintln!("{}[ERR]", e);
+            std::process::exit(3);
+        }
     };
+    events.emit(Event::Device { name: device.name().unwrap_or_default() });
+    events.emit(Event::State { state: "listening" });
 
-    stream.play().expect("Failed to start input stream");
+    let state = pipeline.state.clone();
+    let triggered = pipeline.triggered.clone();
+    let err_flag = pipeline.err_flag.clone();
 
     let start = Instant::now();
     let mut listening_printed = false;
+    let mut shutdown = false;
+    let mut exit_code = 0;
     loop {
+        while let Ok(cmd) = control_rx.try_recv() {
+            match cmd {
+                Control::Shutdown => {
+                    shutdown = true;
+                    break;
+                }
+                Control::Status(reply) => {
+                    let current = if pipeline.paused.load(Ordering::SeqCst) {
+                        "paused"
+                    } else if matches!(*state.lock().unwrap(), ListeningState::WakeDetected { .. }) {
+                        "waiting"
+                    } else {
+                        "listening"
+                    };
+                    let _ = reply.send(serde_json::json!({
+                        "type": "status",
+                        "state": current,
+                        "device": device.name().unwrap_or_default(),
+                        "sample_rate": *pipeline.sample_rate.lock().unwrap(),
+                        "wake_words": *pipeline.lanes[0].wake_words.lock().unwrap(),
+                        "languages": pipeline.lanes.iter().map(|lane| serde_json::json!({
+                            "language": lane.language,
+                            "wake_words": *lane.wake_words.lock().unwrap(),
+                            "model": *lane.model_path.lock().unwrap(),
+                        })).collect::<Vec<_>>(),
+                        "reloading": pipeline.reloading.load(Ordering::SeqCst),
+                        "wake_verification": pipeline.wake_verify.as_ref().map(|v| v.stats_json()),
+                        "audio_warnings": pipeline.meter.lock().unwrap().active_warnings(),
+                        "uptime_secs": start.elapsed().as_secs(),
+                    }));
+                }
+                Control::ReloadConfig => {
+                    let reloaded = match effective_args(&cli_args) {
+                        Ok(a) => a,
+                        Err(msg) => {
+                            eprintln!("{}[ERR]", msg);
+                            events.emit(Event::Error { message: msg });
+                            continue;
+                        }
+                    };
+                    // Wake phrases and the device are applied while running; the rest needs a restart.
+                    let (applied, pending): (Vec<String>, Vec<String>) =
+                        changed_options(&args, &reloaded).into_iter().partition(|key| {
+                            key == "--device"
+                                || key == "--wake"
+                                || pipeline.lanes.iter().any(|lane| *key == format!("--wake-{}", lane.language))
+                        });
+                    if applied.is_empty() {
+                        println!("Configuration reloaded, nothing to apply.\n[RELOADED]");
+                    } else {
+                        println!("Configuration reloaded, applying {}.\n[RELOADED]", applied.join(", "));
+                    }
+                    if !pending.is_empty() {
+                        println!("Warning: changes to {} take effect after a restart", pending.join(", "));
+                    }
+                    // Applied through the same queue so they are handled like client requests.
+                    for (index, lane) in pipeline.lanes.iter().enumerate() {
+                        let phrases = match configured_wake_words(&reloaded, &lane.language) {
+                            Some(phrases) => phrases,
+                            None if index == 0 => wake_words_from_args(&reloaded, &lane.language),
+                            None => {
+                                println!("Warning: no wake phrases for '{}' in the new configuration; keeping the current ones", lane.language);
+                                continue;
+                            }
+                        };
+                        let _ = control_tx.send(Control::SetWake(phrases, Some(lane.language.clone())));
+                    }
+                    if let Some(name) = arg_value(&reloaded, "--device")
+                        && device.name().ok().as_deref() != Some(name)
+                    {
+                        let _ = control_tx.send(Control::SwitchDevice(name.to_string()));
+                    }
+                }
+                Control::ReloadModel { language, path } => {
+                    let lanes: Vec<usize> = match &language {
+                        None if path.is_some() && pipeline.lanes.len() > 1 => {
+                            let msg = "reload-model with a path needs a language when several models are loaded".to_string();
+                            eprintln!("{}[ERR]", msg);
+                            events.emit(Event::Error { message: msg });
+                            continue;
+                        }
+                        None => (0..pipeline.lanes.len()).collect(),
+                        Some(code) => pipeline.lanes.iter().position(|l| l.language == *code).into_iter().collect(),
+                    };
+                    if lanes.is_empty() {
+                        let msg = format!("No model loaded for language '{}'", language.unwrap_or_default());
+                        eprintln!("{}[ERR]", msg);
+                        events.emit(Event::Error { message: msg });
+                        continue;
+                    }
+                    if pipeline.reloading.swap(true, Ordering::SeqCst) {
+                        let msg = "A model reload is already in progress".to_string();
+                        eprintln!("{}[ERR]", msg);
+                        events.emit(Event::Error { message: msg });
+                        continue;
+                    }
+                    let targets = lanes
+                        .into_iter()
+                        .map(|i| {
+                            let dir = path.clone().unwrap_or_else(|| pipeline.lanes[i].model_path.lock().unwrap().clone());
+                            (i, dir)
+                        })
+                        .collect();
+                    pipeline.reload_models(targets);
+                }
+                Control::Pause => {
+                    pipeline.paused.store(true, Ordering::SeqCst);
+                    *state.lock().unwrap() = ListeningState::Idle;
+                    listening_printed = false;
+                    println!("Listening paused.\n[PAUSED]");
+                    events.emit(Event::State { state: "paused" });
+                }
+                Control::Resume => {
+                    if pipeline.paused.swap(false, Ordering::SeqCst) {
+                        pipeline.reset_active();
+                        println!("Listening resumed.\n[RESUMED]");
+                        events.emit(Event::State { state: "listening" });
+                    }
+                }
+                Control::ListenNow => {
+                    if pipeline.paused.load(Ordering::SeqCst) {
+                        events.emit(Event::Error { message: "listen-now ignored while paused".to_string() });
+                        continue;
+                    }
+                    pipeline.reset_active();
+                    *state.lock().unwrap() = ListeningState::WakeDetected { time: Instant::now(), lane: None };
+                    listening_printed = false;
+                    events.emit(Event::Wake { phrase: String::new(), forced: true, language: None });
+                }
+                Control::SetWake(phrases, language) => {
+                    let lane = match &language {
+                        None => pipeline.lanes.first(),
+                        Some(code) => pipeline.lanes.iter().find(|l| l.language == *code),
+                    };
+                    let Some(lane) = lane else {
+                        let msg = format!("No model loaded for language '{}'", language.unwrap_or_default());
+                        eprintln!("{}[ERR]", msg);
+                        events.emit(Event::Error { message: msg });
+                        continue;
+                    };
+                    if pipeline.wake_oov != OovPolicy::Off
+                        && lane.warn_out_of_vocabulary(&phrases) > 0
+                        && pipeline.wake_oov == OovPolicy::Fail
+                    {
+                        let msg = "Wake phrases rejected: words not in the model's vocabulary".to_string();
+                        eprintln!("{}[ERR]", msg);
+                        events.emit(Event::Error { message: msg });
+                        continue;
+                    }
+                    *lane.wake_words.lock().unwrap() = phrases;
+                    println!("Listening for wake words: {} [LISTENING]", pipeline.describe_wake_words());
+                }
+                Control::SwitchDevice(name) => {
+                    let Some(new_device) = match_input_device(&host, &name) else {
+                        eprintln!("Input device '{}' not found[ERR]", name);
+                        events.emit(Event::Error { message: format!("Input device '{}' not found", name) });
+                        continue;
+                    };
+                    // Stop the old stream before the new one starts feeding the recognizers.
+                    drop(stream.take());
+                    stream = match open_stream(&new_device, &pipeline) {
+                        Ok((s, _)) => {
+                            device = new_device;
+                            Some(s)
+                        }
+                        Err(e) => {
+                            eprintln!("{}[ERR]", e);
+                            events.emit(Event::Error { message: e });
+                            match open_stream(&device, &pipeline) {
+                                Ok((s, _)) => Some(s),
+                                Err(e) => {
+                                    // Out through the normal shutdown, so the recorder is flushed.
+                                    eprintln!("{}[ERR]", e);
+                                    events.emit(Event::Error { message: e });
+                                    exit_code = EXIT_DEVICE;
+                                    shutdown = true;
+                                    break;
+                                }
+                            }
+                        }
+                    };
+                    *state.lock().unwrap() = ListeningState::Idle;
+                    listening_printed = false;
+                    println!("Using input device: {device:?}\n[DEVICE]({device:?})", device=device.name());
+                    events.emit(Event::Device { name: device.name().unwrap_or_default() });
+                }
+            }
+        }
+        if shutdown {
+            break;
+        }
+
         if let Some(err) = err_flag.lock().unwrap().take() {
             eprintln!("Stream error: {}\\n[ERR]", err);
+            events.emit(Event::Error { message: err });
             *triggered.lock().unwrap() = false;
             *state.lock().unwrap() = ListeningState::Idle;
             listening_printed = false;
@@ -357,55 +1142,694 @@ fn main() {
             *triggered.lock().unwrap() = false;
             *state.lock().unwrap() = ListeningState::Idle;
             listening_printed = false;
+            events.emit(Event::State { state: "processed" });
             std::thread::sleep(Duration::from_millis(500)); // Prevent immediate retrigger
             continue;
         }
 
+        let mut timed_out = false;
         if let Ok(mut current_state_guard) = state.lock() {
-            if let ListeningState::WakeDetected { time } = &*current_state_guard {
+            if let ListeningState::WakeDetected { time, .. } = &*current_state_guard {
                 let elapsed = time.elapsed();
                 if elapsed > Duration::from_millis(350) {
                     if !listening_printed {
                         println!("Listening for command...\\n[WAITING]");
                         listening_printed = true;
+                        events.emit(Event::State { state: "waiting" });
                     }
-                    if elapsed > Duration::from_secs(3) {
+                    if elapsed > COMMAND_TIMEOUT {
                         println!("No command detected. Resetting.[RESETTING]");
                         *current_state_guard = ListeningState::Idle; // Modify directly
                         listening_printed = false;
-
-                        // Reset recognizer
-                        let active_idx = *active_recognizer.lock().unwrap() as usize;
-                        let mut recs = recognizers.lock().unwrap();
-                        let _ = recs[active_idx].reset();
-
-                        continue;
+                        timed_out = true;
                     }
                 }
             } else {
                 listening_printed = false;
             }
         }
+        if timed_out {
+            // State lock is released first: the audio callback takes it while holding the recognizers.
+            if let Some(rec) = &pipeline.recorder {
+                rec.lock().unwrap().abandon();
+            }
+            pipeline.reset_active();
+            events.emit(Event::State { state: "resetting" });
+            continue;
+        }
 
+        match watchdog.check(&pipeline.activity) {
+            Some(Check::Raised(problem, secs)) => {
+                let name = device.name().unwrap_or_default();
+                let (message, event) = match problem {
+                    Problem::Stalled => (
+                        format!("No audio from '{}' for {} s; the input stream has stalled", name, secs),
+                        Event::MicStalled { device: name, secs },
+                    ),
+                    Problem::Dead => (
+                        format!("Input from '{}' has been flat for {} s; the microphone may be muted or dead", name, secs),
+                        Event::MicDead { device: name, secs },
+                    ),
+                };
+                println!("Warning: {}", message);
+                events.emit(event);
+            }
+            Some(Check::Recovered(problem)) => {
+                println!("Microphone input recovered (was {})", problem.as_str());
+                events.emit(Event::MicRecovered { device: device.name().unwrap_or_default(), problem: problem.as_str() });
+            }
+            Some(Check::Restart(problem)) => {
+                // The device may have been unplugged and come back under a new handle.
+                let name = device.name().unwrap_or_default();
+                if let Some(found) = match_input_device(&host, &name) {
+                    device = found;
+                }
+                println!("Restarting input stream for '{}' (input {})", name, problem.as_str());
+                drop(stream.take());
+                match open_stream(&device, &pipeline) {
+                    Ok((s, _)) => {
+                        stream = Some(s);
+                        pipeline.activity.reset();
+                        *state.lock().unwrap() = ListeningState::Idle;
+                        listening_printed = false;
+                    }
+                    Err(e) => {
+                        // Retried after another restart interval.
+                        let msg = format!("Could not restart input stream: {}", e);
+                        eprintln!("{}[ERR]", msg);
+                        events.emit(Event::Error { message: msg });
+                    }
+                }
+            }
+            None => {}
+        }
 
         std::thread::sleep(Duration::from_millis(50));
-        if start.elapsed() > Duration::from_secs(24 * 60 * 60) {
-            break;
+    }
+
+    // The input stream goes first so nothing new is captured.
+    println!("Shutting down...\n[STOPPING]");
+    events.emit(Event::State { state: "stopping" });
+    drop(stream);
+    if let Some(rec) = &pipeline.recorder {
+        rec.lock().unwrap().close();
+    }
+    events.emit(Event::State { state: "stopped" });
+    let unfinished = events.close(SHUTDOWN_GRACE);
+    if unfinished > 0 {
+        println!("Warning: {} output worker(s) still delivering after {} s; exiting anyway", unfinished, SHUTDOWN_GRACE.as_secs());
+    }
+    println!("Stopped.\n[STOPPED]");
+    let _ = std::io::stdout().flush();
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
+
+/// Options whose value differs between two configurations, or that only one of them sets.
+fn changed_options(old: &[(String, String)], new: &[(String, String)]) -> Vec<String> {
+    let mut keys: Vec<String> = old
+        .iter()
+        .chain(new)
+        .map(|(key, _)| key.clone())
+        .filter(|key| arg_value(old, key) != arg_value(new, key))
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// One model and its wake phrases. Every lane is fed the same audio.
+struct Lane {
+    language: String,
+    model: Mutex<Arc<Model>>,
+    model_path: Mutex<String>,
+    recognizers: Mutex<[Recognizer; 2]>,
+    /// Blocks each recognizer decoded since its last periodic cleanup reset.
+    calls: Mutex<[u32; 2]>,
+    /// Index of the recognizer currently fed audio (0 or 1).
+    active: Mutex<u8>,
+    /// Recognizer slot holding a freshly reloaded model, taken at the next utterance boundary.
+    reload_slot: Mutex<Option<u8>>,
+    wake_words: Mutex<Vec<String>>,
+    itn: Option<Box<dyn itn::LanguageRules>>,
+    last_partial: Mutex<String>,
+    /// Audio of the utterance in progress, re-decoded when wake verification needs it.
+    utterance: Mutex<Vec<i16>>,
+    pending: Mutex<Option<PendingWake>>,
+    setup: RecognizerSetup,
+}
+
+struct LaneFeed {
+    /// Blocks tagged with the `resets` count at the time they were queued.
+    tx: SyncSender<(u64, Arc<[i16]>)>,
+    /// Set while blocks are being dropped because the queue is full.
+    lagging: AtomicBool,
+    /// Bumped by the custom wake thread to reset the lane without waiting on its decoder; blocks
+    /// queued before the latest reset are skipped.
+    resets: AtomicU64,
+}
+
+/// How every recognizer of a lane is created, including ones for reloads and swaps.
+#[derive(Clone)]
+struct RecognizerSetup {
+    /// Adds an x-vector to every result when `--speaker-model` is set.
+    speaker_model: Option<Arc<SpeakerModel>>,
+    /// Per-word confidence and timing in final results.
+    word_timing: bool,
+}
+
+impl Lane {
+    fn new(
+        language: String,
+        model: Arc<Model>,
+        model_path: String,
+        sample_rate_hz: f32,
+        wake_words: Vec<String>,
+        itn: Option<Box<dyn itn::LanguageRules>>,
+        setup: RecognizerSetup,
+    ) -> Self {
+        let recognizers = [new_recognizer(&model, sample_rate_hz, &setup), new_recognizer(&model, sample_rate_hz, &setup)];
+        Lane {
+            language,
+            model: Mutex::new(model),
+            model_path: Mutex::new(model_path),
+            recognizers: Mutex::new(recognizers),
+            calls: Mutex::new([0; 2]),
+            active: Mutex::new(0),
+            reload_slot: Mutex::new(None),
+            wake_words: Mutex::new(wake_words),
+            itn,
+            last_partial: Mutex::new(String::new()),
+            utterance: Mutex::new(Vec::new()),
+            pending: Mutex::new(None),
+            setup,
+        }
+    }
+
+    fn model(&self) -> Arc<Model> {
+        self.model.lock().unwrap().clone()
+    }
+
+    fn fresh_recognizer(&self, model: &Model, sample_rate_hz: f32) -> Recognizer {
+        new_recognizer(model, sample_rate_hz, &self.setup)
+    }
+
+    /// Prints a warning for each word of `phrases` the lane's model can never output;
+    /// returns how many there were.
+    fn warn_out_of_vocabulary(&self, phrases: &[String]) -> usize {
+        let path = self.model_path.lock().unwrap().clone();
+        let Some(oov) = wake_vocab::check_model(phrases, Path::new(&path)) else {
+            println!("Warning: {} has no words.txt; wake phrases can't be checked against it", path);
+            return 0;
+        };
+        for word in &oov {
+            println!("Warning: {}", word.describe(&self.language));
         }
+        oov.len()
     }
 }
 
-fn build_input_stream_i16(
-    device: &Device,
-    config: &StreamConfig,
-    recognizers: Arc<Mutex<[Recognizer; 2]>>,
-    active_recognizer: Arc<Mutex<u8>>,
+/// State shared between the audio callback, the lane threads, the main loop and the swap thread.
+#[derive(Clone)]
+struct Pipeline {
+    /// The first lane is the primary `--language` model.
+    lanes: Arc<Vec<Lane>>,
+    /// Audio for each lane's decoding thread, in lane order.
+    feeds: Arc<Vec<LaneFeed>>,
+    sample_rate: Arc<Mutex<f32>>,
     triggered: Arc<Mutex<bool>>,
-    wake_words: &'static [&'static str],
     state: Arc<Mutex<ListeningState>>,
     err_flag: Arc<Mutex<Option<String>>>,
-) -> Stream {
+    recorder: Option<Arc<Mutex<Recorder>>>,
+    events: Arc<EventBus>,
+    paused: Arc<AtomicBool>,
+    intents: Option<Arc<Intents>>,
+    /// Set while a model reload is loading or waiting for an utterance boundary.
+    reloading: Arc<AtomicBool>,
+    wake_oov: OovPolicy,
+    /// Enrolled voices matched against each command's x-vector.
+    speakers: Option<Arc<Speakers>>,
+    custom_wake: Option<Arc<CustomWake>>,
+    wake_verify: Option<Arc<WakeVerifier>>,
+    /// Levels and warnings for the raw input.
+    meter: Arc<Mutex<Meter>>,
+    /// Removes playback picked up by the microphone; runs before `dsp`.
+    aec: Option<Arc<Aec>>,
+    /// Signal conditioning applied before anything else sees the audio.
+    dsp: Option<Arc<Mutex<Chain>>>,
+    /// Callback and signal timestamps for the microphone watchdog.
+    activity: Arc<Activity>,
+    /// Set when running under systemd with `Type=notify`.
+    #[cfg(unix)]
+    notifier: Option<Arc<systemd::Notifier>>,
+}
+
+impl Pipeline {
+    /// Runs one block of mono audio through recording and recognition.
+    fn process(&self, pcm_mono: &[i16]) {
+        // Recorded while paused too: the device is still expected to deliver audio.
+        self.activity.record(pcm_mono);
+        #[cfg(unix)]
+        // A hung decoder fills its lane's queue; without pings systemd restarts the service.
+        if let Some(notifier) = &self.notifier
+            && !self.feeds.iter().any(|feed| feed.lagging.load(Ordering::Relaxed))
+        {
+            notifier.ping();
+        }
+        if self.paused.load(Ordering::Relaxed) {
+            return;
+        }
+        let readings = self.meter.lock().unwrap().push(pcm_mono);
+        for reading in readings {
+            match reading {
+                Reading::Level { rms_db, peak_db, noise_floor_db, snr_db, clipped } => {
+                    self.events.emit(Event::Level { rms_db, peak_db, noise_floor_db, snr_db, clipped });
+                }
+                Reading::Warning { kind, message, active } => {
+                    if active {
+                        println!("Warning: {}", message);
+                    } else {
+                        println!("Audio input: {}", message);
+                    }
+                    self.events.emit(Event::AudioWarning { warning: kind, message, active });
+                }
+            }
+        }
+        let cancelled;
+        let pcm_mono = match &self.aec {
+            Some(aec) => {
+                cancelled = aec.process(pcm_mono);
+                &cancelled[..]
+            }
+            None => pcm_mono,
+        };
+        let conditioned;
+        let pcm_mono = match &self.dsp {
+            Some(dsp) => {
+                conditioned = dsp.lock().unwrap().process(pcm_mono);
+                &conditioned[..]
+            }
+            None => pcm_mono,
+        };
+        if let Some(rec) = &self.recorder {
+            rec.lock().unwrap().push(pcm_mono);
+        }
+        let block: Arc<[i16]> = pcm_mono.into();
+        if let Some(custom) = &self.custom_wake {
+            match custom.feed.try_send(block.clone()) {
+                Ok(()) => {
+                    if custom.lagging.swap(false, Ordering::Relaxed) {
+                        println!("The custom wake detector has caught up");
+                    }
+                }
+                Err(TrySendError::Full(_)) => {
+                    if !custom.lagging.swap(true, Ordering::Relaxed) {
+                        println!("Warning: the custom wake detector can't keep up with the audio; dropping input");
+                    }
+                }
+                Err(TrySendError::Disconnected(_)) => {}
+            }
+        }
+        for (lane, feed) in self.lanes.iter().zip(self.feeds.iter()) {
+            match feed.tx.try_send((feed.resets.load(Ordering::SeqCst), block.clone())) {
+                Ok(()) => {
+                    if feed.lagging.swap(false, Ordering::Relaxed) {
+                        println!("The {} model has caught up", lane.language);
+                    }
+                }
+                Err(TrySendError::Full(_)) => {
+                    if !feed.lagging.swap(true, Ordering::Relaxed) {
+                        println!("Warning: the {} model can't keep up with the audio; dropping input", lane.language);
+                    }
+                }
+                Err(TrySendError::Disconnected(_)) => {}
+            }
+        }
+    }
+
+    /// Decodes one lane's audio on its own thread, so a slow model holds up neither the audio
+    /// callback nor the other lanes.
+    fn spawn_lane_worker(&self, index: usize, queue: Receiver<(u64, Arc<[i16]>)>) {
+        let pipeline = self.clone();
+        let name = format!("lane-{}", self.lanes[index].language);
+        let spawned = std::thread::Builder::new().name(name).spawn(move || {
+            let lane = &pipeline.lanes[index];
+            let mut done = 0;
+            for (resets, block) in queue {
+                if resets < pipeline.feeds[index].resets.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if resets > done {
+                    pipeline.reset_lane(lane);
+                    done = resets;
+                }
+                pipeline.process_lane(index, lane, &block);
+            }
+        });
+        if let Err(e) = spawned {
+            eprintln!("Failed to start the decoding thread for '{}': {}\n[ERR]", self.lanes[index].language, e);
+            std::process::exit(2);
+        }
+    }
+
+    /// Matches the custom wake templates on their own thread; a standalone match wakes IrisVA
+    /// as the wake phrase would.
+    fn spawn_custom_wake_worker(&self, queue: Receiver<Arc<[i16]>>) {
+        let pipeline = self.clone();
+        let spawned = std::thread::Builder::new().name("custom-wake".to_string()).spawn(move || {
+            let Some(custom) = &pipeline.custom_wake else {
+                return;
+            };
+            for block in queue {
+                let hit = custom.detector.lock().unwrap().push(&block);
+                if let Some(distance) = hit
+                    && custom.mode == custom_wake::Mode::Standalone
+                    && !*pipeline.triggered.lock().unwrap()
+                    && matches!(*pipeline.state.lock().unwrap(), ListeningState::Idle)
+                {
+                    println!("Custom wake '{}' detected (distance {:.2})", custom.name, distance);
+                    // The lanes reset themselves before their next block, and skip the queued wake audio.
+                    for feed in pipeline.feeds.iter() {
+                        feed.resets.fetch_add(1, Ordering::SeqCst);
+                    }
+                    *pipeline.state.lock().unwrap() = ListeningState::WakeDetected { time: Instant::now(), lane: None };
+                    pipeline.events.emit(Event::Wake { phrase: custom.name.clone(), forced: false, language: None });
+                }
+            }
+        });
+        if let Err(e) = spawned {
+            eprintln!("Failed to start the custom wake thread: {}\n[ERR]", e);
+            std::process::exit(2);
+        }
+    }
+
+    fn process_lane(&self, index: usize, lane: &Lane, pcm_mono: &[i16]) {
+        let wake_words = lane.wake_words.lock().unwrap();
+        let wake_words: Vec<&str> = wake_words.iter().map(String::as_str).collect();
+        let mut active = lane.active.lock().unwrap();
+        let active_idx = *active as usize;
+        let mut recs = lane.recognizers.lock().unwrap();
+        let mut calls = lane.calls.lock().unwrap();
+        let model = lane.model();
+        let mut utterance = lane.utterance.lock().unwrap();
+        let mut pending = lane.pending.lock().unwrap();
+        let mut ctx = MatchContext {
+            wake_words: &wake_words,
+            triggered: &self.triggered,
+            state: &self.state,
+            recorder: self.recorder.as_ref(),
+            lane: index,
+            hold: false,
+            confirm: self.custom_wake.as_ref().filter(|c| c.mode == custom_wake::Mode::Confirm).map(|c| &c.detector),
+            quiet: false,
+            itn: lane.itn.as_deref(),
+        };
+
+        let mut events = Vec::new();
+        let verdict = pending.as_ref().and_then(|p| match p.verdict.try_recv() {
+            Ok(verdict) => Some(verdict),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("verification failed".to_string())),
+        });
+        if let Some(verdict) = verdict {
+            let candidate = pending.take().unwrap();
+            let idle = matches!(*self.state.lock().unwrap(), ListeningState::Idle) && !*self.triggered.lock().unwrap();
+            match verdict {
+                Ok(()) if idle => events.extend(accept_wake(&candidate.json, &ctx)),
+                // Another lane or a forced wake took over meanwhile.
+                Ok(()) => {}
+                Err(reason) => {
+                    let text = extract_text_from_complete_json(&candidate.json).unwrap_or_default();
+                    println!("Wake candidate '{}' rejected: {}", text.trim(), reason);
+                    events.push(end_segment(&ctx));
+                }
+            }
+            for json in &candidate.held {
+                // Words after an accepted wake are the command. Without one, a held wake
+                // phrase can't be verified any more since its audio is gone.
+                if matches!(*self.state.lock().unwrap(), ListeningState::Idle) {
+                    events.push(end_segment(&ctx));
+                } else {
+                    events.extend(handle_result(json, &ctx, None));
+                }
+            }
+        }
+
+        ctx.hold = pending.is_some();
+        let mut check = self.wake_verify.as_ref().map(|verifier| WakeCheck {
+            verifier,
+            model: &model,
+            sample_rate: *self.sample_rate.lock().unwrap(),
+            utterance: &mut utterance,
+            background: true,
+        });
+        match create_waveform_match(&mut recs[active_idx], &mut calls[active_idx], pcm_mono, &ctx, check.as_mut()) {
+            Some(MatchEvent::Verifying(candidate)) => *pending = Some(candidate),
+            Some(MatchEvent::Held(json)) => {
+                if let Some(candidate) = pending.as_mut() {
+                    candidate.held.push(json);
+                }
+            }
+            Some(event) => events.push(event),
+            None => {}
+        }
+
+        if events.is_empty() && self.events.has_subscribers() {
+            let json = serde_json::to_string(&recs[active_idx].partial_result()).unwrap_or_default();
+            let partial = extract_partial_from_json(&json).unwrap_or_default();
+            let mut last = lane.last_partial.lock().unwrap();
+            if *last != partial {
+                if !partial.is_empty() {
+                    self.events.emit(Event::Partial { text: partial.clone(), language: lane.language.clone() });
+                }
+                *last = partial;
+            }
+        }
+        for event in events {
+            match event {
+                MatchEvent::Wake(phrase) => {
+                    self.events.emit(Event::Wake { phrase, forced: false, language: Some(lane.language.clone()) });
+                }
+                MatchEvent::Command(text, normalized, vector) => {
+                    lane.last_partial.lock().unwrap().clear();
+                    let speaker = match (&self.speakers, vector) {
+                        (Some(speakers), Some(vector)) => speakers.identify(&vector),
+                        _ => None,
+                    };
+                    let intent = match &self.intents {
+                        Some(intents) if self.events.has_subscribers() => intents.parse(&text),
+                        _ => None,
+                    };
+                    self.events.emit(Event::Command {
+                        text,
+                        normalized,
+                        intent,
+                        speaker,
+                        language: lane.language.clone(),
+                    });
+                }
+                MatchEvent::SegmentEnd => {
+                    // Nothing is mid-utterance here, so a reloaded model can take over without losing audio.
+                    if matches!(*self.state.lock().unwrap(), ListeningState::Idle)
+                        && pending.is_none()
+                        && let Some(slot) = lane.reload_slot.lock().unwrap().take()
+                    {
+                        *active = slot;
+                    }
+                }
+                MatchEvent::Verifying(_) | MatchEvent::Held(_) => {}
+            }
+        }
+    }
+
+    /// Waits for each lane's decoder to finish its block; the audio callback bumps
+    /// `LaneFeed::resets` instead.
+    fn reset_active(&self) {
+        for lane in self.lanes.iter() {
+            self.reset_lane(lane);
+        }
+    }
+
+    fn reset_lane(&self, lane: &Lane) {
+        let active_idx = *lane.active.lock().unwrap() as usize;
+        lane.recognizers.lock().unwrap()[active_idx].reset();
+        lane.utterance.lock().unwrap().clear();
+        lane.pending.lock().unwrap().take();
+    }
+
+    /// Switches the lane to its reloaded recognizer when nothing is in progress: paused, or
+    /// idle with no wake being verified and no words heard since the last utterance. `force`
+    /// switches regardless. Returns whether it switched.
+    fn swap_reloaded(&self, lane: &Lane, force: bool) -> bool {
+        let mut active = lane.active.lock().unwrap();
+        let mut recs = lane.recognizers.lock().unwrap();
+        let mut quiet = || {
+            let json = serde_json::to_string(&recs[*active as usize].partial_result()).unwrap_or_default();
+            matches!(*self.state.lock().unwrap(), ListeningState::Idle)
+                && !*self.triggered.lock().unwrap()
+                && lane.pending.lock().unwrap().is_none()
+                && extract_partial_from_json(&json).is_none_or(|partial| partial.trim().is_empty())
+        };
+        if !(force || self.paused.load(Ordering::SeqCst) || quiet()) {
+            return false;
+        }
+        let slot = lane.reload_slot.lock().unwrap().take();
+        match slot {
+            Some(slot) => {
+                *active = slot;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Loads each `(lane, model dir)` into a fresh `Model` on a background thread. The new
+    /// recognizer goes into the idle slot and is switched in at the next utterance boundary;
+    /// the previous model is dropped once its last recognizer is replaced.
+    fn reload_models(&self, targets: Vec<(usize, String)>) {
+        let pipeline = self.clone();
+        std::thread::spawn(move || {
+            for (index, path) in targets {
+                let lane = &pipeline.lanes[index];
+                println!("Loading {} model from {}...", lane.language, path);
+                let Some(model) = Model::new(path.as_str()) else {
+                    let msg = format!("Failed to load Vosk model for '{}' at '{}'", lane.language, path);
+                    eprintln!("{}[ERR]", msg);
+                    pipeline.events.emit(Event::Error { message: msg });
+                    continue;
+                };
+                let model = Arc::new(model);
+                let sample_rate_hz = *pipeline.sample_rate.lock().unwrap();
+                let fresh = lane.fresh_recognizer(&model, sample_rate_hz);
+                *lane.model.lock().unwrap() = model.clone();
+                *lane.model_path.lock().unwrap() = path.clone();
+
+                let slot = 1 - *lane.active.lock().unwrap();
+                lane.recognizers.lock().unwrap()[slot as usize] = fresh;
+                lane.calls.lock().unwrap()[slot as usize] = 0;
+                *lane.reload_slot.lock().unwrap() = Some(slot);
+                // Usually taken at the next utterance boundary; a silent room has none.
+                let waiting = Instant::now();
+                while lane.reload_slot.lock().unwrap().is_some() {
+                    let overdue = waiting.elapsed() >= RELOAD_SWAP_TIMEOUT;
+                    if pipeline.swap_reloaded(lane, overdue) && overdue {
+                        println!("Switched to the reloaded {} model without waiting for a pause in speech", lane.language);
+                    }
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+
+                // The other slot still runs the previous model; replacing it releases that model.
+                let fresh = lane.fresh_recognizer(&model, sample_rate_hz);
+                let inactive = 1 - *lane.active.lock().unwrap() as usize;
+                drop(std::mem::replace(&mut lane.recognizers.lock().unwrap()[inactive], fresh));
+                lane.calls.lock().unwrap()[inactive] = 0;
+                drop(model);
+
+                println!("Reloaded {} model from {}\n[MODEL]({})", lane.language, path, path);
+                if pipeline.wake_oov != OovPolicy::Off {
+                    // Reading words.txt takes a while; don't hold up the audio thread meanwhile.
+                    let phrases = lane.wake_words.lock().unwrap().clone();
+                    lane.warn_out_of_vocabulary(&phrases);
+                }
+                pipeline.events.emit(Event::Model { language: lane.language.clone(), path });
+            }
+            pipeline.reloading.store(false, Ordering::SeqCst);
+        });
+    }
+
+    // "hey iris" with one model, "hey iris (en); hallo iris (de)" with several.
+    fn describe_wake_words(&self) -> String {
+        if let [lane] = self.lanes.as_slice() {
+            return lane.wake_words.lock().unwrap().join(", ");
+        }
+        self.lanes
+            .iter()
+            .map(|lane| format!("{} ({})", lane.wake_words.lock().unwrap().join(", "), lane.language))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+fn new_recognizer(model: &Model, sample_rate_hz: f32, setup: &RecognizerSetup) -> Recognizer {
+    let rec = match &setup.speaker_model {
+        Some(spk) => Recognizer::new_with_speaker(model, sample_rate_hz, spk),
+        None => Recognizer::new(model, sample_rate_hz),
+    };
+    let mut rec = rec.expect("Failed to create recognizer");
+    configure_recognizer(&mut rec, setup.word_timing);
+    rec
+}
+
+fn input_config(device: &Device) -> Result<(SupportedStreamConfig, StreamConfig), String> {
+    let supported_config = device
+        .default_input_config()
+        .map_err(|e| format!("Failed to get default input config: {:?}", e))?;
+
+    let mut config: StreamConfig = supported_config.clone().into();
+
+    if config.channels == 0 {
+        config.channels = 1;
+    }
+    Ok((supported_config, config))
+}
+
+/// Builds and starts an input stream on `device`, recreating the recognizers if its sample rate differs.
+fn open_stream(device: &Device, pipeline: &Pipeline) -> Result<(Stream, StreamConfig), String> {
+    let (supported_config, config) = input_config(device)?;
+    let sample_rate_hz = config.sample_rate.0 as f32;
+
+    {
+        let mut current = pipeline.sample_rate.lock().unwrap();
+        if *current != sample_rate_hz {
+            for lane in pipeline.lanes.iter() {
+                let model = lane.model();
+                let fresh = [
+                    lane.fresh_recognizer(&model, sample_rate_hz),
+                    lane.fresh_recognizer(&model, sample_rate_hz),
+                ];
+                *lane.recognizers.lock().unwrap() = fresh;
+                *lane.calls.lock().unwrap() = [0; 2];
+            }
+            if let Some(custom) = &pipeline.custom_wake {
+                custom.detector.lock().unwrap().set_sample_rate(sample_rate_hz as u32);
+            }
+            pipeline.meter.lock().unwrap().set_sample_rate(sample_rate_hz as u32);
+            if let Some(aec) = &pipeline.aec {
+                aec.set_sample_rate(sample_rate_hz as u32);
+            }
+            if let Some(dsp) = &pipeline.dsp {
+                dsp.lock().unwrap().set_sample_rate(sample_rate_hz as u32);
+            }
+            *current = sample_rate_hz;
+        }
+    }
+    if let Some(rec) = &pipeline.recorder {
+        rec.lock()
+            .unwrap()
+            .set_format(config.sample_rate.0, device.name().unwrap_or_default());
+    }
+
+    let stream = match supported_config.sample_format() {
+        SampleFormat::I16 => build_input_stream_i16(device, &config, pipeline.clone()),
+        SampleFormat::U16 => build_input_stream_u16(device, &config, pipeline.clone()),
+        SampleFormat::F32 => build_input_stream_f32(device, &config, pipeline.clone()),
+        other => return Err(format!("Unsupported sample format {:?}", other)),
+    }?;
+
+    stream
+        .play()
+        .map_err(|e| format!("Failed to start input stream: {}", e))?;
+    Ok((stream, config))
+}
+
+fn build_input_stream_i16(
+    device: &Device,
+    config: &StreamConfig,
+    pipeline: Pipeline,
+) -> Result<Stream, String> {
     let channels = config.channels as usize;
+    let err_flag = pipeline.err_flag.clone();
 
     let data_fn = move |data: &[i16], _: &cpal::InputCallbackInfo| {
         let mut pcm_mono: Vec<i16> = Vec::with_capacity(data.len() / channels + 1);
@@ -422,15 +1846,7 @@ fn build_input_stream_i16(
             }
         }
 
-        let active_idx = *active_recognizer.lock().unwrap() as usize;
-        let mut recs = recognizers.lock().unwrap();
-        create_waveform_match(
-            &mut recs[active_idx],
-            &pcm_mono,
-            &wake_words,
-            &triggered,
-            &state,
-        );
+        pipeline.process(&pcm_mono);
     };
 
     let err_fn = move |err: cpal::StreamError| {
@@ -441,20 +1857,16 @@ fn build_input_stream_i16(
 
     device
         .build_input_stream(config, data_fn, err_fn, None)
-        .expect("Failed to build input stream[ERR]")
+        .map_err(|e| format!("Failed to build input stream: {}", e))
 }
 
 fn build_input_stream_u16(
     device: &Device,
     config: &StreamConfig,
-    recognizers: Arc<Mutex<[Recognizer; 2]>>,
-    active_recognizer: Arc<Mutex<u8>>,
-    triggered: Arc<Mutex<bool>>,
-    wake_words: &'static [&'static str],
-    state: Arc<Mutex<ListeningState>>,
-    err_flag: Arc<Mutex<Option<String>>>,
-) -> Stream {
+    pipeline: Pipeline,
+) -> Result<Stream, String> {
     let channels = config.channels as usize;
+    let err_flag = pipeline.err_flag.clone();
 
     let data_fn = move |data: &[u16], _: &cpal::InputCallbackInfo| {
         let mut pcm_mono: Vec<i16> = Vec::with_capacity(data.len() / channels + 1);
@@ -474,15 +1886,7 @@ fn build_input_stream_u16(
             }
         }
 
-        let active_idx = *active_recognizer.lock().unwrap() as usize;
-        let mut recs = recognizers.lock().unwrap();
-        create_waveform_match(
-            &mut recs[active_idx],
-            &pcm_mono,
-            &wake_words,
-            &triggered,
-            &state,
-        );
+        pipeline.process(&pcm_mono);
     };
 
     let err_fn = move |err: cpal::StreamError| {
@@ -493,20 +1897,16 @@ fn build_input_stream_u16(
 
     device
         .build_input_stream(config, data_fn, err_fn, None)
-        .expect("Failed to build input stream[ERR]")
+        .map_err(|e| format!("Failed to build input stream: {}", e))
 }
 
 fn build_input_stream_f32(
     device: &Device,
     config: &StreamConfig,
-    recognizers: Arc<Mutex<[Recognizer; 2]>>,
-    active_recognizer: Arc<Mutex<u8>>,
-    triggered: Arc<Mutex<bool>>,
-    wake_words: &'static [&'static str],
-    state: Arc<Mutex<ListeningState>>,
-    err_flag: Arc<Mutex<Option<String>>>,
-) -> Stream {
+    pipeline: Pipeline,
+) -> Result<Stream, String> {
     let channels = config.channels as usize;
+    let err_flag = pipeline.err_flag.clone();
 
     let data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
         let mut pcm_mono: Vec<i16> = Vec::with_capacity(4096usize);
@@ -527,15 +1927,7 @@ fn build_input_stream_f32(
             }
         }
 
-        let active_idx = *active_recognizer.lock().unwrap() as usize;
-        let mut recs = recognizers.lock().unwrap();
-        create_waveform_match(
-            &mut recs[active_idx],
-            &pcm_mono,
-            &wake_words,
-            &triggered,
-            &state,
-        );
+        pipeline.process(&pcm_mono);
     };
 
     let err_fn = move |err: cpal::StreamError| {
@@ -546,7 +1938,7 @@ fn build_input_stream_f32(
 
     device
         .build_input_stream(config, data_fn, err_fn, None)
-        .expect("Failed to build input stream[ERR]")
+        .map_err(|e| format!("Failed to build input stream: {}", e))
 }
 
 fn extract_text_from_complete_json(result_json: &str) -> Option<String> {
@@ -556,6 +1948,26 @@ fn extract_text_from_complete_json(result_json: &str) -> Option<String> {
         .map(|s| s.to_string())
 }
 
+fn extract_partial_from_json(partial_json: &str) -> Option<String> {
+    let v: serde_json::Value = serde_json::from_str(partial_json).ok()?;
+    v.get("partial")
+        .and_then(|x| x.as_str())
+        .map(|s| s.trim().to_string())
+}
+
+fn extract_confidence_from_complete_json(result_json: &str) -> Option<f32> {
+    let v: serde_json::Value = serde_json::from_str(result_json).ok()?;
+    let words = v.get("result")?.as_array()?;
+    let confs: Vec<f64> = words
+        .iter()
+        .filter_map(|w| w.get("conf").and_then(|c| c.as_f64()))
+        .collect();
+    if confs.is_empty() {
+        return None;
+    }
+    Some((confs.iter().sum::<f64>() / confs.len() as f64) as f32)
+}
+
 fn contains_wake_word(text: &str, wake_words: &[&str]) -> Option<String> {
     let t = text.trim().to_lowercase();
     if t.is_empty() {
@@ -580,72 +1992,170 @@ fn is_just_wake_word(text: &str, wake_words: &[&str]) -> bool {
     wake_words.iter().any(|w| t == *w)
 }
 
+fn split_wake_and_command<'a>(full_command: &'a str, wake_words: &[&str]) -> (&'a str, &'a str) {
+    for wake_word in wake_words {
+        if let Some(rest) = full_command.strip_prefix(wake_word) {
+            return (&full_command[..wake_word.len()], rest.trim());
+        }
+    }
+    ("", full_command)
+}
+
+/// What a recognizer's final results act on.
+struct MatchContext<'a> {
+    wake_words: &'a [&'a str],
+    triggered: &'a Mutex<bool>,
+    state: &'a Mutex<ListeningState>,
+    recorder: Option<&'a Arc<Mutex<Recorder>>>,
+    lane: usize,
+    /// A wake candidate of this lane is still being verified: final results come back as
+    /// `MatchEvent::Held` instead of moving the state machine.
+    hold: bool,
+    /// In confirm mode a wake phrase from the recognizer needs a recent template match as well.
+    confirm: Option<&'a Mutex<Detector>>,
+    /// Offline runs report results themselves and keep stdout for their report.
+    quiet: bool,
+    itn: Option<&'a dyn itn::LanguageRules>,
+}
+
+impl MatchContext<'_> {
+    fn normalize(&self, command: &str) -> Option<String> {
+        self.itn.map(|rules| itn::normalize(rules, command))
+    }
+}
+
+/// Feeds one block to `recognizer`. `calls` counts the blocks decoded towards the next
+/// periodic cleanup reset and belongs to that recognizer alone, so the reset never lands in
+/// another recognizer's utterance.
 fn create_waveform_match(
     recognizer: &mut Recognizer,
+    calls: &mut u32,
     pcm_mono: &[i16],
-    wake_words: &[&str],
-    triggered: &Arc<Mutex<bool>>,
-    state: &Arc<Mutex<ListeningState>>,
-) {
-    match recognizer.accept_waveform(&pcm_mono) {
+    ctx: &MatchContext,
+    mut verify: Option<&mut WakeCheck>,
+) -> Option<MatchEvent> {
+    if let Some(check) = verify.as_mut() {
+        check.push(pcm_mono);
+    }
+    match recognizer.accept_waveform(pcm_mono) {
         Ok(DecodingState::Running) => {
             // Force periodic cleanup every ~1000 calls
-            static mut CALL_COUNT: u32 = 0;
-            unsafe {
-                CALL_COUNT += 1;
-                if CALL_COUNT % 1000 == 0 {
-                    let _ = recognizer.reset();
+            *calls += 1;
+            if *calls >= 1000 {
+                *calls = 0;
+                recognizer.reset();
+                if let Some(check) = verify.as_mut() {
+                    check.utterance.clear();
                 }
             }
+            None
         }
         Ok(_) => {
-            let complete = recognizer.result();
-            if let Ok(json) = serde_json::to_string(&complete) {
-                if let Some(text) = extract_text_from_complete_json(&json) {
-                    let current_state = state.lock().unwrap().clone();
-
-                    match current_state {
-                        ListeningState::Idle => {
-                            if let Some(full_command) = contains_wake_word(&text, &wake_words) {
-                                if is_just_wake_word(&text, &wake_words) {
-                                    // Just wake word detected, start pause timer
-                                    *state.lock().unwrap() = ListeningState::WakeDetected {
-                                        time: Instant::now(),
-                                    };
-                                } else {
-                                    // Full command in one go
-                                    println!("Full command: {command}\n[COMMAND](hey iris {command})", command=full_command);
-                                    if let Ok(mut t) = triggered.lock() {
-                                        *t = true;
-                                    }
-                                }
-                            }
-                        }
-                        ListeningState::WakeDetected { .. } => {
-                            // Any speech after wake word is treated as command
-                            if !text.trim().is_empty() {
-                                println!("Full command: hey iris {command}\\n[COMMAND]({command})", command=text.trim());
-                                if let Ok(mut t) = triggered.lock() {
-                                    *t = true;
-                                }
-                                *state.lock().unwrap() = ListeningState::Idle;
-
-                                // Reset triggered after a short delay
-                                // std::thread::spawn({
-                                //     let triggered = triggered.clone();
-                                //     move || {
-                                //         std::thread::sleep(Duration::from_millis(100));
-                                //         *triggered.lock().unwrap() = false;
-                                //     }
-                                // });
-                            }
-                        }
+            let event = match serde_json::to_string(&recognizer.result()) {
+                Ok(json) if ctx.hold => Some(MatchEvent::Held(json)),
+                Ok(json) => handle_result(&json, ctx, verify.as_deref()),
+                Err(_) => None,
+            };
+            recognizer.reset();
+            if let Some(check) = verify {
+                check.utterance.clear();
+            }
+            event
+        }
+        Err(_) => None,
+    }
+}
 
+/// Moves the listening state machine on a final recognizer result.
+fn handle_result(json: &str, ctx: &MatchContext, verify: Option<&WakeCheck>) -> Option<MatchEvent> {
+    let text = extract_text_from_complete_json(json)?;
+    let current_state = ctx.state.lock().unwrap().clone();
+    match current_state {
+        // Another language already took this utterance.
+        ListeningState::Idle if *ctx.triggered.lock().unwrap() => None,
+        ListeningState::Idle => {
+            if contains_wake_word(&text, ctx.wake_words).is_none() {
+                return Some(end_segment(ctx));
+            }
+            if let Some(detector) = ctx.confirm
+                && !detector.lock().unwrap().take_recent_hit(CONFIRM_WINDOW)
+            {
+                if !ctx.quiet {
+                    println!("Wake phrase not confirmed by the custom wake detector; ignored");
+                }
+                return Some(end_segment(ctx));
+            }
+            // A candidate only counts once the second stage agrees.
+            match verify.map(|check| check.verify(json, ctx.wake_words)) {
+                None | Some(Verdict::Accepted) => accept_wake(json, ctx),
+                Some(Verdict::Rejected(reason)) => {
+                    if !ctx.quiet {
+                        println!("Wake candidate '{}' rejected: {}", text.trim(), reason);
                     }
+                    Some(end_segment(ctx))
                 }
+                Some(Verdict::Pending(verdict)) => Some(MatchEvent::Verifying(PendingWake {
+                    json: json.to_string(),
+                    verdict,
+                    held: Vec::new(),
+                })),
             }
-            let _ = recognizer.reset();
         }
-        Err(_) => {}
+        ListeningState::WakeDetected { lane: Some(owner), .. } if owner != ctx.lane => None,
+        ListeningState::WakeDetected { .. } => {
+            // Any speech after wake word is treated as command
+            let command = text.trim();
+            if command.is_empty() {
+                return None;
+            }
+            if !ctx.quiet {
+                println!("Full command: hey iris {command}\\n[COMMAND]({command})", command=command);
+            }
+            *ctx.triggered.lock().unwrap() = true;
+            *ctx.state.lock().unwrap() = ListeningState::Idle;
+            if let Some(rec) = ctx.recorder {
+                rec.lock().unwrap().command(None, command, extract_confidence_from_complete_json(json));
+            }
+            let speaker = speaker::extract_speaker_from_complete_json(json).map(|(v, _)| v);
+            Some(MatchEvent::Command(command.to_string(), ctx.normalize(command), speaker))
+        }
+    }
+}
+
+/// Commits a verified wake candidate: a bare wake phrase starts waiting for the command,
+/// one followed by more words is the command.
+fn accept_wake(json: &str, ctx: &MatchContext) -> Option<MatchEvent> {
+    let text = extract_text_from_complete_json(json)?;
+    let full_command = contains_wake_word(&text, ctx.wake_words)?;
+    let confidence = extract_confidence_from_complete_json(json);
+    if is_just_wake_word(&text, ctx.wake_words) {
+        // Just wake word detected, start pause timer
+        *ctx.state.lock().unwrap() = ListeningState::WakeDetected { time: Instant::now(), lane: Some(ctx.lane) };
+        if let Some(rec) = ctx.recorder {
+            rec.lock().unwrap().wake(text.trim(), confidence);
+        }
+        Some(MatchEvent::Wake(text.trim().to_string()))
+    } else {
+        // Full command in one go
+        if !ctx.quiet {
+            println!("Full command: {command}\n[COMMAND](hey iris {command})", command=full_command);
+        }
+        *ctx.triggered.lock().unwrap() = true;
+        let (wake, command) = split_wake_and_command(&full_command, ctx.wake_words);
+        if let Some(rec) = ctx.recorder {
+            rec.lock().unwrap().command(Some(wake), command, confidence);
+        }
+        let speaker = speaker::extract_speaker_from_complete_json(json).map(|(v, _)| v);
+        Some(MatchEvent::Command(command.to_string(), ctx.normalize(command), speaker))
+    }
+}
+
+fn end_segment(ctx: &MatchContext) -> MatchEvent {
+    // Every language sees the same audio; only the first one closes segments.
+    if let Some(rec) = ctx.recorder
+        && ctx.lane == 0
+    {
+        rec.lock().unwrap().segment_end();
     }
+    MatchEvent::SegmentEnd
 }
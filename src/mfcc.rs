@@ -0,0 +1,275 @@
+//! Mel-frequency cepstral coefficients for template matching: 25 ms frames every 10 ms,
+//! 26 mel bands up to 8 kHz, 12 coefficients (c0 dropped; loudness is reported separately).
+
+use std::f32::consts::PI;
+
+const FRAME_MS: u32 = 25;
+const HOP_MS: u32 = 10;
+const MEL_BANDS: usize = 26;
+pub const COEFFS: usize = 12;
+const PRE_EMPHASIS: f32 = 0.97;
+const MAX_FREQ: f32 = 8000.0;
+
+pub struct Frame {
+    pub coeffs: [f32; COEFFS],
+    /// Frame level in dBFS.
+    pub db: f32,
+}
+
+pub struct Mfcc {
+    frame_len: usize,
+    hop: usize,
+    fft_len: usize,
+    window: Vec<f32>,
+    /// Per band: first FFT bin and the weights from there on.
+    filters: Vec<(usize, Vec<f32>)>,
+    /// Samples not yet covered by a full frame.
+    pending: Vec<f32>,
+    last_sample: f32,
+}
+
+impl Mfcc {
+    pub fn new(sample_rate: u32) -> Self {
+        let frame_len = (sample_rate * FRAME_MS / 1000) as usize;
+        let hop = (sample_rate * HOP_MS / 1000) as usize;
+        let fft_len = frame_len.next_power_of_two();
+        let window = (0..frame_len)
+            .map(|i| 0.54 - 0.46 * (2.0 * PI * i as f32 / (frame_len - 1) as f32).cos())
+            .collect();
+        Mfcc {
+            frame_len,
+            hop,
+            fft_len,
+            window,
+            filters: mel_filters(sample_rate as f32, fft_len),
+            pending: Vec::new(),
+            last_sample: 0.0,
+        }
+    }
+
+    /// Frames for every complete 25 ms window the new samples finish.
+    pub fn push(&mut self, pcm: &[i16]) -> Vec<Frame> {
+        for &s in pcm {
+            let x = s as f32 / 32768.0;
+            self.pending.push(x - PRE_EMPHASIS * self.last_sample);
+            self.last_sample = x;
+        }
+        let mut out = Vec::new();
+        let mut start = 0;
+        while start + self.frame_len <= self.pending.len() {
+            out.push(self.frame(&self.pending[start..start + self.frame_len]));
+            start += self.hop;
+        }
+        self.pending.drain(..start);
+        out
+    }
+
+    /// All frames of a clip, from a fresh state.
+    pub fn frames(sample_rate: u32, pcm: &[i16]) -> Vec<Frame> {
+        Mfcc::new(sample_rate).push(pcm)
+    }
+
+    fn frame(&self, samples: &[f32]) -> Frame {
+        let power = samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32;
+        let mut re: Vec<f32> = samples.iter().zip(&self.window).map(|(x, w)| x * w).collect();
+        re.resize(self.fft_len, 0.0);
+        let mut im = vec![0.0; self.fft_len];
+        fft(&mut re, &mut im);
+        let spectrum: Vec<f32> = (0..=self.fft_len / 2).map(|k| re[k] * re[k] + im[k] * im[k]).collect();
+
+        let log_mel: Vec<f32> = self
+            .filters
+            .iter()
+            .map(|(first, weights)| {
+                let energy: f32 = weights.iter().zip(&spectrum[*first..]).map(|(w, p)| w * p).sum();
+                energy.max(1e-10).ln()
+            })
+            .collect();
+        let mut coeffs = [0.0; COEFFS];
+        for (k, c) in coeffs.iter_mut().enumerate() {
+            let k = k + 1;
+            *c = log_mel
+                .iter()
+                .enumerate()
+                .map(|(m, e)| e * (PI * k as f32 * (m as f32 + 0.5) / MEL_BANDS as f32).cos())
+                .sum();
+        }
+        Frame { coeffs, db: 10.0 * power.max(1e-10).log10() }
+    }
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+// Triangular filters evenly spaced on the mel scale.
+fn mel_filters(sample_rate: f32, fft_len: usize) -> Vec<(usize, Vec<f32>)> {
+    let top = hz_to_mel(MAX_FREQ.min(sample_rate / 2.0));
+    let low = hz_to_mel(20.0);
+    let bin_hz = sample_rate / fft_len as f32;
+    let edges: Vec<f32> = (0..MEL_BANDS + 2)
+        .map(|i| mel_to_hz(low + (top - low) * i as f32 / (MEL_BANDS + 1) as f32) / bin_hz)
+        .collect();
+    edges
+        .windows(3)
+        .map(|e| {
+            let (left, center, right) = (e[0], e[1], e[2]);
+            let first = left.ceil() as usize;
+            let last = (right.floor() as usize).min(fft_len / 2);
+            let weights = (first..=last)
+                .map(|bin| {
+                    let b = bin as f32;
+                    if b <= center {
+                        (b - left) / (center - left).max(1e-6)
+                    } else {
+                        (right - b) / (right - center).max(1e-6)
+                    }
+                    .max(0.0)
+                })
+                .collect();
+            (first, weights)
+        })
+        .collect()
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two.
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for k in 0..len / 2 {
+            let (sin, cos) = (angle * k as f32).sin_cos();
+            for start in (0..n).step_by(len) {
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Inverse of [`fft`], through the forward one: conj(fft(conj(X))) / n.
+pub fn ifft(re: &mut [f32], im: &mut [f32]) {
+    for v in im.iter_mut() {
+        *v = -*v;
+    }
+    fft(re, im);
+    let scale = 1.0 / re.len() as f32;
+    for (r, i) in re.iter_mut().zip(im.iter_mut()) {
+        *r *= scale;
+        *i *= -scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(sample_rate: u32, hz: f32, len: usize, amplitude: f32) -> Vec<i16> {
+        (0..len)
+            .map(|i| (amplitude * 32767.0 * (2.0 * PI * hz * i as f32 / sample_rate as f32).sin()) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn fft_of_an_impulse_is_flat() {
+        let mut re = vec![0.0; 16];
+        let mut im = vec![0.0; 16];
+        re[0] = 1.0;
+        fft(&mut re, &mut im);
+        assert!(re.iter().all(|x| (x - 1.0).abs() < 1e-6));
+        assert!(im.iter().all(|x| x.abs() < 1e-6));
+    }
+
+    #[test]
+    fn fft_puts_a_cosine_in_its_bin() {
+        let n = 64;
+        let mut re: Vec<f32> = (0..n).map(|i| (2.0 * PI * 5.0 * i as f32 / n as f32).cos()).collect();
+        let mut im = vec![0.0; n];
+        fft(&mut re, &mut im);
+        let magnitude: Vec<f32> = re.iter().zip(&im).map(|(r, i)| (r * r + i * i).sqrt()).collect();
+        assert!((magnitude[5] - n as f32 / 2.0).abs() < 1e-3);
+        assert!((magnitude[n - 5] - n as f32 / 2.0).abs() < 1e-3);
+        let others = magnitude.iter().enumerate().filter(|(k, _)| *k != 5 && *k != n - 5);
+        assert!(others.map(|(_, m)| *m).fold(0.0, f32::max) < 1e-3);
+    }
+
+    #[test]
+    fn ifft_undoes_fft() {
+        let signal: Vec<f32> = (0..32).map(|i| ((i * 7) % 11) as f32 - 5.0).collect();
+        let mut re = signal.clone();
+        let mut im = vec![0.0; 32];
+        fft(&mut re, &mut im);
+        ifft(&mut re, &mut im);
+        assert!(re.iter().zip(&signal).all(|(a, b)| (a - b).abs() < 1e-4));
+        assert!(im.iter().all(|x| x.abs() < 1e-4));
+    }
+
+    #[test]
+    fn mel_filters_are_ordered_triangles_within_the_spectrum() {
+        let fft_len = 512;
+        let filters = mel_filters(16000.0, fft_len);
+        assert_eq!(filters.len(), MEL_BANDS);
+        let mut last_peak = 0;
+        for (first, weights) in &filters {
+            assert!(!weights.is_empty());
+            assert!(first + weights.len() - 1 <= fft_len / 2);
+            assert!(weights.iter().all(|w| (0.0..=1.0).contains(w)));
+            let peak = first + weights.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap().0;
+            assert!(peak >= last_peak);
+            last_peak = peak;
+        }
+        // Nothing above 8 kHz, even at higher sample rates.
+        let top = mel_filters(48000.0, 2048).last().map(|(first, w)| first + w.len()).unwrap();
+        assert!(top as f32 * 48000.0 / 2048.0 <= MAX_FREQ + 48000.0 / 2048.0);
+    }
+
+    #[test]
+    fn frames_every_hop_regardless_of_block_size() {
+        let pcm = tone(16000, 440.0, 16000, 0.5);
+        let whole = Mfcc::frames(16000, &pcm);
+        // 25 ms windows every 10 ms over one second.
+        assert_eq!(whole.len(), (16000 - 400) / 160 + 1);
+        let mut streaming = Mfcc::new(16000);
+        let pieces: Vec<Frame> = pcm.chunks(123).flat_map(|block| streaming.push(block)).collect();
+        assert_eq!(pieces.len(), whole.len());
+        for (a, b) in whole.iter().zip(&pieces) {
+            assert!(a.coeffs.iter().zip(&b.coeffs).all(|(x, y)| (x - y).abs() < 1e-3));
+        }
+    }
+
+    #[test]
+    fn reports_frame_levels_and_tells_tones_apart() {
+        let silence = Mfcc::frames(16000, &[0; 1600]);
+        assert!(silence.iter().all(|f| f.db <= -99.0));
+        // Pre-emphasis takes about 15 dB off a 440 Hz tone at -9 dBFS.
+        let loud = Mfcc::frames(16000, &tone(16000, 440.0, 1600, 0.5));
+        assert!(loud.iter().all(|f| f.db > -30.0));
+        let high = Mfcc::frames(16000, &tone(16000, 3000.0, 1600, 0.5));
+        let gap: f32 = loud[3].coeffs.iter().zip(&high[3].coeffs).map(|(a, b)| (a - b).abs()).sum();
+        assert!(gap > 1.0);
+    }
+}
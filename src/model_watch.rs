@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
+
+use crate::control::Control;
+
+/// Polls each `(language, model dir)` and requests a reload once a change has settled,
+/// i.e. the newest modification time is the same on two polls in a row.
+pub fn spawn(dirs: Vec<(String, PathBuf)>, interval: Duration, control: Sender<Control>) {
+    std::thread::spawn(move || {
+        let mut seen: Vec<Option<SystemTime>> = dirs.iter().map(|(_, d)| latest_mtime(d)).collect();
+        let mut pending: Vec<Option<SystemTime>> = vec![None; dirs.len()];
+        loop {
+            std::thread::sleep(interval);
+            for (i, (language, dir)) in dirs.iter().enumerate() {
+                let current = latest_mtime(dir);
+                if current.is_none() || current == seen[i] {
+                    pending[i] = None;
+                    continue;
+                }
+                // Still being written; wait for it to stop changing.
+                if pending[i] != current {
+                    pending[i] = current;
+                    continue;
+                }
+                seen[i] = current;
+                pending[i] = None;
+                println!("Model directory {} changed, reloading", dir.display());
+                let cmd = Control::ReloadModel { language: Some(language.clone()), path: None };
+                if control.send(cmd).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+fn latest_mtime(dir: &Path) -> Option<SystemTime> {
+    let mut latest = std::fs::metadata(dir).and_then(|m| m.modified()).ok()?;
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Some(latest);
+    };
+    for entry in entries.flatten() {
+        // Not following links: a symlink loop inside the model would recurse forever.
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let modified = if file_type.is_dir() {
+            latest_mtime(&entry.path())
+        } else {
+            entry.metadata().and_then(|m| m.modified()).ok()
+        };
+        if let Some(m) = modified {
+            latest = latest.max(m);
+        }
+    }
+    Some(latest)
+}
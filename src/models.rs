@@ -0,0 +1,451 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{arg_value, looks_like_vosk_model_dir};
+
+/// Vosk's published model list; `--model-index` points elsewhere (a local file works offline).
+const DEFAULT_INDEX: &str = "https://alphacephei.com/vosk/models/model-list.json";
+const DEFAULT_FILE: &str = "default";
+
+const USAGE: &str = "Usage: IrisVA model <command>\n  \
+    list                         installed models (* marks the default)\n  \
+    available [language]         models in the index\n  \
+    install <name|url|archive>   download, verify and extract a model\n  \
+    set-default <name>           model used when no --model is given\n\
+    Options: --models-dir <dir>, --model-index <file|url>, --sha256 <hex>, --md5 <hex>, --force true";
+
+struct IndexEntry {
+    name: String,
+    lang: String,
+    url: String,
+    md5: Option<String>,
+    sha256: Option<String>,
+    size: Option<String>,
+    obsolete: bool,
+}
+
+pub fn run(positionals: &[String], args: &[(String, String)]) -> Result<(), String> {
+    let dir = models_dir(args);
+    match positionals.first().map(String::as_str) {
+        Some("list") => list(&dir),
+        Some("available") => available(args, positionals.get(1).map(String::as_str)),
+        Some("install") => match positionals.get(1) {
+            Some(source) => install(&dir, source, args),
+            None => Err(USAGE.to_string()),
+        },
+        Some("set-default") => match positionals.get(1) {
+            Some(name) => set_default(&dir, name),
+            None => Err(USAGE.to_string()),
+        },
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// `--models-dir`, then `IRISVA_MODELS_DIR`, then `models` in the data directory.
+pub fn models_dir(args: &[(String, String)]) -> PathBuf {
+    if let Some(dir) = arg_value(args, "--models-dir") {
+        return PathBuf::from(dir);
+    }
+    if let Ok(dir) = std::env::var("IRISVA_MODELS_DIR") {
+        return PathBuf::from(dir);
+    }
+    data_dir().join("models")
+}
+
+/// Per-user data directory for installed models and enrolled speakers.
+pub fn data_dir() -> PathBuf {
+    #[cfg(windows)]
+    if let Ok(appdata) = std::env::var("APPDATA") {
+        return PathBuf::from(appdata).join("IrisVA");
+    }
+    if let Ok(data) = std::env::var("XDG_DATA_HOME") {
+        return PathBuf::from(data).join("irisva");
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        return PathBuf::from(home).join(".local/share/irisva");
+    }
+    PathBuf::from(".")
+}
+
+/// The model picked with `model set-default`, if it is still installed.
+pub fn default_model(args: &[(String, String)]) -> Option<PathBuf> {
+    let dir = models_dir(args);
+    let name = fs::read_to_string(dir.join(DEFAULT_FILE)).ok()?;
+    let path = dir.join(name.trim());
+    looks_like_vosk_model_dir(&path).then_some(path)
+}
+
+fn installed(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .map(|rd| {
+            rd.flatten()
+                .filter(|e| looks_like_vosk_model_dir(&e.path()))
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|n| !n.starts_with('.'))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+fn list(dir: &Path) -> Result<(), String> {
+    let names = installed(dir);
+    if names.is_empty() {
+        println!("No models installed in {}", dir.display());
+        return Ok(());
+    }
+    let default = fs::read_to_string(dir.join(DEFAULT_FILE)).unwrap_or_default();
+    println!("Models in {}:", dir.display());
+    for name in names {
+        let mark = if name == default.trim() { "*" } else { " " };
+        println!("{} {}", mark, name);
+    }
+    Ok(())
+}
+
+fn available(args: &[(String, String)], language: Option<&str>) -> Result<(), String> {
+    let index = load_index(args)?;
+    for e in index.iter().filter(|e| !e.obsolete) {
+        if language.is_some_and(|l| !e.lang.eq_ignore_ascii_case(l) && !e.lang.starts_with(l)) {
+            continue;
+        }
+        println!("{:<45} {:<8} {}", e.name, e.lang, e.size.as_deref().unwrap_or(""));
+    }
+    Ok(())
+}
+
+fn set_default(dir: &Path, name: &str) -> Result<(), String> {
+    check_name(name)?;
+    if !looks_like_vosk_model_dir(&dir.join(name)) {
+        return Err(format!("Model '{}' is not installed in {}", name, dir.display()));
+    }
+    fs::write(dir.join(DEFAULT_FILE), name)
+        .map_err(|e| format!("Failed to write {}: {}", dir.join(DEFAULT_FILE).display(), e))?;
+    println!("Default model: {}", name);
+    Ok(())
+}
+
+fn install(dir: &Path, source: &str, args: &[(String, String)]) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let mut md5 = arg_value(args, "--md5").map(str::to_lowercase);
+    let mut sha256 = arg_value(args, "--sha256").map(str::to_lowercase);
+    let local = Path::new(source);
+    let (name, url) = if local.is_file() {
+        (archive_stem(source), None)
+    } else if source.starts_with("http://") || source.starts_with("https://") || source.starts_with("file://") {
+        (archive_stem(source), Some(source.to_string()))
+    } else {
+        let index = load_index(args)?;
+        let entry = index
+            .into_iter()
+            .find(|e| e.name == source)
+            .ok_or_else(|| format!("No model named '{}' in the index (see `model available`)", source))?;
+        md5 = md5.or(entry.md5);
+        sha256 = sha256.or(entry.sha256);
+        (entry.name, Some(entry.url))
+    };
+    check_name(&name)?;
+
+    let target = dir.join(&name);
+    if target.exists() && arg_value(args, "--force") != Some("true") {
+        return Err(format!("{} already exists (use --force true to replace it)", target.display()));
+    }
+
+    let (archive, temporary) = match &url {
+        Some(url) => download(url, &dir.join(format!(".{}.download", name)))?,
+        None => (local.to_path_buf(), false),
+    };
+    let result = verify(&archive, md5.as_deref(), sha256.as_deref())
+        .and_then(|()| extract_model(&archive, dir, &name, &target));
+    // Only our own download; a local or file:// archive belongs to the user.
+    if temporary {
+        let _ = fs::remove_file(&archive);
+    }
+    result?;
+
+    println!("Installed {} to {}", name, target.display());
+    if fs::read_to_string(dir.join(DEFAULT_FILE)).is_err() {
+        set_default(dir, &name)?;
+    }
+    Ok(())
+}
+
+// Names end up as directories under the models dir, so they must stay a single component.
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("Invalid model name '{}'", name));
+    }
+    Ok(())
+}
+
+fn load_index(args: &[(String, String)]) -> Result<Vec<IndexEntry>, String> {
+    let source = arg_value(args, "--model-index").unwrap_or(DEFAULT_INDEX);
+    let raw = if source.starts_with("http://") || source.starts_with("https://") {
+        ureq::get(source)
+            .call()
+            .map_err(|e| format!("Failed to fetch model index {}: {}", source, e))?
+            .into_string()
+            .map_err(|e| format!("Failed to read model index {}: {}", source, e))?
+    } else {
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        fs::read_to_string(path).map_err(|e| format!("Failed to read model index {}: {}", path, e))?
+    };
+    let v: Value =
+        serde_json::from_str(&raw).map_err(|e| format!("Invalid model index {}: {}", source, e))?;
+    let items = v
+        .as_array()
+        .ok_or_else(|| format!("Model index {} must be a JSON array", source))?;
+    let text = |e: &Value, key: &str| e.get(key).and_then(|x| x.as_str()).map(str::to_string);
+    Ok(items
+        .iter()
+        .filter_map(|e| {
+            Some(IndexEntry {
+                name: text(e, "name")?,
+                url: text(e, "url")?,
+                lang: text(e, "lang").unwrap_or_default(),
+                md5: text(e, "md5").map(|h| h.to_lowercase()),
+                sha256: text(e, "sha256").map(|h| h.to_lowercase()),
+                size: text(e, "size_text"),
+                // The Vosk list spells booleans as strings.
+                obsolete: matches!(e.get("obsolete"), Some(Value::Bool(true)))
+                    || text(e, "obsolete").as_deref() == Some("true"),
+            })
+        })
+        .collect())
+}
+
+/// The archive path, and whether it is a temporary download to delete after installing.
+fn download(url: &str, dest: &Path) -> Result<(PathBuf, bool), String> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Ok((PathBuf::from(path), false));
+    }
+    println!("Downloading {}...", url);
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    let mut file =
+        File::create(dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    let bytes = io::copy(&mut response.into_reader(), &mut file)
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    println!("Downloaded {:.1} MB", bytes as f64 / (1024.0 * 1024.0));
+    Ok((dest.to_path_buf(), true))
+}
+
+fn verify(archive: &Path, md5: Option<&str>, sha256: Option<&str>) -> Result<(), String> {
+    if md5.is_none() && sha256.is_none() {
+        println!("No checksum available for {}; skipping verification", archive.display());
+        return Ok(());
+    }
+    let mut file =
+        File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?;
+    let mut md5_hasher = md5::Md5::new();
+    let mut sha_hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| format!("Failed to read {}: {}", archive.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        md5_hasher.update(&buf[..n]);
+        sha_hasher.update(&buf[..n]);
+    }
+    let checks = [
+        ("MD5", md5, hex(&md5_hasher.finalize())),
+        ("SHA-256", sha256, hex(&sha_hasher.finalize())),
+    ];
+    for (algo, expected, actual) in checks {
+        if let Some(expected) = expected
+            && expected != actual
+        {
+            return Err(format!(
+                "{} mismatch for {}: expected {}, got {}",
+                algo,
+                archive.display(),
+                expected,
+                actual
+            ));
+        }
+    }
+    println!("Checksum OK");
+    Ok(())
+}
+
+// Unpacks into a hidden staging directory and moves the model folder into place.
+fn extract_model(archive: &Path, dir: &Path, name: &str, target: &Path) -> Result<(), String> {
+    let staging = dir.join(format!(".{}.partial", name));
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(&staging).map_err(|e| format!("Failed to create {}: {}", staging.display(), e))?;
+
+    println!("Extracting {}...", archive.display());
+    let result = unpack(archive, &staging).and_then(|()| {
+        let root = find_model_root(&staging).ok_or_else(|| {
+            format!("{} does not contain a Vosk model (no am/graph/conf folders)", archive.display())
+        })?;
+        if target.exists() {
+            fs::remove_dir_all(target).map_err(|e| format!("Failed to remove {}: {}", target.display(), e))?;
+        }
+        fs::rename(&root, target).map_err(|e| format!("Failed to move model into {}: {}", target.display(), e))
+    });
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn unpack(archive: &Path, dest: &Path) -> Result<(), String> {
+    let mut file =
+        File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?;
+    let mut magic = [0u8; 4];
+    let n = file.read(&mut magic).unwrap_or(0);
+    let file = File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?;
+    let err = |e: &dyn std::fmt::Display| format!("Failed to extract {}: {}", archive.display(), e);
+    match &magic[..n] {
+        [b'P', b'K', 3, 4] => zip::ZipArchive::new(file)
+            .and_then(|mut z| z.extract(dest))
+            .map_err(|e| err(&e)),
+        [0x1f, 0x8b, ..] => tar::Archive::new(flate2::read::GzDecoder::new(file))
+            .unpack(dest)
+            .map_err(|e| err(&e)),
+        _ => Err(format!("{} is not a .zip or .tar.gz archive", archive.display())),
+    }
+}
+
+fn find_model_root(staging: &Path) -> Option<PathBuf> {
+    if looks_like_vosk_model_dir(staging) {
+        return Some(staging.to_path_buf());
+    }
+    fs::read_dir(staging)
+        .ok()?
+        .flatten()
+        .map(|e| e.path())
+        .find(|p| looks_like_vosk_model_dir(p))
+}
+
+// "vosk-model-small-en-us-0.15.zip" -> "vosk-model-small-en-us-0.15"
+fn archive_stem(source: &str) -> String {
+    let file = source.rsplit(['/', '\\']).next().unwrap_or(source);
+    [".tar.gz", ".tgz", ".zip"]
+        .iter()
+        .find_map(|ext| file.strip_suffix(ext))
+        .unwrap_or(file)
+        .to_string()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::scratch;
+    use crate::test_args::args;
+
+    // "abc"
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    // A .tar.gz holding `<name>/{am,graph,conf}` like the published models.
+    fn model_archive(dir: &Path, name: &str) -> PathBuf {
+        let tree = dir.join("tree").join(name);
+        for sub in ["am", "graph", "conf"] {
+            fs::create_dir_all(tree.join(sub)).unwrap();
+        }
+        fs::write(tree.join("conf/model.conf"), "--sample-frequency=16000\n").unwrap();
+        let path = dir.join(format!("{}.tar.gz", name));
+        let gz = flate2::write::GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::fast());
+        let mut tar = tar::Builder::new(gz);
+        tar.append_dir_all(name, &tree).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    fn write_index(dir: &Path, entries: &str) -> String {
+        let path = dir.join("index.json");
+        fs::write(&path, entries).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn verify_checks_the_given_digest() {
+        let dir = scratch("models", "verify");
+        let file = dir.join("abc");
+        fs::write(&file, "abc").unwrap();
+        assert!(verify(&file, None, Some(ABC_SHA256)).is_ok());
+        assert!(verify(&file, None, Some(&"0".repeat(64))).unwrap_err().contains("SHA-256 mismatch"));
+        assert!(verify(&file, None, None).is_ok());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn index_parsing_keeps_complete_entries() {
+        let dir = scratch("models", "index");
+        let index = write_index(
+            &dir,
+            r#"[
+                {"name": "small-en", "lang": "en-us", "url": "https://x/small-en.zip", "md5": "ABC", "obsolete": "false"},
+                {"name": "old-de", "lang": "de", "url": "https://x/old-de.zip", "obsolete": "true"},
+                {"name": "no-url", "lang": "fr"}
+            ]"#,
+        );
+        let entries = load_index(&args(&[("--model-index", &index)])).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "small-en");
+        assert_eq!(entries[0].md5.as_deref(), Some("abc"));
+        assert!(!entries[0].obsolete);
+        assert!(entries[1].obsolete);
+
+        let bad = write_index(&dir, r#"{"name": "x"}"#);
+        assert!(load_index(&args(&[("--model-index", &bad)])).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn install_from_file_url_keeps_the_source_archive() {
+        let dir = scratch("models", "install");
+        let archive = model_archive(&dir, "tiny-model");
+        let index = write_index(
+            &dir,
+            &format!(r#"[{{"name": "tiny-model", "lang": "en", "url": "file://{}"}}]"#, archive.display()),
+        );
+        let models = dir.join("models");
+        install(&models, "tiny-model", &args(&[("--model-index", &index)])).unwrap();
+        assert!(looks_like_vosk_model_dir(&models.join("tiny-model")));
+        assert!(archive.is_file());
+        assert_eq!(fs::read_to_string(models.join(DEFAULT_FILE)).unwrap(), "tiny-model");
+
+        // Installing again needs --force; a local path works the same way.
+        let source = archive.to_string_lossy().into_owned();
+        assert!(install(&models, &source, &[]).is_err());
+        install(&models, &source, &args(&[("--force", "true")])).unwrap();
+        assert!(archive.is_file());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn install_rejects_a_bad_checksum_and_unsafe_names() {
+        let dir = scratch("models", "reject");
+        let archive = model_archive(&dir, "tiny-model");
+        let models = dir.join("models");
+        let source = archive.to_string_lossy().into_owned();
+        let err = install(&models, &source, &args(&[("--sha256", &"0".repeat(64))])).unwrap_err();
+        assert!(err.contains("mismatch"));
+        assert!(!models.join("tiny-model").exists());
+
+        for name in ["../escape", "a/b", "..", ".hidden"] {
+            let index = write_index(
+                &dir,
+                &format!(r#"[{{"name": "{}", "url": "file://{}"}}]"#, name, archive.display()),
+            );
+            let err = install(&models, name, &args(&[("--model-index", &index)])).unwrap_err();
+            assert!(err.contains("Invalid model name"), "{}: {}", name, err);
+        }
+        assert!(!dir.join("escape").exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use rumqttc::{Client, Connection, Event as MqttEvent, LastWill, MqttOptions, Outgoing, Packet, QoS, Transport};
+use serde_json::Value;
+
+use crate::arg_value;
+use crate::control::Control;
+use crate::events::EventBus;
+
+// The event kinds published, each to `<prefix>/<kind>`.
+const PUBLISHED_EVENTS: &[&str] = &[
+    "wake",
+    "command",
+    "intent",
+    "state",
+    "audio-warning",
+    "mic-stalled",
+    "mic-dead",
+    "mic-recovered",
+];
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub ca_file: Option<String>,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Topics are `<prefix>/wake`, `<prefix>/command`, `<prefix>/intent`, `<prefix>/state`,
+    /// `<prefix>/audio-warning`, `<prefix>/mic-stalled`, `<prefix>/mic-dead`,
+    /// `<prefix>/mic-recovered`, `<prefix>/availability` and `<prefix>/control`.
+    pub prefix: String,
+    pub qos: QoS,
+}
+
+impl MqttConfig {
+    /// Returns `None` when no `--mqtt-url` is configured.
+    pub fn from_args(args: &[(String, String)]) -> Option<Result<Self, String>> {
+        let url = arg_value(args, "--mqtt-url")?;
+        Some(Self::parse(url, args))
+    }
+
+    fn parse(url: &str, args: &[(String, String)]) -> Result<Self, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("mqtts://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("mqtt://") {
+            (false, rest)
+        } else {
+            return Err(format!("Invalid --mqtt-url '{}' (expected mqtt://host[:port] or mqtts://...)", url));
+        };
+        let rest = rest.trim_end_matches('/');
+        // An IPv6 address is bracketed when a port follows: mqtt://[::1]:1883.
+        let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+            let (h, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| format!("Missing ']' in --mqtt-url '{}'", url))?;
+            match after {
+                "" => (h, None),
+                _ => match after.strip_prefix(':') {
+                    Some(p) => (h, Some(p)),
+                    None => return Err(format!("Invalid port in --mqtt-url '{}'", url)),
+                },
+            }
+        } else {
+            match rest.rsplit_once(':') {
+                // More than one colon without brackets is a bare IPv6 address.
+                Some((h, p)) if !h.contains(':') => (h, Some(p)),
+                _ => (rest, None),
+            }
+        };
+        let port = match port {
+            Some(p) => p.parse::<u16>().map_err(|_| format!("Invalid port in --mqtt-url '{}'", url))?,
+            None if tls => 8883,
+            None => 1883,
+        };
+        let host = host.to_string();
+        if host.is_empty() {
+            return Err(format!("Missing host in --mqtt-url '{}'", url));
+        }
+
+        let qos = match arg_value(args, "--mqtt-qos").unwrap_or("1") {
+            "0" => QoS::AtMostOnce,
+            "1" => QoS::AtLeastOnce,
+            "2" => QoS::ExactlyOnce,
+            other => return Err(format!("Invalid --mqtt-qos '{}' (expected 0, 1 or 2)", other)),
+        };
+        let site = arg_value(args, "--mqtt-site").unwrap_or("default");
+        let prefix = arg_value(args, "--mqtt-prefix")
+            .map(|p| p.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| format!("irisva/{}", site));
+
+        Ok(MqttConfig {
+            host,
+            port,
+            tls,
+            ca_file: arg_value(args, "--mqtt-ca").map(str::to_string),
+            client_id: arg_value(args, "--mqtt-client-id")
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("irisva-{}-{}", site, std::process::id())),
+            username: arg_value(args, "--mqtt-username").map(str::to_string),
+            password: arg_value(args, "--mqtt-password").map(str::to_string),
+            prefix,
+            qos,
+        })
+    }
+
+    fn topic(&self, leaf: &str) -> String {
+        format!("{}/{}", self.prefix, leaf)
+    }
+}
+
+/// Connects to the broker, publishes events and listens on `<prefix>/control`.
+pub fn spawn(config: MqttConfig, events: &Arc<EventBus>, control: Sender<Control>) -> Result<(), String> {
+    let mut opts = MqttOptions::new(config.client_id.clone(), config.host.clone(), config.port);
+    opts.set_keep_alive(Duration::from_secs(30));
+    // Brokers publish this for us if we drop off without a clean disconnect.
+    opts.set_last_will(LastWill::new(config.topic("availability"), "offline", QoS::AtLeastOnce, true));
+    if let Some(user) = &config.username {
+        opts.set_credentials(user.clone(), config.password.clone().unwrap_or_default());
+    }
+    if config.tls {
+        let transport = match &config.ca_file {
+            Some(path) => {
+                let ca = std::fs::read(path)
+                    .map_err(|e| format!("Failed to read --mqtt-ca {}: {}", path, e))?;
+                Transport::tls(ca, None, None)
+            }
+            None => Transport::tls_with_default_config(),
+        };
+        opts.set_transport(transport);
+    }
+
+    let (client, connection) = Client::new(opts, 64);
+    let config = Arc::new(config);
+
+    let rx = events.subscribe();
+    let publisher = client.clone();
+    let publish_config = config.clone();
+    events.track(std::thread::spawn(move || {
+        for event in rx {
+            let kind = event.get("type").and_then(|t| t.as_str()).unwrap_or("");
+            if !PUBLISHED_EVENTS.contains(&kind) {
+                continue;
+            }
+            // The latest state is retained so new subscribers see it immediately.
+            let retain = kind == "state";
+            let topic = publish_config.topic(kind);
+            if publisher
+                .publish(topic, publish_config.qos, retain, event.to_string())
+                .is_err()
+            {
+                return;
+            }
+        }
+        // The event bus closed: go offline cleanly, after everything queued so far.
+        let _ = publisher.publish(publish_config.topic("availability"), QoS::AtLeastOnce, true, "offline");
+        let _ = publisher.disconnect();
+    }));
+
+    events.track(std::thread::spawn(move || run_connection(connection, client, config, control)));
+    Ok(())
+}
+
+fn run_connection(mut connection: Connection, client: Client, config: Arc<MqttConfig>, control: Sender<Control>) {
+    let control_topic = config.topic("control");
+    for notification in connection.iter() {
+        match notification {
+            Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                println!("Connected to MQTT broker {}:{}", config.host, config.port);
+                // Non-blocking: this thread is the one draining the request queue.
+                let _ = client.try_publish(config.topic("availability"), QoS::AtLeastOnce, true, "online");
+                let _ = client.try_subscribe(control_topic.clone(), config.qos);
+            }
+            Ok(MqttEvent::Incoming(Packet::Publish(msg))) if msg.topic == control_topic => {
+                match parse_control_payload(&msg.payload) {
+                    Ok(cmd) => {
+                        if control.send(cmd).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("Ignoring MQTT control message: {}", e),
+                }
+            }
+            Ok(MqttEvent::Outgoing(Outgoing::Disconnect)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("MQTT connection error: {}[ERR]", e);
+                std::thread::sleep(RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+/// Accepts a JSON control message or a bare command name such as `pause`.
+fn parse_control_payload(payload: &[u8]) -> Result<Control, String> {
+    let text = std::str::from_utf8(payload).map_err(|_| "payload is not UTF-8".to_string())?;
+    let v = match serde_json::from_str::<Value>(text) {
+        Ok(v @ Value::Object(_)) => v,
+        _ => serde_json::json!({ "type": text.trim() }),
+    };
+    Control::from_json(&v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_args::args;
+
+    fn parse(url: &str, pairs: &[(&str, &str)]) -> Result<MqttConfig, String> {
+        MqttConfig::parse(url, &args(pairs))
+    }
+
+    fn address(url: &str) -> (String, u16, bool) {
+        let config = parse(url, &[]).unwrap();
+        (config.host, config.port, config.tls)
+    }
+
+    #[test]
+    fn parses_scheme_host_and_port() {
+        assert_eq!(address("mqtt://broker.lan"), ("broker.lan".to_string(), 1883, false));
+        assert_eq!(address("mqtts://broker.lan/"), ("broker.lan".to_string(), 8883, true));
+        assert_eq!(address("mqtt://10.0.0.2:1884"), ("10.0.0.2".to_string(), 1884, false));
+        assert_eq!(address("mqtts://broker.lan:443"), ("broker.lan".to_string(), 443, true));
+        for bad in ["broker.lan", "http://broker.lan", "mqtt://", "mqtt://:1883", "mqtt://host:port", "mqtt://host:70000"] {
+            assert!(parse(bad, &[]).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parses_ipv6_hosts() {
+        assert_eq!(address("mqtt://[::1]:1884"), ("::1".to_string(), 1884, false));
+        assert_eq!(address("mqtts://[fd00::2]"), ("fd00::2".to_string(), 8883, true));
+        assert_eq!(address("mqtt://::1"), ("::1".to_string(), 1883, false));
+        for bad in ["mqtt://[::1", "mqtt://[::1]1883", "mqtt://[]:1883"] {
+            assert!(parse(bad, &[]).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parses_qos() {
+        assert_eq!(parse("mqtt://h", &[]).unwrap().qos, QoS::AtLeastOnce);
+        assert_eq!(parse("mqtt://h", &[("--mqtt-qos", "0")]).unwrap().qos, QoS::AtMostOnce);
+        assert_eq!(parse("mqtt://h", &[("--mqtt-qos", "2")]).unwrap().qos, QoS::ExactlyOnce);
+        assert!(parse("mqtt://h", &[("--mqtt-qos", "3")]).is_err());
+    }
+
+    #[test]
+    fn topics_use_the_site_or_a_trimmed_prefix() {
+        assert_eq!(parse("mqtt://h", &[]).unwrap().topic("wake"), "irisva/default/wake");
+        let config = parse("mqtt://h", &[("--mqtt-site", "kitchen")]).unwrap();
+        assert_eq!(config.topic("state"), "irisva/kitchen/state");
+        assert!(config.client_id.starts_with("irisva-kitchen-"));
+        let config = parse("mqtt://h", &[("--mqtt-prefix", "home/voice//")]).unwrap();
+        assert_eq!(config.topic("command"), "home/voice/command");
+    }
+
+    #[test]
+    fn publishes_only_known_event_kinds() {
+        for kind in PUBLISHED_EVENTS {
+            assert!(crate::events::Event::KINDS.contains(kind), "{}", kind);
+        }
+    }
+}
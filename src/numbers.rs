@@ -0,0 +1,343 @@
+//! Spelled-out English numbers as Vosk emits them ("two hundred and five"), and the
+//! durations and clock times built from them.
+
+const UNITS: &[&str] = &[
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen",
+    "nineteen",
+];
+const TENS: &[&str] = &[
+    "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const SCALES: &[(&str, u64)] = &[
+    ("thousand", 1_000),
+    ("million", 1_000_000),
+    ("billion", 1_000_000_000),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Last {
+    Start,
+    Unit,
+    Ten,
+    Hundred,
+    Scale,
+    And,
+}
+
+pub fn unit_value(word: &str) -> Option<u64> {
+    UNITS.iter().position(|u| *u == word).map(|n| n as u64)
+}
+
+fn tens_value(word: &str) -> Option<u64> {
+    TENS.iter().position(|t| *t == word).map(|n| (n as u64 + 2) * 10)
+}
+
+/// Parses a whole-number phrase; every word must belong to it.
+/// A single digit string ("42") is accepted too.
+pub fn parse_integer(words: &[&str]) -> Option<u64> {
+    if let [w] = words
+        && let Ok(n) = w.parse::<u64>()
+    {
+        return Some(n);
+    }
+    let mut total = 0u64;
+    let mut current = 0u64;
+    let mut last = Last::Start;
+    let mut last_scale = u64::MAX;
+    for (i, &w) in words.iter().enumerate() {
+        let next_is_scale =
+            words.get(i + 1).is_some_and(|n| *n == "hundred" || SCALES.iter().any(|(s, _)| s == n));
+        if let Some(n) = unit_value(w) {
+            match last {
+                Last::Start | Last::Hundred | Last::Scale | Last::And => {}
+                Last::Ten if (1..10).contains(&n) => {}
+                _ => return None,
+            }
+            current += n;
+            last = Last::Unit;
+        } else if let Some(n) = tens_value(w) {
+            if !matches!(last, Last::Start | Last::Hundred | Last::Scale | Last::And) {
+                return None;
+            }
+            current += n;
+            last = Last::Ten;
+        } else if (w == "a" || w == "an") && last == Last::Start && next_is_scale {
+            current = 1;
+            last = Last::Unit;
+        } else if w == "hundred" {
+            if last != Last::Unit || current >= 100 {
+                return None;
+            }
+            current *= 100;
+            last = Last::Hundred;
+        } else if let Some((_, scale)) = SCALES.iter().find(|(s, _)| *s == w) {
+            // Scales only get smaller: "two million three thousand", not "two thousand one million".
+            if !matches!(last, Last::Unit | Last::Ten | Last::Hundred) || *scale >= last_scale {
+                return None;
+            }
+            last_scale = *scale;
+            total += current * scale;
+            current = 0;
+            last = Last::Scale;
+        } else if w == "and" && matches!(last, Last::Hundred | Last::Scale) {
+            last = Last::And;
+        } else {
+            return None;
+        }
+    }
+    match last {
+        Last::Start | Last::And => None,
+        _ => Some(total + current),
+    }
+}
+
+/// Like [`parse_integer`], plus decimals spoken digit by digit ("two point five").
+pub fn parse_number(words: &[&str]) -> Option<f64> {
+    parse_decimal(words)?.parse().ok()
+}
+
+/// The number as digits, keeping spoken trailing zeros ("two point five oh" -> "2.50").
+pub fn parse_decimal(words: &[&str]) -> Option<String> {
+    let Some(point) = words.iter().position(|w| *w == "point") else {
+        return parse_integer(words).map(|n| n.to_string());
+    };
+    let whole = if point == 0 {
+        0
+    } else {
+        parse_integer(&words[..point])?
+    };
+    let digits = &words[point + 1..];
+    if digits.is_empty() {
+        return None;
+    }
+    let mut out = format!("{}.", whole);
+    for w in digits {
+        match unit_value(w).filter(|d| *d < 10) {
+            Some(d) => out.push(char::from(b'0' + d as u8)),
+            None if *w == "oh" => out.push('0'),
+            None => return None,
+        }
+    }
+    Some(out)
+}
+
+fn unit_seconds(word: &str) -> Option<f64> {
+    match word {
+        "second" | "seconds" => Some(1.0),
+        "minute" | "minutes" => Some(60.0),
+        "hour" | "hours" => Some(3600.0),
+        "day" | "days" => Some(86400.0),
+        _ => None,
+    }
+}
+
+/// Seconds in phrases like "five minutes", "an hour and a half" or
+/// "two hours and ten minutes".
+pub fn parse_duration(words: &[&str]) -> Option<f64> {
+    let mut total = 0.0;
+    let mut last_unit = None;
+    let mut i = 0;
+    while i < words.len() {
+        match &words[i..] {
+            ["and", rest @ ..] if last_unit.is_some() && !rest.is_empty() => i += 1,
+            ["a", "half", ..] if last_unit.is_some() => {
+                total += last_unit? * 0.5;
+                last_unit = None;
+                i += 2;
+            }
+            ["half", "a" | "an", unit, ..] => {
+                let unit = unit_seconds(unit)?;
+                total += unit * 0.5;
+                last_unit = Some(unit);
+                i += 3;
+            }
+            rest => {
+                let at = rest.iter().position(|w| unit_seconds(w).is_some())?;
+                let unit = unit_seconds(rest[at])?;
+                let count = match &rest[..at] {
+                    ["a" | "an"] => 1.0,
+                    count => parse_number(count)?,
+                };
+                total += count * unit;
+                last_unit = Some(unit);
+                i += at + 1;
+            }
+        }
+    }
+    (total > 0.0).then_some(total)
+}
+
+/// Clock time as (hour, minute): "seven thirty pm", "half past six", "quarter to eight",
+/// "fourteen hundred", "noon".
+pub fn parse_time(words: &[&str]) -> Option<(u32, u32)> {
+    let (words, meridiem) = match words {
+        [rest @ .., "a", "m"] | [rest @ .., "am"] | [rest @ .., "a.m."] => (rest, Some(false)),
+        [rest @ .., "p", "m"] | [rest @ .., "pm"] | [rest @ .., "p.m."] => (rest, Some(true)),
+        _ => (words, None),
+    };
+    let words = match words {
+        [rest @ .., "o'clock"] | [rest @ .., "o", "clock"] => rest,
+        _ => words,
+    };
+    let (mut hour, minute) = match words {
+        ["noon"] | ["midday"] => (12, 0),
+        ["midnight"] => (0, 0),
+        ["half", "past", hour @ ..] => (hour_value(hour)?, 30),
+        ["quarter", "past", hour @ ..] | ["a", "quarter", "past", hour @ ..] => (hour_value(hour)?, 15),
+        ["quarter", "to", hour @ ..] | ["a", "quarter", "to", hour @ ..] => {
+            ((hour_value(hour)? + 23) % 24, 45)
+        }
+        _ => {
+            if let Some(at) = words.iter().position(|w| *w == "past" || *w == "to") {
+                let mut minutes = &words[..at];
+                if let [m @ .., "minute" | "minutes"] = minutes {
+                    minutes = m;
+                }
+                let m = parse_integer(minutes).filter(|m| (1..60).contains(m))? as u32;
+                let h = hour_value(&words[at + 1..])?;
+                if words[at] == "past" { (h, m) } else { ((h + 23) % 24, 60 - m) }
+            } else if let [hour @ .., "hundred"] = words {
+                (hour_value(hour)?, 0)
+            } else {
+                hour_and_minutes(words)?
+            }
+        }
+    };
+    match meridiem {
+        Some(true) if hour < 12 => hour += 12,
+        Some(false) if hour == 12 => hour = 0,
+        Some(_) if hour > 12 => return None,
+        _ => {}
+    }
+    Some((hour, minute))
+}
+
+fn hour_value(words: &[&str]) -> Option<u32> {
+    parse_integer(words).filter(|h| *h < 24).map(|h| h as u32)
+}
+
+// "seven", "seven thirty", "seven oh five", "twenty one fifteen".
+fn hour_and_minutes(words: &[&str]) -> Option<(u32, u32)> {
+    if let Some(h) = hour_value(words) {
+        return Some((h, 0));
+    }
+    for split in 1..words.len() {
+        let Some(h) = hour_value(&words[..split]) else {
+            continue;
+        };
+        let minute = match &words[split..] {
+            ["oh" | "o", digit] => parse_integer(&[*digit]).filter(|d| *d < 10),
+            rest => parse_integer(rest).filter(|m| (10..60).contains(m)),
+        };
+        if let Some(m) = minute {
+            return Some((h, m as u32));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<&str> {
+        text.split_whitespace().collect()
+    }
+
+    #[test]
+    fn integers() {
+        for (text, expected) in [
+            ("zero", 0),
+            ("seven", 7),
+            ("nineteen", 19),
+            ("forty two", 42),
+            ("a hundred", 100),
+            ("two hundred and five", 205),
+            ("nine hundred ninety nine", 999),
+            ("a thousand", 1_000),
+            ("one hundred thousand", 100_000),
+            ("two thousand and twenty six", 2_026),
+            ("three million four hundred thousand", 3_400_000),
+            ("one billion two million three thousand four", 1_002_003_004),
+            ("1234", 1_234),
+        ] {
+            assert_eq!(parse_integer(&words(text)), Some(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects() {
+        for text in [
+            "",
+            "and",
+            "five and",
+            "two five",
+            "twenty thirty",
+            "fifteen one",
+            "hundred",
+            "thousand",
+            "one hundred hundred",
+            "two thousand one million",
+            "one thousand two thousand",
+            "a",
+            "five apples",
+        ] {
+            assert_eq!(parse_integer(&words(text)), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn decimals() {
+        for (text, expected) in [
+            ("two point five", Some("2.5")),
+            ("two point five oh", Some("2.50")),
+            ("point seven five", Some("0.75")),
+            ("ninety nine point nine", Some("99.9")),
+            ("twelve", Some("12")),
+            ("two point", None),
+            ("two point twelve", None),
+        ] {
+            assert_eq!(parse_decimal(&words(text)).as_deref(), expected, "{}", text);
+        }
+        assert_eq!(parse_number(&words("one point two five")), Some(1.25));
+    }
+
+    #[test]
+    fn durations() {
+        for (text, expected) in [
+            ("five minutes", Some(300.0)),
+            ("a minute", Some(60.0)),
+            ("an hour and a half", Some(5400.0)),
+            ("half an hour", Some(1800.0)),
+            ("two hours and ten minutes", Some(7800.0)),
+            ("one point five seconds", Some(1.5)),
+            ("ninety seconds", Some(90.0)),
+            ("zero minutes", None),
+            ("minutes", None),
+            ("five", None),
+            ("five minutes and", None),
+        ] {
+            assert_eq!(parse_duration(&words(text)), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn times() {
+        for (text, expected) in [
+            ("seven thirty pm", Some((19, 30))),
+            ("seven oh five a m", Some((7, 5))),
+            ("twelve am", Some((0, 0))),
+            ("half past six", Some((6, 30))),
+            ("quarter to eight", Some((7, 45))),
+            ("ten to nine", Some((8, 50))),
+            ("fourteen hundred", Some((14, 0))),
+            ("eleven o'clock", Some((11, 0))),
+            ("noon", Some((12, 0))),
+            ("thirteen pm", None),
+            ("twenty five", None),
+        ] {
+            assert_eq!(parse_time(&words(text)), expected, "{}", text);
+        }
+    }
+}
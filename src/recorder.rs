@@ -0,0 +1,535 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::arg_value;
+use crate::wav::write_wav_i16;
+
+// Upper bound on how much audio we keep for a single not-yet-finalized utterance.
+const MAX_UTTERANCE: Duration = Duration::from_secs(30);
+
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    pub pre_padding: Duration,
+    pub post_padding: Duration,
+    pub max_files: Option<usize>,
+    pub max_bytes: Option<u64>,
+}
+
+impl RecorderConfig {
+    /// Returns `None` when recording is not enabled (no `--record-dir`).
+    pub fn from_args(args: &[(String, String)]) -> Option<Result<Self, String>> {
+        let dir = arg_value(args, "--record-dir")?;
+        Some(Self::parse(dir, args))
+    }
+
+    fn parse(dir: &str, args: &[(String, String)]) -> Result<Self, String> {
+        let ms = |key: &str, default: u64| -> Result<Duration, String> {
+            match arg_value(args, key) {
+                Some(v) => v
+                    .parse::<u64>()
+                    .map(Duration::from_millis)
+                    .map_err(|_| format!("Invalid value for {}: '{}' (expected milliseconds)", key, v)),
+                None => Ok(Duration::from_millis(default)),
+            }
+        };
+        let max_files = match arg_value(args, "--record-max-files") {
+            Some(v) => Some(
+                v.parse::<usize>()
+                    .map_err(|_| format!("Invalid value for --record-max-files: '{}'", v))?,
+            ),
+            None => None,
+        };
+        let max_bytes = match arg_value(args, "--record-max-bytes") {
+            Some(v) => Some(parse_byte_size(v).ok_or_else(|| {
+                format!("Invalid value for --record-max-bytes: '{}' (e.g. 500000000, 500M, 2G)", v)
+            })?),
+            None => None,
+        };
+        Ok(RecorderConfig {
+            dir: PathBuf::from(dir),
+            pre_padding: ms("--record-pre-ms", 500)?,
+            post_padding: ms("--record-post-ms", 300)?,
+            max_files,
+            max_bytes,
+        })
+    }
+}
+
+fn parse_byte_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, mult) = match s.char_indices().last()? {
+        (i, 'k' | 'K') => (&s[..i], 1 << 10),
+        (i, 'm' | 'M') => (&s[..i], 1 << 20),
+        (i, 'g' | 'G') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    num.trim().parse::<u64>().ok().map(|n| n * mult)
+}
+
+struct Capture {
+    id: u64,
+    started: SystemTime,
+    samples: Vec<i16>,
+    pre_samples: usize,
+    wake: Option<String>,
+    command: Option<String>,
+    conf_sum: f32,
+    conf_count: u32,
+    post_remaining: Option<usize>,
+}
+
+struct Clip {
+    id: u64,
+    started: SystemTime,
+    sample_rate: u32,
+    device: String,
+    samples: Vec<i16>,
+    pre_samples: usize,
+    post_samples: usize,
+    wake: Option<String>,
+    command: Option<String>,
+    confidence: Option<f32>,
+}
+
+/// Keeps a short audio history and writes each wake (+ command) segment to
+/// `<dir>/<timestamp>_<event id>.wav` with a JSON sidecar next to it.
+pub struct Recorder {
+    sample_rate: u32,
+    device: String,
+    pre_padding: Duration,
+    post_padding: Duration,
+    pre_samples: usize,
+    post_samples: usize,
+    max_utterance: usize,
+    // Audio before the current utterance, used as pre-padding.
+    history: VecDeque<i16>,
+    // Audio since the recognizer last finalized a result.
+    utterance: Vec<i16>,
+    capture: Option<Capture>,
+    next_id: u64,
+    writer: Sender<Clip>,
+    writer_thread: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub fn new(config: RecorderConfig, sample_rate: u32, device: String) -> Result<Self, String> {
+        fs::create_dir_all(&config.dir).map_err(|e| {
+            format!("Failed to create recording directory '{}': {}", config.dir.display(), e)
+        })?;
+        let (pre_padding, post_padding) = (config.pre_padding, config.post_padding);
+
+        let (tx, rx) = mpsc::channel::<Clip>();
+        let writer_thread = std::thread::spawn(move || {
+            for clip in rx {
+                if let Err(e) = write_clip(&config.dir, &clip) {
+                    eprintln!("Failed to save recording {}: {}[ERR]", clip.id, e);
+                    continue;
+                }
+                enforce_retention(&config.dir, config.max_files, config.max_bytes);
+            }
+        });
+
+        let mut recorder = Recorder {
+            sample_rate,
+            device,
+            pre_padding,
+            post_padding,
+            pre_samples: 0,
+            post_samples: 0,
+            max_utterance: 0,
+            history: VecDeque::new(),
+            utterance: Vec::new(),
+            capture: None,
+            next_id: 1,
+            writer: tx,
+            writer_thread: Some(writer_thread),
+        };
+        recorder.set_format(sample_rate, recorder.device.clone());
+        Ok(recorder)
+    }
+
+    /// Called when the input device (and possibly its sample rate) changes.
+    pub fn set_format(&mut self, sample_rate: u32, device: String) {
+        let samples_for = |d: Duration| (d.as_millis() as u64 * sample_rate as u64 / 1000) as usize;
+        if sample_rate != self.sample_rate {
+            // Buffered audio at the old rate can't be mixed with the new one.
+            self.capture = None;
+            self.history.clear();
+            self.utterance.clear();
+        }
+        self.sample_rate = sample_rate;
+        self.device = device;
+        self.pre_samples = samples_for(self.pre_padding);
+        self.post_samples = samples_for(self.post_padding);
+        self.max_utterance = samples_for(MAX_UTTERANCE);
+    }
+
+    /// Feeds mono audio; call before handing the same samples to the recognizer.
+    pub fn push(&mut self, pcm_mono: &[i16]) {
+        if let Some(cap) = &mut self.capture {
+            cap.samples.extend_from_slice(pcm_mono);
+            if let Some(rem) = &mut cap.post_remaining {
+                *rem = rem.saturating_sub(pcm_mono.len());
+                if *rem == 0 {
+                    self.finish();
+                }
+            }
+            return;
+        }
+        self.utterance.extend_from_slice(pcm_mono);
+        if self.utterance.len() > self.max_utterance {
+            let excess = self.utterance.len() - self.max_utterance;
+            let drained: Vec<i16> = self.utterance.drain(..excess).collect();
+            self.push_history(&drained);
+        }
+    }
+
+    /// The recognizer finalized a result that was neither a wake nor a command.
+    pub fn segment_end(&mut self) {
+        if self.capture.is_some() {
+            return;
+        }
+        let utterance = std::mem::take(&mut self.utterance);
+        self.push_history(&utterance);
+    }
+
+    /// A wake phrase was heard; starts capturing (with pre-padding) if not already.
+    pub fn wake(&mut self, wake: &str, confidence: Option<f32>) {
+        self.start(Some(wake), confidence);
+    }
+
+    fn start(&mut self, wake: Option<&str>, confidence: Option<f32>) {
+        if self.capture.is_some() {
+            return;
+        }
+        let mut samples: Vec<i16> = self.history.drain(..).collect();
+        let pre_samples = samples.len();
+        samples.append(&mut self.utterance);
+        let mut cap = Capture {
+            id: self.next_id,
+            started: SystemTime::now(),
+            samples,
+            pre_samples,
+            wake: wake.map(str::to_string),
+            command: None,
+            conf_sum: 0.0,
+            conf_count: 0,
+            post_remaining: None,
+        };
+        if let Some(c) = confidence {
+            cap.conf_sum += c;
+            cap.conf_count += 1;
+        }
+        self.next_id += 1;
+        self.capture = Some(cap);
+    }
+
+    /// A command completed the segment; the clip is written once post-padding is collected.
+    /// `wake` is `None` when no phrase was spoken, e.g. after a forced wake.
+    pub fn command(&mut self, wake: Option<&str>, command: &str, confidence: Option<f32>) {
+        self.start(wake, None);
+        let post = self.post_samples;
+        if let Some(cap) = &mut self.capture {
+            cap.command = Some(command.to_string());
+            if let Some(c) = confidence {
+                cap.conf_sum += c;
+                cap.conf_count += 1;
+            }
+            cap.post_remaining = Some(post);
+        }
+        if post == 0 {
+            self.finish();
+        }
+    }
+
+    /// The wake timed out without a command; save what we have.
+    pub fn abandon(&mut self) {
+        if self.capture.as_ref().is_some_and(|c| c.post_remaining.is_none()) {
+            self.finish();
+        }
+    }
+
+    /// Saves any capture in progress and waits until every queued clip is on disk.
+    pub fn close(&mut self) {
+        self.finish();
+        // Replacing the sender ends the writer's loop once the queue is drained.
+        self.writer = mpsc::channel().0;
+        if let Some(thread) = self.writer_thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn push_history(&mut self, pcm: &[i16]) {
+        if self.pre_samples == 0 {
+            return;
+        }
+        let skip = pcm.len().saturating_sub(self.pre_samples);
+        self.history.extend(&pcm[skip..]);
+        while self.history.len() > self.pre_samples {
+            self.history.pop_front();
+        }
+    }
+
+    fn finish(&mut self) {
+        let Some(cap) = self.capture.take() else {
+            return;
+        };
+        let tail_start = cap.samples.len().saturating_sub(self.pre_samples);
+        self.history.clear();
+        self.history.extend(&cap.samples[tail_start..]);
+        self.utterance.clear();
+
+        let post_samples = if cap.command.is_some() { self.post_samples } else { 0 };
+        let confidence = (cap.conf_count > 0).then(|| cap.conf_sum / cap.conf_count as f32);
+        let _ = self.writer.send(Clip {
+            id: cap.id,
+            started: cap.started,
+            sample_rate: self.sample_rate,
+            device: self.device.clone(),
+            samples: cap.samples,
+            pre_samples: cap.pre_samples,
+            post_samples,
+            wake: cap.wake,
+            command: cap.command,
+            confidence,
+        });
+    }
+}
+
+fn write_clip(dir: &Path, clip: &Clip) -> std::io::Result<()> {
+    let sample_rate = clip.sample_rate;
+    let stem = format!("{}_{:06}", file_stamp(clip.started), clip.id);
+    let wav_path = dir.join(format!("{}.wav", stem));
+    write_wav_i16(&wav_path, sample_rate, &clip.samples)?;
+
+    let transcript = [clip.wake.as_deref(), clip.command.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let ms = |n: usize| n as u64 * 1000 / sample_rate as u64;
+    let sidecar = serde_json::json!({
+        "event_id": clip.id,
+        "timestamp": iso_stamp(clip.started),
+        "device": clip.device,
+        "sample_rate": sample_rate,
+        "transcript": transcript,
+        "wake": clip.wake,
+        "command": clip.command,
+        "confidence": clip.confidence,
+        "pre_padding_ms": ms(clip.pre_samples),
+        "post_padding_ms": ms(clip.post_samples),
+        "duration_ms": ms(clip.samples.len()),
+        "audio": wav_path.file_name().map(|n| n.to_string_lossy().into_owned()),
+    });
+    let json = serde_json::to_string_pretty(&sidecar).map_err(std::io::Error::other)?;
+    fs::write(dir.join(format!("{}.json", stem)), json)
+}
+
+/// Deletes the oldest clips (and their sidecars) until both caps are satisfied. Only files named
+/// like the recorder's own output are considered, so other audio in the directory is left alone.
+fn enforce_retention(dir: &Path, max_files: Option<usize>, max_bytes: Option<u64>) {
+    if max_files.is_none() && max_bytes.is_none() {
+        return;
+    }
+    let Ok(rd) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut clips: Vec<(PathBuf, u64)> = Vec::new();
+    for e in rd.flatten() {
+        let p = e.path();
+        if p.file_name().and_then(|n| n.to_str()).is_some_and(is_clip_name) {
+            let sidecar = p.with_extension("json");
+            let size = file_len(&p) + file_len(&sidecar);
+            clips.push((p, size));
+        }
+    }
+    // File names start with a UTC timestamp, so lexical order is chronological.
+    clips.sort();
+
+    let mut total: u64 = clips.iter().map(|(_, s)| s).sum();
+    let mut count = clips.len();
+    for (path, size) in clips {
+        let over_count = max_files.is_some_and(|m| count > m);
+        let over_bytes = max_bytes.is_some_and(|m| total > m);
+        if !over_count && !over_bytes {
+            break;
+        }
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("json"));
+        count -= 1;
+        total = total.saturating_sub(size);
+    }
+}
+
+// "20250101-120000.000_000042.wav", as written by `write_clip`.
+fn is_clip_name(name: &str) -> bool {
+    let Some((stamp, id)) = name.strip_suffix(".wav").and_then(|s| s.split_once('_')) else {
+        return false;
+    };
+    let digits = |s: &[u8]| !s.is_empty() && s.iter().all(u8::is_ascii_digit);
+    let stamp = stamp.as_bytes();
+    stamp.len() == 19
+        && stamp[8] == b'-'
+        && stamp[15] == b'.'
+        && digits(&stamp[..8])
+        && digits(&stamp[9..15])
+        && digits(&stamp[16..])
+        && id.len() >= 6
+        && digits(id.as_bytes())
+}
+
+fn file_len(p: &Path) -> u64 {
+    fs::metadata(p).map(|m| m.len()).unwrap_or(0)
+}
+
+// (year, month, day, hour, minute, second, millis) in UTC.
+fn utc_parts(t: SystemTime) -> (i64, u32, u32, u32, u32, u32, u32) {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs() as i64;
+    let days = secs.div_euclid(86_400);
+    let sod = secs.rem_euclid(86_400) as u32;
+
+    // Civil-from-days (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, sod / 3600, sod / 60 % 60, sod % 60, d.subsec_millis())
+}
+
+fn file_stamp(t: SystemTime) -> String {
+    let (y, mo, d, h, mi, s, ms) = utc_parts(t);
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}.{:03}", y, mo, d, h, mi, s, ms)
+}
+
+pub fn iso_stamp(t: SystemTime) -> String {
+    let (y, mo, d, h, mi, s, ms) = utc_parts(t);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", y, mo, d, h, mi, s, ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::scratch;
+
+    fn config(dir: &Path) -> RecorderConfig {
+        RecorderConfig {
+            dir: dir.to_path_buf(),
+            pre_padding: Duration::from_millis(100),
+            post_padding: Duration::from_millis(100),
+            max_files: None,
+            max_bytes: None,
+        }
+    }
+
+    fn clip_stems(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|n| is_clip_name(n))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn parses_byte_sizes() {
+        assert_eq!(parse_byte_size("500"), Some(500));
+        assert_eq!(parse_byte_size("2k"), Some(2048));
+        assert_eq!(parse_byte_size("500M"), Some(500 << 20));
+        assert_eq!(parse_byte_size(" 1 G "), Some(1 << 30));
+        assert_eq!(parse_byte_size("lots"), None);
+    }
+
+    #[test]
+    fn stamps_are_utc() {
+        let t = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        assert_eq!(file_stamp(t), "20231114-221320.123");
+        assert_eq!(iso_stamp(t), "2023-11-14T22:13:20.123Z");
+    }
+
+    #[test]
+    fn recognizes_only_its_own_file_names() {
+        assert!(is_clip_name("20231114-221320.123_000042.wav"));
+        assert!(is_clip_name("20231114-221320.123_1234567.wav"));
+        assert!(!is_clip_name("20231114-221320.123_000042.json"));
+        assert!(!is_clip_name("song.wav"));
+        assert!(!is_clip_name("take_000001.wav"));
+        assert!(!is_clip_name("20231114-221320_000042.wav"));
+    }
+
+    #[test]
+    fn retention_leaves_other_audio_alone() {
+        let dir = scratch("recorder", "retention");
+        for name in ["20230101-000000.000_000001", "20230102-000000.000_000002", "20230103-000000.000_000003"] {
+            fs::write(dir.join(format!("{}.wav", name)), [0u8; 100]).unwrap();
+            fs::write(dir.join(format!("{}.json", name)), "{}").unwrap();
+        }
+        fs::write(dir.join("00-my-song.wav"), [0u8; 100]).unwrap();
+        fs::write(dir.join("00-my-song.json"), "{}").unwrap();
+
+        enforce_retention(&dir, Some(1), None);
+        assert_eq!(clip_stems(&dir), vec!["20230103-000000.000_000003.wav"]);
+        assert!(dir.join("20230103-000000.000_000003.json").exists());
+        assert!(!dir.join("20230101-000000.000_000001.json").exists());
+        assert!(dir.join("00-my-song.wav").exists());
+        assert!(dir.join("00-my-song.json").exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn writes_wake_and_command_with_padding() {
+        let dir = scratch("recorder", "clip");
+        let mut recorder = Recorder::new(config(&dir), 1000, "mic".to_string()).unwrap();
+        recorder.push(&[1; 300]);
+        recorder.segment_end();
+        recorder.push(&[2; 200]);
+        recorder.wake("hey iris", Some(0.9));
+        recorder.push(&[3; 200]);
+        recorder.command(Some("hey iris"), "lights on", Some(0.7));
+        recorder.push(&[4; 150]);
+        recorder.close();
+
+        let clips = clip_stems(&dir);
+        assert_eq!(clips.len(), 1);
+        let sidecar = dir.join(&clips[0]).with_extension("json");
+        let meta: serde_json::Value = serde_json::from_str(&fs::read_to_string(sidecar).unwrap()).unwrap();
+        assert_eq!(meta["transcript"], "hey iris lights on");
+        assert_eq!(meta["pre_padding_ms"], 100);
+        assert_eq!(meta["post_padding_ms"], 100);
+        // 100 ms of history, the wake utterance, the command, then the 150 samples pushed
+        // after the command (the clip closes once 100 of them have arrived).
+        assert_eq!(meta["duration_ms"], 100 + 200 + 200 + 150);
+        assert!((meta["confidence"].as_f64().unwrap() - 0.8).abs() < 1e-6);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn command_without_a_wake_phrase_has_a_clean_transcript() {
+        let dir = scratch("recorder", "forced");
+        let mut recorder = Recorder::new(config(&dir), 1000, "mic".to_string()).unwrap();
+        recorder.push(&[3; 200]);
+        recorder.command(None, "lights on", None);
+        recorder.push(&[4; 150]);
+        recorder.close();
+
+        let clips = clip_stems(&dir);
+        let sidecar = dir.join(&clips[0]).with_extension("json");
+        let meta: serde_json::Value = serde_json::from_str(&fs::read_to_string(sidecar).unwrap()).unwrap();
+        assert_eq!(meta["transcript"], "lights on");
+        assert_eq!(meta["wake"], serde_json::Value::Null);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
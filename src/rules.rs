@@ -0,0 +1,526 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::events::{Event, EventBus};
+
+/// Rules file, e.g.
+/// ```json
+/// [
+///   {"name": "lights", "glob": "turn * the lights", "run": ["lightctl", "{1}"], "cooldown_ms": 2000},
+///   {"regex": "set a timer for (?P<minutes>\\d+) minutes", "intent": "timer"},
+///   {"exact": "stop", "fifo": "/run/player.fifo", "write": "stop\n"},
+///   {"exact": "unlock the front door", "run": ["doorctl", "unlock"], "speakers": ["alice", "bob"]}
+/// ]
+/// ```
+/// Rules match the command's normalized text ("set a timer for 25 minutes"), or the raw
+/// recognizer output ("twenty five") when normalization is off, so a digit pattern matches
+/// unless `--itn off` is given or the language has no normalization rules. The first
+/// matching rule wins. `{name}`/`{1}` placeholders expand to captures,
+/// `{text}` to the whole command. A rule with `speakers` only runs for a verified,
+/// enrolled voice on that list.
+pub struct Rules {
+    rules: Vec<Rule>,
+    dry_run: bool,
+}
+
+struct Rule {
+    name: String,
+    pattern: Pattern,
+    action: Action,
+    cooldown: Duration,
+    last_fired: Option<Instant>,
+    speakers: Option<Vec<String>>,
+}
+
+enum Pattern {
+    Exact(String),
+    /// Globs are compiled to a regex; each `*` becomes a numbered capture.
+    Glob(Regex),
+    Regex(Regex),
+}
+
+enum Action {
+    /// Program and arguments; run directly, never through a shell.
+    Run(Vec<String>),
+    Fifo { path: PathBuf, write: String },
+    Intent(String),
+}
+
+impl Rules {
+    pub fn load(path: &Path, dry_run: bool) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read rules {}: {}", path.display(), e))?;
+        let v: Value = serde_json::from_str(&raw)
+            .map_err(|e| format!("Invalid rules {}: {}", path.display(), e))?;
+        let items = match &v {
+            Value::Array(items) => items,
+            Value::Object(obj) => obj
+                .get("rules")
+                .and_then(|r| r.as_array())
+                .ok_or_else(|| format!("Rules {} needs a \"rules\" array", path.display()))?,
+            _ => return Err(format!("Rules {} must be a JSON array", path.display())),
+        };
+        let rules = items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                Rule::parse(item, i).map_err(|e| format!("Rules {}: rule {}: {}", path.display(), i + 1, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Rules { rules, dry_run })
+    }
+
+    pub fn count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Runs the first rule matching `text`, unless it is still cooling down or restricted to
+    /// other voices. `speaker` is the verified speaker of the command, if any.
+    pub fn dispatch(&mut self, text: &str, speaker: Option<&str>, events: &EventBus) {
+        let dry_run = self.dry_run;
+        let Some((rule, captures)) = self
+            .rules
+            .iter_mut()
+            .find_map(|r| r.pattern.captures(text).map(|c| (r, c)))
+        else {
+            return;
+        };
+        // A refused match doesn't fall through to a broader rule further down.
+        if let Some(allowed) = &rule.speakers
+            && !speaker.is_some_and(|s| allowed.iter().any(|a| a == s))
+        {
+            let msg = format!(
+                "Rule '{}' refused: speaker {} is not allowed",
+                rule.name,
+                speaker.map(|s| format!("'{}'", s)).unwrap_or_else(|| "unverified".to_string())
+            );
+            println!("{}", msg);
+            events.emit(Event::Error { message: msg });
+            return;
+        }
+        let now = Instant::now();
+        if let Some(last) = rule.last_fired
+            && now.duration_since(last) < rule.cooldown
+        {
+            println!("Rule '{}' matched but is cooling down", rule.name);
+            return;
+        }
+        rule.last_fired = Some(now);
+
+        let fill = |template: &str| expand(template, text, &captures);
+        let prefix = if dry_run { "(dry run) " } else { "" };
+        match &rule.action {
+            Action::Run(argv) => {
+                let argv: Vec<String> = argv.iter().map(|a| fill(a)).collect();
+                println!("{}Rule '{}': run {}", prefix, rule.name, argv.join(" "));
+                if !dry_run {
+                    run_program(&rule.name, argv);
+                }
+            }
+            Action::Fifo { path, write } => {
+                let payload = fill(write);
+                println!("{}Rule '{}': write {:?} to {}", prefix, rule.name, payload, path.display());
+                if !dry_run {
+                    write_fifo(path.clone(), payload);
+                }
+            }
+            Action::Intent(name) => {
+                println!("{}Rule '{}': intent {}", prefix, rule.name, name);
+                if !dry_run {
+                    events.emit(Event::Intent {
+                        name: name.clone(),
+                        slots: captures.into_iter().filter(|(k, _)| k != "0").collect(),
+                        text: text.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Rule {
+    fn parse(v: &Value, index: usize) -> Result<Self, String> {
+        let field = |key: &str| v.get(key).and_then(|x| x.as_str());
+        let pattern = if let Some(s) = field("exact") {
+            Pattern::Exact(normalize(s))
+        } else if let Some(s) = field("glob") {
+            Pattern::Glob(glob_to_regex(s)?)
+        } else if let Some(s) = field("regex") {
+            Pattern::Regex(Regex::new(s).map_err(|e| format!("invalid regex: {}", e))?)
+        } else {
+            return Err("needs one of \"exact\", \"glob\" or \"regex\"".to_string());
+        };
+
+        let action = if let Some(run) = v.get("run") {
+            let argv: Vec<String> = match run {
+                Value::Array(items) => items
+                    .iter()
+                    .map(|i| i.as_str().map(str::to_string).ok_or("\"run\" entries must be strings"))
+                    .collect::<Result<_, _>>()?,
+                Value::String(s) => s.split_whitespace().map(str::to_string).collect(),
+                _ => return Err("\"run\" must be an array or string".to_string()),
+            };
+            if argv.is_empty() {
+                return Err("\"run\" is empty".to_string());
+            }
+            Action::Run(argv)
+        } else if let Some(path) = field("fifo") {
+            Action::Fifo {
+                path: PathBuf::from(path),
+                write: field("write").unwrap_or("{text}\n").to_string(),
+            }
+        } else if let Some(name) = field("intent") {
+            Action::Intent(name.to_string())
+        } else {
+            return Err("needs one of \"run\", \"fifo\" or \"intent\"".to_string());
+        };
+
+        let speakers = match v.get("speakers") {
+            None => None,
+            Some(Value::Array(items)) if !items.is_empty() => Some(
+                items
+                    .iter()
+                    .map(|i| i.as_str().map(str::to_string).ok_or("\"speakers\" entries must be strings"))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            Some(_) => return Err("\"speakers\" must be a non-empty array of names".to_string()),
+        };
+        let cooldown = match v.get("cooldown_ms") {
+            None => Duration::ZERO,
+            Some(ms) => Duration::from_millis(ms.as_u64().ok_or("\"cooldown_ms\" must be a number")?),
+        };
+        Ok(Rule {
+            name: field("name").map(str::to_string).unwrap_or_else(|| format!("#{}", index + 1)),
+            pattern,
+            action,
+            cooldown,
+            last_fired: None,
+            speakers,
+        })
+    }
+}
+
+impl Pattern {
+    /// Named and numbered captures on a match; `"0"` is the whole match.
+    fn captures(&self, text: &str) -> Option<Vec<(String, String)>> {
+        let re = match self {
+            Pattern::Exact(s) => return (normalize(text) == *s).then(Vec::new),
+            Pattern::Glob(re) | Pattern::Regex(re) => re,
+        };
+        let caps = re.captures(text)?;
+        let mut out = Vec::new();
+        for (i, name) in re.capture_names().enumerate() {
+            if let Some(m) = caps.get(i) {
+                out.push((i.to_string(), m.as_str().to_string()));
+                if let Some(name) = name {
+                    out.push((name.to_string(), m.as_str().to_string()));
+                }
+            }
+        }
+        Some(out)
+    }
+}
+
+/// Lowercased, with runs of whitespace collapsed to one space; how rules and intents
+/// compare command text.
+pub fn normalize(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+// `*` matches any run of text, `?` a single character; the whole command must match.
+fn glob_to_regex(glob: &str) -> Result<Regex, String> {
+    let mut re = String::from("(?i)^");
+    for c in glob.trim().chars() {
+        match c {
+            '*' => re.push_str("(.*?)"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).map_err(|e| format!("invalid glob: {}", e))
+}
+
+// Replaces `{key}` with the matching capture; unknown placeholders are left alone.
+fn expand(template: &str, text: &str, captures: &[(String, String)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        let key = &after[..end];
+        let value = if key == "text" {
+            Some(text)
+        } else {
+            captures.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+        };
+        match value {
+            Some(v) => out.push_str(v.trim()),
+            None => out.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn run_program(rule: &str, argv: Vec<String>) {
+    let child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .spawn();
+    match child {
+        // Reap it off the dispatcher thread so a slow program doesn't hold up later commands.
+        Ok(mut child) => {
+            let rule = rule.to_string();
+            std::thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => {
+                    eprintln!("Rule '{}': {} exited with {}[ERR]", rule, argv[0], status)
+                }
+                Err(e) => eprintln!("Rule '{}': {}: {}[ERR]", rule, argv[0], e),
+                _ => {}
+            });
+        }
+        Err(e) => eprintln!("Rule '{}': failed to run {}: {}[ERR]", rule, argv[0], e),
+    }
+}
+
+// Opening a FIFO blocks until a reader shows up, so write from a separate thread.
+fn write_fifo(path: PathBuf, payload: String) {
+    std::thread::spawn(move || {
+        let result = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(payload.as_bytes()));
+        if let Err(e) = result {
+            eprintln!("Failed to write to {}: {}[ERR]", path.display(), e);
+        }
+    });
+}
+
+/// Feeds every recognized command through the rules on a dedicated thread.
+pub fn spawn(mut rules: Rules, events: &Arc<EventBus>) {
+    let rx = events.subscribe();
+    let bus = events.clone();
+    events.track(std::thread::spawn(move || {
+        for event in rx {
+            if event.get("type").and_then(|t| t.as_str()) != Some("command") {
+                continue;
+            }
+            let speaker = event
+                .get("speaker")
+                .filter(|s| s.get("verified").and_then(|v| v.as_bool()) == Some(true))
+                .and_then(|s| s.get("name"))
+                .and_then(|n| n.as_str());
+            let text = event.get("normalized").or_else(|| event.get("text")).and_then(|t| t.as_str());
+            if let Some(text) = text {
+                rules.dispatch(text, speaker, &bus);
+            }
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc::Receiver;
+
+    fn load(name: &str, json: &str, dry_run: bool) -> Result<Rules, String> {
+        let path = std::env::temp_dir().join(format!("irisva-rules-{}-{}.json", std::process::id(), name));
+        fs::write(&path, json).unwrap();
+        let rules = Rules::load(&path, dry_run);
+        let _ = fs::remove_file(&path);
+        rules
+    }
+
+    // Captures as `Pattern::captures` returns them, name or group index first.
+    fn capture_list(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    // Types of the events emitted so far, with the intent or error detail.
+    fn emitted(rx: &Receiver<Arc<Value>>) -> Vec<String> {
+        rx.try_iter()
+            .map(|e| match e["type"].as_str().unwrap_or_default() {
+                "intent" => format!("intent {}", e["name"].as_str().unwrap_or_default()),
+                "error" => format!("error {}", e["message"].as_str().unwrap_or_default()),
+                other => other.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loads_an_array_or_a_rules_object() {
+        let rule = r#"{"exact": "stop", "intent": "stop"}"#;
+        assert_eq!(load("array", &format!("[{}]", rule), false).unwrap().count(), 1);
+        assert_eq!(load("object", &format!(r#"{{"rules": [{}, {}]}}"#, rule, rule), false).unwrap().count(), 2);
+        assert!(load("scalar", "42", false).is_err());
+        assert!(load("no-array", r#"{"items": []}"#, false).is_err());
+        assert!(load("json", "[", false).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_rules_with_their_position() {
+        let cases = [
+            (r#"{"intent": "x"}"#, "needs one of \"exact\""),
+            (r#"{"exact": "x"}"#, "needs one of \"run\""),
+            (r#"{"regex": "(", "intent": "x"}"#, "invalid regex"),
+            (r#"{"exact": "x", "run": []}"#, "\"run\" is empty"),
+            (r#"{"exact": "x", "run": [1]}"#, "entries must be strings"),
+            (r#"{"exact": "x", "intent": "x", "speakers": []}"#, "non-empty array"),
+            (r#"{"exact": "x", "intent": "x", "cooldown_ms": "soon"}"#, "must be a number"),
+        ];
+        for (rule, expected) in cases {
+            let json = format!(r#"[{{"exact": "ok", "intent": "ok"}}, {}]"#, rule);
+            let err = load("invalid", &json, false).err().unwrap_or_default();
+            assert!(err.contains("rule 2") && err.contains(expected), "{}: {}", rule, err);
+        }
+    }
+
+    #[test]
+    fn exact_ignores_case_and_spacing() {
+        let pattern = Pattern::Exact(normalize("Stop  the music"));
+        assert_eq!(pattern.captures(" stop the   MUSIC "), Some(Vec::new()));
+        assert_eq!(pattern.captures("stop the music now"), None);
+    }
+
+    #[test]
+    fn glob_stars_become_numbered_captures() {
+        let pattern = Pattern::Glob(glob_to_regex("turn * the * lights").unwrap());
+        assert_eq!(
+            pattern.captures("Turn on the kitchen lights"),
+            Some(capture_list(&[("0", "Turn on the kitchen lights"), ("1", "on"), ("2", "kitchen")]))
+        );
+        // The whole command has to match, and other characters are literal.
+        assert_eq!(pattern.captures("please turn on the kitchen lights"), None);
+        let pattern = Pattern::Glob(glob_to_regex("volume ? (max)").unwrap());
+        assert!(pattern.captures("volume 5 (max)").is_some());
+        assert!(pattern.captures("volume 50 (max)").is_none());
+    }
+
+    #[test]
+    fn regex_keeps_named_and_numbered_captures() {
+        let pattern = Pattern::Regex(Regex::new(r"for (?P<minutes>\d+) (minutes|hours)").unwrap());
+        let caps = pattern.captures("set a timer for 25 minutes").unwrap();
+        assert!(caps.contains(&("minutes".to_string(), "25".to_string())));
+        assert!(caps.contains(&("1".to_string(), "25".to_string())));
+        assert!(caps.contains(&("2".to_string(), "minutes".to_string())));
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let caps = capture_list(&[("0", "turn on the lights"), ("1", " on "), ("room", "kitchen")]);
+        let text = "turn on the lights";
+        assert_eq!(expand("lightctl {1} {room}", text, &caps), "lightctl on kitchen");
+        assert_eq!(expand("say {text}!", text, &caps), "say turn on the lights!");
+        // Unknown and unterminated placeholders stay as written.
+        assert_eq!(expand("{missing} {2}", text, &caps), "{missing} {2}");
+        assert_eq!(expand("{1} {room", text, &caps), "on {room");
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let json = r#"[
+            {"glob": "play *", "intent": "play"},
+            {"exact": "play music", "intent": "music"}
+        ]"#;
+        let mut rules = load("first", json, false).unwrap();
+        let bus = EventBus::default();
+        let rx = bus.subscribe();
+        rules.dispatch("play music", None, &bus);
+        rules.dispatch("stop", None, &bus);
+        assert_eq!(emitted(&rx), ["intent play"]);
+    }
+
+    #[test]
+    fn intent_carries_the_captures_but_not_the_whole_match() {
+        let json = r#"[{"regex": "for (?P<minutes>\\d+) minutes", "intent": "timer"}]"#;
+        let mut rules = load("slots", json, false).unwrap();
+        let bus = EventBus::default();
+        let rx = bus.subscribe();
+        rules.dispatch("set a timer for 5 minutes", None, &bus);
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event["slots"], serde_json::json!({"1": "5", "minutes": "5"}));
+        assert_eq!(event["text"], "set a timer for 5 minutes");
+    }
+
+    #[test]
+    fn speaker_restricted_rule_refuses_other_voices() {
+        let json = r#"[{"exact": "unlock the front door", "intent": "unlock", "speakers": ["alice", "bob"]},
+                       {"glob": "unlock *", "intent": "fallback"}]"#;
+        let mut rules = load("speakers", json, false).unwrap();
+        let bus = EventBus::default();
+        let rx = bus.subscribe();
+        rules.dispatch("unlock the front door", None, &bus);
+        rules.dispatch("unlock the front door", Some("carol"), &bus);
+        assert_eq!(
+            emitted(&rx),
+            [
+                "error Rule '#1' refused: speaker unverified is not allowed",
+                "error Rule '#1' refused: speaker 'carol' is not allowed",
+            ]
+        );
+        rules.dispatch("unlock the front door", Some("bob"), &bus);
+        assert_eq!(emitted(&rx), ["intent unlock"]);
+    }
+
+    #[test]
+    fn cooldown_holds_back_a_repeat() {
+        let json = r#"[{"name": "lights", "exact": "lights", "intent": "lights", "cooldown_ms": 60000},
+                       {"exact": "music", "intent": "music"}]"#;
+        let mut rules = load("cooldown", json, false).unwrap();
+        let bus = EventBus::default();
+        let rx = bus.subscribe();
+        for text in ["lights", "lights", "music", "music"] {
+            rules.dispatch(text, None, &bus);
+        }
+        assert_eq!(emitted(&rx), ["intent lights", "intent music", "intent music"]);
+    }
+
+    #[test]
+    fn dry_run_only_reports() {
+        let json = r#"[{"exact": "stop", "intent": "stop"}, {"exact": "go", "run": ["/nonexistent/program"]}]"#;
+        let mut rules = load("dry-run", json, true).unwrap();
+        let bus = EventBus::default();
+        let rx = bus.subscribe();
+        rules.dispatch("stop", None, &bus);
+        rules.dispatch("go", None, &bus);
+        assert!(emitted(&rx).is_empty());
+    }
+
+    #[test]
+    fn matches_the_normalized_command_text() {
+        let json = r#"[{"regex": "set a timer for (?P<minutes>\\d+) minutes", "intent": "timer"}]"#;
+        let rules = load("normalized", json, false).unwrap();
+        let bus = Arc::new(EventBus::default());
+        let rx = bus.subscribe();
+        spawn(rules, &bus);
+        let command = |normalized: Option<&str>| Event::Command {
+            text: "set a timer for twenty five minutes".to_string(),
+            normalized: normalized.map(str::to_string),
+            intent: None,
+            speaker: None,
+            language: "en".to_string(),
+        };
+        // The raw text spells the number out, so only the second command can match.
+        bus.emit(command(None));
+        bus.emit(command(Some("set a timer for 25 minutes")));
+        let intent = std::iter::from_fn(|| rx.recv_timeout(Duration::from_secs(2)).ok())
+            .find(|e| e["type"] == "intent")
+            .unwrap();
+        assert_eq!(intent["slots"], serde_json::json!({"1": "25", "minutes": "25"}));
+        bus.close(Duration::from_secs(2));
+    }
+}
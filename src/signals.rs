@@ -0,0 +1,42 @@
+use std::sync::mpsc::Sender;
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::control::Control;
+
+/// Exit code when a second SIGINT/SIGTERM cuts a graceful shutdown short.
+pub const EXIT_FORCED: i32 = 1;
+
+/// SIGHUP re-reads the configuration and reloads the model(s) from their directories.
+/// SIGINT/SIGTERM ask the main loop to shut down; a second one exits immediately.
+pub fn spawn(control: Sender<Control>) -> Result<(), String> {
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])
+        .map_err(|e| format!("Failed to install signal handlers: {}", e))?;
+    std::thread::spawn(move || {
+        let mut stopping = false;
+        for signal in signals.forever() {
+            let name = if signal == SIGINT { "SIGINT" } else { "SIGTERM" };
+            let sent = match signal {
+                SIGHUP => {
+                    println!("SIGHUP received, reloading configuration and model(s)");
+                    control.send(Control::ReloadConfig).is_ok()
+                        && control.send(Control::ReloadModel { language: None, path: None }).is_ok()
+                }
+                _ if stopping => {
+                    eprintln!("{} received again, exiting without cleanup[ERR]", name);
+                    std::process::exit(EXIT_FORCED);
+                }
+                _ => {
+                    stopping = true;
+                    println!("{} received, shutting down", name);
+                    control.send(Control::Shutdown).is_ok()
+                }
+            };
+            if !sent {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
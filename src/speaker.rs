@@ -0,0 +1,337 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Value, json};
+use vosk::{DecodingState, Model, Recognizer, SpeakerModel};
+
+use crate::wav::WavAudio;
+use crate::{arg_value, capture, models};
+
+// Cosine similarity of two x-vectors from the same voice is usually well above this.
+const DEFAULT_THRESHOLD: f32 = 0.5;
+
+pub struct SpeakerConfig {
+    pub model: PathBuf,
+    pub store: PathBuf,
+    pub threshold: f32,
+}
+
+impl SpeakerConfig {
+    /// `--speaker-model <dir>` turns speaker identification on; `--speakers <file>` holds the
+    /// enrolled voices and `--speaker-threshold` the similarity needed to count as a match.
+    pub fn from_args(args: &[(String, String)]) -> Option<Result<Self, String>> {
+        let model = arg_value(args, "--speaker-model")?;
+        Some(Self::parse(model, args))
+    }
+
+    fn parse(model: &str, args: &[(String, String)]) -> Result<Self, String> {
+        let threshold = match arg_value(args, "--speaker-threshold") {
+            Some(v) => v
+                .parse::<f32>()
+                .ok()
+                .filter(|t| (-1.0..=1.0).contains(t))
+                .ok_or_else(|| format!("Invalid value for --speaker-threshold: '{}' (expected -1 to 1)", v))?,
+            None => DEFAULT_THRESHOLD,
+        };
+        Ok(SpeakerConfig {
+            model: PathBuf::from(model),
+            store: store_path(args),
+            threshold,
+        })
+    }
+
+    pub fn load_model(&self) -> Result<SpeakerModel, String> {
+        SpeakerModel::new(self.model.to_string_lossy())
+            .ok_or_else(|| format!("Failed to load Vosk speaker model at '{}'", self.model.display()))
+    }
+}
+
+fn store_path(args: &[(String, String)]) -> PathBuf {
+    arg_value(args, "--speakers")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| models::data_dir().join("speakers.json"))
+}
+
+struct Voice {
+    name: String,
+    /// Mean x-vector over every enrolled utterance.
+    vector: Vec<f32>,
+    /// Speech frames behind `vector`, used to weight further enrollment.
+    frames: u64,
+}
+
+/// Enrolled voices, stored as
+/// `{"speakers": [{"name": "alice", "vector": [...], "frames": 1234}]}`.
+pub struct Speakers {
+    voices: Vec<Voice>,
+    threshold: f32,
+}
+
+impl Speakers {
+    /// A missing file means nobody is enrolled yet.
+    pub fn load(path: &Path, threshold: f32) -> Result<Self, String> {
+        let mut speakers = Speakers { voices: Vec::new(), threshold };
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(speakers),
+            Err(e) => return Err(format!("Failed to read speakers {}: {}", path.display(), e)),
+        };
+        let v: Value = serde_json::from_str(&raw)
+            .map_err(|e| format!("Invalid speakers {}: {}", path.display(), e))?;
+        for item in v.get("speakers").and_then(|s| s.as_array()).into_iter().flatten() {
+            let name = item.get("name").and_then(|n| n.as_str());
+            let vector: Option<Vec<f32>> = item
+                .get("vector")
+                .and_then(|v| v.as_array())
+                .and_then(|a| a.iter().map(|x| x.as_f64().map(|x| x as f32)).collect());
+            let (Some(name), Some(vector)) = (name, vector) else {
+                return Err(format!("Invalid speakers {}: entries need a name and a vector", path.display()));
+            };
+            speakers.voices.push(Voice {
+                name: name.to_string(),
+                vector,
+                frames: item.get("frames").and_then(|f| f.as_u64()).unwrap_or(1),
+            });
+        }
+        Ok(speakers)
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let voices: Vec<Value> = self
+            .voices
+            .iter()
+            .map(|v| json!({"name": v.name, "vector": v.vector, "frames": v.frames}))
+            .collect();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let body = serde_json::to_string_pretty(&json!({"speakers": voices})).unwrap_or_default();
+        fs::write(path, body).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn count(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// The closest enrolled voice as `{"name", "score", "verified"}`; `verified` means the
+    /// score reached the threshold.
+    pub fn identify(&self, vector: &[f32]) -> Option<Value> {
+        let (name, score) = self
+            .voices
+            .iter()
+            .map(|v| (&v.name, cosine(&v.vector, vector)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        Some(json!({
+            "name": name,
+            "score": (score * 1000.0).round() / 1000.0,
+            "verified": score >= self.threshold,
+        }))
+    }
+
+    // Frame-weighted running mean, so one long clip counts for more than a short one.
+    fn add(&mut self, name: &str, vector: &[f32], frames: u64) -> Result<(), String> {
+        match self.voices.iter_mut().find(|v| v.name == name) {
+            Some(voice) if voice.vector.len() != vector.len() => Err(format!(
+                "'{}' was enrolled with a different speaker model ({} vs {} dimensions)",
+                name,
+                voice.vector.len(),
+                vector.len()
+            )),
+            Some(voice) => {
+                let total = (voice.frames + frames) as f32;
+                for (mean, x) in voice.vector.iter_mut().zip(vector) {
+                    *mean = (*mean * voice.frames as f32 + x * frames as f32) / total;
+                }
+                voice.frames += frames;
+                Ok(())
+            }
+            None => {
+                self.voices.push(Voice { name: name.to_string(), vector: vector.to_vec(), frames });
+                Ok(())
+            }
+        }
+    }
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return -1.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denom = norm(a) * norm(b);
+    if denom == 0.0 { -1.0 } else { dot / denom }
+}
+
+/// The x-vector and its frame count from a recognizer result, when Vosk heard enough speech.
+pub fn extract_speaker_from_complete_json(result_json: &str) -> Option<(Vec<f32>, u64)> {
+    let v: Value = serde_json::from_str(result_json).ok()?;
+    let vector: Vec<f32> = v
+        .get("spk")?
+        .as_array()?
+        .iter()
+        .map(|x| x.as_f64().map(|x| x as f32))
+        .collect::<Option<_>>()?;
+    let frames = v.get("spk_frames").and_then(|f| f.as_u64()).unwrap_or(1);
+    (!vector.is_empty()).then_some((vector, frames))
+}
+
+/// `enroll <name> [clip.wav|dir]... [--enroll-clips N] [--enroll-secs S] [--replace true]`
+///
+/// Adds the voice in the clips to `<name>`'s enrollment; without clips, records
+/// `--enroll-clips` (default 3) prompts of `--enroll-secs` (default 5) from the input device.
+pub fn enroll(model: &Model, positionals: &[String], args: &[(String, String)]) -> Result<(), String> {
+    const USAGE: &str = "Usage: enroll <name> [clip.wav|dir]... --speaker-model <dir> [--speakers file] [--enroll-clips N] [--enroll-secs S] [--replace true]";
+    let name = positionals.first().ok_or(USAGE)?;
+    let config = match SpeakerConfig::from_args(args) {
+        Some(config) => config?,
+        None => return Err(format!("enroll needs --speaker-model\n{}", USAGE)),
+    };
+    let speaker_model = config.load_model()?;
+    let mut speakers = Speakers::load(&config.store, config.threshold)?;
+    if arg_value(args, "--replace") == Some("true") {
+        speakers.voices.retain(|v| v.name != *name);
+    }
+
+    let clips = capture::enrollment_clips(&positionals[1..], args, 3, 5, "speak naturally")?;
+
+    let mut enrolled = 0;
+    for (label, audio) in &clips {
+        let vectors = speaker_vectors(model, &speaker_model, audio)?;
+        if vectors.is_empty() {
+            println!("{}: not enough speech for a voice print, skipped", label);
+            continue;
+        }
+        for (vector, frames) in &vectors {
+            speakers.add(name, vector, *frames)?;
+        }
+        println!("{}: {} utterance(s)", label, vectors.len());
+        enrolled += 1;
+    }
+    if enrolled == 0 {
+        return Err(format!("No usable speech for '{}'; nothing enrolled", name));
+    }
+    speakers.save(&config.store)?;
+    println!("Enrolled '{}' from {} clip(s) in {}", name, enrolled, config.store.display());
+    Ok(())
+}
+
+fn speaker_vectors(
+    model: &Model,
+    speaker_model: &SpeakerModel,
+    audio: &WavAudio,
+) -> Result<Vec<(Vec<f32>, u64)>, String> {
+    let mut recognizer = Recognizer::new_with_speaker(model, audio.sample_rate as f32, speaker_model)
+        .ok_or_else(|| format!("Failed to create recognizer at {} Hz", audio.sample_rate))?;
+    let mut vectors = Vec::new();
+    let chunk = (audio.sample_rate as usize / 5).max(1);
+    for pcm in audio.samples.chunks(chunk) {
+        if let Ok(DecodingState::Finalized) = recognizer.accept_waveform(pcm) {
+            let json = serde_json::to_string(&recognizer.result()).unwrap_or_default();
+            vectors.extend(extract_speaker_from_complete_json(&json));
+        }
+    }
+    let json = serde_json::to_string(&recognizer.final_result()).unwrap_or_default();
+    vectors.extend(extract_speaker_from_complete_json(&json));
+    Ok(vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::scratch;
+
+    fn speakers(threshold: f32) -> Speakers {
+        Speakers { voices: Vec::new(), threshold }
+    }
+
+    #[test]
+    fn enrollment_is_a_frame_weighted_mean() {
+        let mut s = speakers(DEFAULT_THRESHOLD);
+        s.add("alice", &[1.0, 0.0], 100).unwrap();
+        s.add("alice", &[0.0, 1.0], 300).unwrap();
+        s.add("bob", &[0.5, 0.5], 10).unwrap();
+        assert_eq!(s.count(), 2);
+        assert_eq!(s.voices[0].vector, [0.25, 0.75]);
+        assert_eq!(s.voices[0].frames, 400);
+        assert_eq!(s.voices[1].vector, [0.5, 0.5]);
+    }
+
+    #[test]
+    fn enrollment_rejects_a_different_dimension() {
+        let mut s = speakers(DEFAULT_THRESHOLD);
+        s.add("alice", &[1.0, 0.0], 100).unwrap();
+        let err = s.add("alice", &[1.0, 0.0, 0.0], 100).unwrap_err();
+        assert!(err.contains("2 vs 3 dimensions"), "{}", err);
+        assert_eq!(s.voices[0].vector, [1.0, 0.0]);
+    }
+
+    #[test]
+    fn identifies_the_closest_voice_against_the_threshold() {
+        let mut s = speakers(0.9);
+        s.add("alice", &[1.0, 0.0, 0.0], 1).unwrap();
+        s.add("bob", &[0.0, 1.0, 0.0], 1).unwrap();
+        let close = s.identify(&[0.1, 2.0, 0.0]).unwrap();
+        assert_eq!(close["name"], "bob");
+        assert!((close["score"].as_f64().unwrap() - 0.999).abs() < 1e-6);
+        assert_eq!(close["verified"], true);
+        let far = s.identify(&[1.0, 1.0, 1.0]).unwrap();
+        assert_eq!(far["verified"], false);
+        assert!(speakers(0.9).identify(&[1.0, 0.0, 0.0]).is_none());
+    }
+
+    #[test]
+    fn cosine_of_degenerate_vectors_never_matches() {
+        assert_eq!(cosine(&[1.0, 2.0], &[2.0, 4.0]), 1.0);
+        assert_eq!(cosine(&[1.0, 0.0], &[-1.0, 0.0]), -1.0);
+        assert_eq!(cosine(&[0.0, 0.0], &[1.0, 0.0]), -1.0);
+        assert_eq!(cosine(&[1.0, 0.0], &[1.0, 0.0, 0.0]), -1.0);
+    }
+
+    #[test]
+    fn load_treats_a_missing_file_as_nobody_enrolled() {
+        let dir = scratch("speaker", "missing");
+        let s = Speakers::load(&dir.join("speakers.json"), DEFAULT_THRESHOLD).unwrap();
+        assert_eq!(s.count(), 0);
+    }
+
+    #[test]
+    fn load_rejects_malformed_entries() {
+        let dir = scratch("speaker", "malformed");
+        let path = dir.join("speakers.json");
+        for body in [
+            "not json",
+            r#"{"speakers": [{"vector": [1.0]}]}"#,
+            r#"{"speakers": [{"name": "alice"}]}"#,
+            r#"{"speakers": [{"name": "alice", "vector": [1.0, "x"]}]}"#,
+        ] {
+            fs::write(&path, body).unwrap();
+            assert!(Speakers::load(&path, DEFAULT_THRESHOLD).is_err(), "{}", body);
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = scratch("speaker", "round-trip");
+        let path = dir.join("nested").join("speakers.json");
+        let mut s = speakers(DEFAULT_THRESHOLD);
+        s.add("alice", &[0.5, -0.25], 42).unwrap();
+        s.save(&path).unwrap();
+        let loaded = Speakers::load(&path, DEFAULT_THRESHOLD).unwrap();
+        assert_eq!(loaded.voices[0].name, "alice");
+        assert_eq!(loaded.voices[0].vector, [0.5, -0.25]);
+        assert_eq!(loaded.voices[0].frames, 42);
+    }
+
+    #[test]
+    fn extracts_the_x_vector_from_a_result() {
+        let json = r#"{"text": "hello", "spk": [0.5, -1.0, 2.0], "spk_frames": 123}"#;
+        assert_eq!(extract_speaker_from_complete_json(json), Some((vec![0.5, -1.0, 2.0], 123)));
+        assert_eq!(extract_speaker_from_complete_json(r#"{"spk": [1.0]}"#), Some((vec![1.0], 1)));
+        assert_eq!(extract_speaker_from_complete_json(r#"{"text": "hello"}"#), None);
+        assert_eq!(extract_speaker_from_complete_json(r#"{"spk": []}"#), None);
+        assert_eq!(extract_speaker_from_complete_json(r#"{"spk": [1.0, null]}"#), None);
+        assert_eq!(extract_speaker_from_complete_json("{"), None);
+    }
+}
@@ -0,0 +1,250 @@
+//! systemd integration without libsystemd: readiness, status and watchdog notifications over
+//! `$NOTIFY_SOCKET`, and listeners passed in by socket activation.
+
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::events::EventBus;
+
+// First descriptor passed by socket activation (sd_listen_fds(3)).
+const LISTEN_FDS_START: RawFd = 3;
+
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// `WatchdogSec=` of the unit; pings go out at half of it.
+    watchdog: Option<Duration>,
+    last_ping: Mutex<Option<Instant>>,
+}
+
+impl Notifier {
+    /// `None` unless started by systemd with `NOTIFY_SOCKET` set (`Type=notify`).
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        Self::connect(&path, watchdog_from_env()).map(Some)
+    }
+
+    fn connect(path: &OsStr, watchdog: Option<Duration>) -> Result<Self, String> {
+        let addr = match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => abstract_addr(name),
+            None => SocketAddr::from_pathname(Path::new(path)),
+        }
+        .map_err(|e| format!("Invalid NOTIFY_SOCKET '{}': {}", path.to_string_lossy(), e))?;
+        let socket =
+            UnixDatagram::unbound().map_err(|e| format!("Failed to create notify socket: {}", e))?;
+        Ok(Notifier { socket, addr, watchdog, last_ping: Mutex::new(None) })
+    }
+
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    /// Sends newline-separated `KEY=value` assignments; systemd may be gone, so failures are ignored.
+    pub fn notify(&self, message: &str) {
+        let _ = self.socket.send_to_addr(message.as_bytes(), &self.addr);
+    }
+
+    /// Called from the audio callback, which skips it while a decoder is stuck so the service
+    /// gets restarted.
+    pub fn ping(&self) {
+        let Some(interval) = self.watchdog else {
+            return;
+        };
+        let mut last = self.last_ping.lock().unwrap();
+        if last.is_some_and(|t| t.elapsed() < interval / 2) {
+            return;
+        }
+        *last = Some(Instant::now());
+        self.notify("WATCHDOG=1");
+    }
+
+    /// Mirrors state and error events into `STATUS=`, with `READY=1` on the first "listening"
+    /// (model loaded, stream playing) and `STOPPING=1` once shutdown starts.
+    pub fn follow(self: Arc<Self>, events: &EventBus) {
+        let rx = events.subscribe();
+        std::thread::spawn(move || {
+            let mut ready = false;
+            for event in rx {
+                let kind = event.get("type").and_then(|t| t.as_str()).unwrap_or("");
+                let text = |key: &str| event.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+                let status = match (kind, text("state").as_str()) {
+                    ("state", "listening" | "processed" | "resetting") | ("mic-recovered", _) => {
+                        "Listening for wake words".to_string()
+                    }
+                    ("state", "waiting") => "Waiting for a command".to_string(),
+                    ("state", "paused") => "Paused".to_string(),
+                    ("state", "stopping") => {
+                        self.notify("STOPPING=1\nSTATUS=Shutting down");
+                        continue;
+                    }
+                    ("error", _) => format!("Error: {}", text("message")),
+                    ("mic-stalled", _) => format!("Microphone '{}' stalled", text("device")),
+                    ("mic-dead", _) => format!("Microphone '{}' silent", text("device")),
+                    _ => continue,
+                };
+                if !ready && kind == "state" {
+                    ready = true;
+                    self.notify(&format!("READY=1\nSTATUS={}", status));
+                } else {
+                    self.notify(&format!("STATUS={}", status));
+                }
+            }
+        });
+    }
+}
+
+fn watchdog_from_env() -> Option<Duration> {
+    // Meant for another process when WATCHDOG_PID names someone else.
+    if let Ok(pid) = env::var("WATCHDOG_PID")
+        && pid.parse::<u32>().ok() != Some(std::process::id())
+    {
+        return None;
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &[u8]) -> io::Result<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_name: &[u8]) -> io::Result<SocketAddr> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "abstract sockets need Linux"))
+}
+
+/// Listeners from socket activation: a TCP one serves websockets, a Unix one the control
+/// protocol. Empty unless `LISTEN_PID` is this process. Like `sd_listen_fds(3)`, it unsets the
+/// `LISTEN_*` variables so children such as rule commands don't take the descriptors as theirs,
+/// and closes the descriptors it doesn't use. Call it before starting any thread.
+pub fn listen_fds() -> (Option<TcpListener>, Option<UnixListener>) {
+    let (mut websocket, mut control) = (None, None);
+    let ours = env::var("LISTEN_PID").ok().and_then(|p| p.parse::<u32>().ok()) == Some(std::process::id());
+    let count = env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<RawFd>().ok()).unwrap_or(0);
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        // SAFETY: called at the top of `main`, before any other thread exists.
+        unsafe { env::remove_var(name) };
+    }
+    if !ours {
+        return (websocket, control);
+    }
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        if !is_stream_listener(fd) {
+            eprintln!("Warning: ignoring socket-activated descriptor {} (not a stream listener)", fd);
+            // SAFETY: systemd passed this descriptor to us and nothing else owns it.
+            drop(unsafe { OwnedFd::from_raw_fd(fd) });
+            continue;
+        }
+        // SAFETY: systemd passed these descriptors to this process and nothing else owns them.
+        let unix = unsafe { UnixListener::from_raw_fd(fd) };
+        if unix.local_addr().is_ok() {
+            // The clone is close-on-exec, unlike the inherited descriptor, so rule commands
+            // don't keep the socket open.
+            control = unix.try_clone().ok();
+            continue;
+        }
+        let tcp = unsafe { TcpListener::from_raw_fd(unix.into_raw_fd()) };
+        if tcp.local_addr().is_ok() {
+            websocket = tcp.try_clone().ok();
+        } else {
+            eprintln!("Warning: ignoring socket-activated descriptor {} (not a stream listener)", fd);
+        }
+        // Closes the inherited descriptor; a listener kept above is its clone.
+        drop(tcp);
+    }
+    (websocket, control)
+}
+
+// A datagram socket or one that isn't listening would make `accept` fail on every call.
+fn is_stream_listener(fd: RawFd) -> bool {
+    let option = |name| {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `value` and `len` describe a live c_int for the call to fill in.
+        let ok = unsafe {
+            libc::getsockopt(fd, libc::SOL_SOCKET, name, (&raw mut value).cast(), &mut len) == 0
+        };
+        ok.then_some(value)
+    };
+    option(libc::SO_TYPE) == Some(libc::SOCK_STREAM) && option(libc::SO_ACCEPTCONN).is_some_and(|v| v != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stub(name: &str) -> (UnixDatagram, std::path::PathBuf) {
+        let path = env::temp_dir().join(format!("irisva-notify-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        (socket, path)
+    }
+
+    fn recv(socket: &UnixDatagram) -> Option<String> {
+        let mut buf = [0u8; 256];
+        let n = socket.recv(&mut buf).ok()?;
+        Some(String::from_utf8_lossy(&buf[..n]).into_owned())
+    }
+
+    #[test]
+    fn only_listening_stream_sockets_are_taken() {
+        use std::os::unix::io::AsRawFd;
+        let path = env::temp_dir().join(format!("irisva-listen-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let datagram = UnixDatagram::unbound().unwrap();
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let connected = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        assert!(is_stream_listener(listener.as_raw_fd()));
+        assert!(is_stream_listener(tcp.as_raw_fd()));
+        assert!(!is_stream_listener(datagram.as_raw_fd()));
+        assert!(!is_stream_listener(udp.as_raw_fd()));
+        assert!(!is_stream_listener(connected.as_raw_fd()));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn reports_readiness_and_status_from_events() {
+        let (socket, path) = stub("events");
+        let notifier = Arc::new(Notifier::connect(path.as_os_str(), None).unwrap());
+        let events = EventBus::default();
+        notifier.follow(&events);
+        events.emit(crate::events::Event::State { state: "listening" });
+        events.emit(crate::events::Event::State { state: "waiting" });
+        events.emit(crate::events::Event::State { state: "stopping" });
+        assert_eq!(recv(&socket).unwrap(), "READY=1\nSTATUS=Listening for wake words");
+        assert_eq!(recv(&socket).unwrap(), "STATUS=Waiting for a command");
+        assert_eq!(recv(&socket).unwrap(), "STOPPING=1\nSTATUS=Shutting down");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn pings_at_half_the_watchdog_interval() {
+        let (socket, path) = stub("watchdog");
+        let notifier = Notifier::connect(path.as_os_str(), Some(Duration::from_millis(200))).unwrap();
+        notifier.ping();
+        notifier.ping();
+        assert_eq!(recv(&socket).unwrap(), "WATCHDOG=1");
+        socket.set_nonblocking(true).unwrap();
+        assert_eq!(recv(&socket), None);
+        std::thread::sleep(Duration::from_millis(110));
+        notifier.ping();
+        socket.set_nonblocking(false).unwrap();
+        assert_eq!(recv(&socket).unwrap(), "WATCHDOG=1");
+        let _ = std::fs::remove_file(path);
+    }
+}
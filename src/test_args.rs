@@ -0,0 +1,6 @@
+//! Command line pairs for the option parsing tests.
+
+/// `[("--flag", "value")]` as the parsed arguments the `from_args` functions take.
+pub fn args(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
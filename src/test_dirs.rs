@@ -0,0 +1,12 @@
+//! Scratch directories for the tests that work with files.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// An empty `irisva-<prefix>-<pid>-<name>` directory under the system temp dir.
+pub fn scratch(prefix: &str, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("irisva-{}-{}-{}", prefix, std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,19 @@
+//! Synthetic signals shared by the audio processing tests.
+
+/// Deterministic white noise in [-amplitude, amplitude] from a xorshift generator.
+pub fn noise(amplitude: f32, len: usize, seed: u32) -> Vec<f32> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            amplitude * (state as f32 / u32::MAX as f32 * 2.0 - 1.0)
+        })
+        .collect()
+}
+
+/// Mean square of `x`.
+pub fn power(x: &[f32]) -> f32 {
+    x.iter().map(|s| s * s).sum::<f32>() / x.len() as f32
+}
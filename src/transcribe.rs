@@ -0,0 +1,362 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Value, json};
+use vosk::{DecodingState, Model, Recognizer};
+
+use crate::arg_value;
+use crate::itn::{self, LanguageRules};
+use crate::wav::{WavAudio, read_wav};
+
+// Subtitle cues are split so a single line never runs on for too long.
+const MAX_CUE_WORDS: usize = 12;
+
+struct Word {
+    word: String,
+    start: f64,
+    end: f64,
+    conf: f64,
+}
+
+struct Segment {
+    text: String,
+    /// `text` after inverse text normalization, unless `--itn off`.
+    normalized: Option<String>,
+    words: Vec<Word>,
+}
+
+/// `transcribe <file|dir>... [--format text|json|srt|vtt] [--out-dir dir] [--raw-rate hz]`
+///
+/// `.wav` files are read with their own header; `.raw`/`.pcm` files are
+/// treated as 16-bit little-endian mono at `--raw-rate` (default 16000 Hz). Numbers and times
+/// are normalized as in command events (`--itn`); JSON output carries both forms.
+pub fn run(model: &Model, positionals: &[String], args: &[(String, String)]) -> Result<(), String> {
+    let format = arg_value(args, "--format").unwrap_or("text");
+    let ext = match format {
+        "text" => "txt",
+        "json" => "json",
+        "srt" => "srt",
+        "vtt" => "vtt",
+        other => {
+            return Err(format!(
+                "Unknown --format '{}' (expected text, json, srt or vtt)",
+                other
+            ));
+        }
+    };
+    let raw_rate = match arg_value(args, "--raw-rate") {
+        Some(v) => v
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid value for --raw-rate: '{}'", v))?,
+        None => 16000,
+    };
+    let out_dir = arg_value(args, "--out-dir").map(PathBuf::from);
+    if let Some(dir) = &out_dir {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create output directory {}: {}", dir.display(), e))?;
+    }
+
+    let itn = itn::rules_from_args(args)?;
+    let files = collect_inputs(positionals)?;
+    if files.is_empty() {
+        return Err("Usage: transcribe <file|dir>... [--format text|json|srt|vtt] [--out-dir dir] [--raw-rate hz]".to_string());
+    }
+
+    let mut failures = 0;
+    for file in &files {
+        let rendered = load_audio(file, raw_rate)
+            .and_then(|audio| transcribe_audio(model, &audio, itn.as_deref()))
+            .map(|segments| render(&segments, format, itn.as_deref()));
+        let rendered = match rendered {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("{}[ERR]", e);
+                failures += 1;
+                continue;
+            }
+        };
+        match &out_dir {
+            Some(dir) => {
+                let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+                let out = dir.join(format!("{}.{}", stem, ext));
+                fs::write(&out, rendered)
+                    .map_err(|e| format!("Failed to write {}: {}", out.display(), e))?;
+                println!("{} -> {}", file.display(), out.display());
+            }
+            None if files.len() > 1 && format == "text" => {
+                println!("{}: {}", file.display(), rendered.trim_end());
+            }
+            None => print!("{}", rendered),
+        }
+    }
+    if failures > 0 {
+        return Err(format!("{} of {} files failed to transcribe", failures, files.len()));
+    }
+    Ok(())
+}
+
+fn is_audio_file(p: &Path) -> bool {
+    matches!(
+        p.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref(),
+        Some("wav" | "raw" | "pcm")
+    )
+}
+
+fn collect_inputs(positionals: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for p in positionals.iter().map(PathBuf::from) {
+        if p.is_dir() {
+            let rd = fs::read_dir(&p).map_err(|e| format!("{}: {}", p.display(), e))?;
+            let mut entries: Vec<PathBuf> =
+                rd.flatten().map(|e| e.path()).filter(|f| is_audio_file(f)).collect();
+            entries.sort();
+            files.extend(entries);
+        } else if p.is_file() {
+            files.push(p);
+        } else {
+            return Err(format!("No such file or directory: {}", p.display()));
+        }
+    }
+    Ok(files)
+}
+
+fn load_audio(path: &Path, raw_rate: u32) -> Result<WavAudio, String> {
+    let is_wav = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+    if is_wav {
+        return read_wav(path);
+    }
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let samples = bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    Ok(WavAudio { sample_rate: raw_rate, samples })
+}
+
+fn transcribe_audio(
+    model: &Model,
+    audio: &WavAudio,
+    itn: Option<&dyn LanguageRules>,
+) -> Result<Vec<Segment>, String> {
+    let mut recognizer = Recognizer::new(model, audio.sample_rate as f32)
+        .ok_or_else(|| format!("Failed to create recognizer at {} Hz", audio.sample_rate))?;
+    recognizer.set_max_alternatives(0);
+    recognizer.set_words(true);
+
+    let mut segments = Vec::new();
+    let chunk = (audio.sample_rate as usize / 5).max(1);
+    for pcm in audio.samples.chunks(chunk) {
+        if let Ok(DecodingState::Finalized) = recognizer.accept_waveform(pcm) {
+            let json = serde_json::to_string(&recognizer.result()).unwrap_or_default();
+            segments.extend(parse_segment(&json, itn));
+        }
+    }
+    let json = serde_json::to_string(&recognizer.final_result()).unwrap_or_default();
+    segments.extend(parse_segment(&json, itn));
+    Ok(segments)
+}
+
+fn parse_segment(result_json: &str, itn: Option<&dyn LanguageRules>) -> Option<Segment> {
+    let v: Value = serde_json::from_str(result_json).ok()?;
+    let text = v.get("text")?.as_str()?.trim().to_string();
+    if text.is_empty() {
+        return None;
+    }
+    let words = v
+        .get("result")
+        .and_then(|r| r.as_array())
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|w| {
+                    Some(Word {
+                        word: w.get("word")?.as_str()?.to_string(),
+                        start: w.get("start")?.as_f64()?,
+                        end: w.get("end")?.as_f64()?,
+                        conf: w.get("conf").and_then(|c| c.as_f64()).unwrap_or(1.0),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let normalized = itn.map(|rules| itn::normalize(rules, &text));
+    Some(Segment { text, normalized, words })
+}
+
+impl Segment {
+    fn written(&self) -> &str {
+        self.normalized.as_deref().unwrap_or(&self.text)
+    }
+}
+
+fn render(segments: &[Segment], format: &str, itn: Option<&dyn LanguageRules>) -> String {
+    match format {
+        "json" => {
+            let segs: Vec<Value> = segments
+                .iter()
+                .map(|s| {
+                    json!({
+                        "text": s.text,
+                        "normalized": s.normalized,
+                        "start": s.words.first().map(|w| w.start),
+                        "end": s.words.last().map(|w| w.end),
+                        "words": s.words.iter().map(|w| json!({
+                            "word": w.word,
+                            "start": w.start,
+                            "end": w.end,
+                            "conf": w.conf,
+                        })).collect::<Vec<_>>(),
+                    })
+                })
+                .collect();
+            let text = segments.iter().map(|s| s.text.as_str()).collect::<Vec<_>>().join(" ");
+            let normalized = segments.iter().map(Segment::written).collect::<Vec<_>>().join(" ");
+            let doc = match itn {
+                Some(_) => json!({ "text": text, "normalized": normalized, "segments": segs }),
+                None => json!({ "text": text, "segments": segs }),
+            };
+            serde_json::to_string_pretty(&doc).unwrap_or_default() + "\n"
+        }
+        "srt" | "vtt" => render_cues(segments, format == "vtt", itn),
+        _ => {
+            let mut out = String::new();
+            for s in segments {
+                out.push_str(s.written());
+                out.push('\n');
+            }
+            out
+        }
+    }
+}
+
+fn render_cues(segments: &[Segment], vtt: bool, itn: Option<&dyn LanguageRules>) -> String {
+    let mut out = String::new();
+    if vtt {
+        out.push_str("WEBVTT\n\n");
+    }
+    let mut index = 1;
+    for seg in segments {
+        for cue in seg.words.chunks(MAX_CUE_WORDS) {
+            let (Some(first), Some(last)) = (cue.first(), cue.last()) else {
+                continue;
+            };
+            let text = cue.iter().map(|w| w.word.as_str()).collect::<Vec<_>>().join(" ");
+            let text = itn.map(|rules| itn::normalize(rules, &text)).unwrap_or(text);
+            if !vtt {
+                out.push_str(&format!("{}\n", index));
+            }
+            out.push_str(&format!(
+                "{} --> {}\n{}\n\n",
+                cue_time(first.start, vtt),
+                cue_time(last.end, vtt),
+                text
+            ));
+            index += 1;
+        }
+    }
+    out
+}
+
+fn cue_time(seconds: f64, vtt: bool) -> String {
+    let total_ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    let (h, m, s, ms) = (
+        total_ms / 3_600_000,
+        total_ms / 60_000 % 60,
+        total_ms / 1000 % 60,
+        total_ms % 1000,
+    );
+    let sep = if vtt { '.' } else { ',' };
+    format!("{:02}:{:02}:{:02}{}{:03}", h, m, s, sep, ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::itn::English;
+
+    // A recognizer result for "set a timer for twenty five minutes and then play some music
+    // in the kitchen please", one word every half second starting at 59.5 s.
+    fn result_json() -> String {
+        let words = "set a timer for twenty five minutes and then play some music in the kitchen please";
+        let result: Vec<Value> = words
+            .split(' ')
+            .enumerate()
+            .map(|(i, w)| json!({"word": w, "start": 59.5 + i as f64 * 0.5, "end": 59.9 + i as f64 * 0.5, "conf": 0.9}))
+            .collect();
+        json!({"text": words, "result": result}).to_string()
+    }
+
+    fn segment(itn: Option<&dyn LanguageRules>) -> Segment {
+        parse_segment(&result_json(), itn).unwrap()
+    }
+
+    #[test]
+    fn cue_times_use_the_format_separator() {
+        assert_eq!(cue_time(0.0, false), "00:00:00,000");
+        assert_eq!(cue_time(61.2345, false), "00:01:01,235");
+        assert_eq!(cue_time(61.2345, true), "00:01:01.235");
+        assert_eq!(cue_time(3599.9996, true), "01:00:00.000");
+        assert_eq!(cue_time(36_000.0 + 62.5, false), "10:01:02,500");
+        assert_eq!(cue_time(-1.0, false), "00:00:00,000");
+    }
+
+    #[test]
+    fn parses_words_and_skips_empty_results() {
+        let seg = segment(None);
+        assert_eq!(seg.words.len(), 16);
+        assert_eq!(seg.words[2].word, "timer");
+        assert_eq!(seg.words[2].start, 60.5);
+        assert!(seg.normalized.is_none());
+        assert!(parse_segment(r#"{"text": "  "}"#, None).is_none());
+        assert!(parse_segment("not json", None).is_none());
+        // Words without timing are dropped; a missing confidence counts as certain.
+        let seg = parse_segment(r#"{"text": "hi there", "result": [{"word": "hi"}, {"word": "there", "start": 1, "end": 2}]}"#, None)
+            .unwrap();
+        assert_eq!(seg.words.len(), 1);
+        assert_eq!(seg.words[0].conf, 1.0);
+    }
+
+    #[test]
+    fn srt_cues_are_numbered_and_split() {
+        let srt = render(&[segment(None)], "srt", None);
+        assert_eq!(
+            srt,
+            "1\n00:00:59,500 --> 00:01:05,400\n\
+             set a timer for twenty five minutes and then play some music\n\n\
+             2\n00:01:05,500 --> 00:01:07,400\n\
+             in the kitchen please\n\n"
+        );
+    }
+
+    #[test]
+    fn vtt_has_a_header_and_normalized_cues() {
+        let vtt = render(&[segment(Some(&English))], "vtt", Some(&English));
+        assert!(vtt.starts_with("WEBVTT\n\n00:00:59.500 --> 00:01:05.400\nset a timer for 25 minutes"), "{}", vtt);
+        assert!(vtt.ends_with("00:01:05.500 --> 00:01:07.400\nin the kitchen please\n\n"));
+    }
+
+    #[test]
+    fn text_prefers_the_normalized_form() {
+        assert!(render(&[segment(None)], "text", None).starts_with("set a timer for twenty five minutes"));
+        assert_eq!(
+            render(&[segment(Some(&English))], "text", Some(&English)),
+            "set a timer for 25 minutes and then play some music in the kitchen please\n"
+        );
+    }
+
+    #[test]
+    fn json_carries_both_forms_and_word_timing() {
+        let doc: Value = serde_json::from_str(&render(&[segment(Some(&English))], "json", Some(&English))).unwrap();
+        assert_eq!(doc["text"], "set a timer for twenty five minutes and then play some music in the kitchen please");
+        assert_eq!(doc["normalized"], "set a timer for 25 minutes and then play some music in the kitchen please");
+        let seg = &doc["segments"][0];
+        assert_eq!(seg["start"], 59.5);
+        assert_eq!(seg["end"], 67.4);
+        assert_eq!(seg["words"][15], json!({"word": "please", "start": 67.0, "end": 67.4, "conf": 0.9}));
+
+        let doc: Value = serde_json::from_str(&render(&[segment(None)], "json", None)).unwrap();
+        assert!(doc.get("normalized").is_none());
+        assert!(doc["segments"][0]["normalized"].is_null());
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{Value, json};
+
+use crate::control::{self, Control};
+use crate::events::{Event, EventBus};
+
+// How often an idle event thread checks whether its client is still connected.
+const DISCONNECT_POLL: Duration = Duration::from_millis(200);
+// Pause after a failed accept, e.g. out of descriptors, so a lasting error doesn't spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Newline-delimited JSON control socket. Clients send one request per line, e.g.
+/// `{"type":"pause"}` or `{"type":"subscribe","events":["command","state"]}`,
+/// and get one reply line back; subscribed events are interleaved as they happen.
+pub fn spawn(path: &Path, events: Arc<EventBus>, control: Sender<Control>) -> Result<(), String> {
+    // A socket left behind by a previous run would make bind fail; one that still accepts
+    // connections belongs to a running instance.
+    if let Ok(meta) = std::fs::symlink_metadata(path)
+        && meta.file_type().is_socket()
+    {
+        match UnixStream::connect(path) {
+            Ok(_) => return Err(format!("Control socket {} is already in use", path.display())),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                let _ = std::fs::remove_file(path);
+            }
+            Err(_) => {}
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|e| format!("Failed to bind control socket {}: {}", path.display(), e))?;
+    serve(listener, events, control);
+    Ok(())
+}
+
+/// Accepts clients on an already bound listener, e.g. one from socket activation.
+pub fn serve(listener: UnixListener, events: Arc<EventBus>, control: Sender<Control>) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Control socket accept failed: {}", e);
+                    std::thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            let events = events.clone();
+            let control = control.clone();
+            std::thread::spawn(move || serve_client(stream, events, control));
+        }
+    });
+}
+
+fn serve_client(stream: UnixStream, events: Arc<EventBus>, control: Sender<Control>) {
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let writer = Arc::new(Mutex::new(stream));
+    let subscriptions: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    // Set when the client goes away; the event thread, if any, stops at its next poll.
+    let disconnected = Arc::new(AtomicBool::new(false));
+    let mut forwarding = false;
+
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Value>(&line) {
+            Ok(v) => handle_request(&v, &subscriptions, &control),
+            Err(e) => json!({ "type": "error", "message": format!("Invalid JSON: {}", e) }),
+        };
+        // Clients that only send commands never hold a subscription on the bus.
+        if !forwarding && !subscriptions.lock().unwrap().is_empty() {
+            forward_events(&events, writer.clone(), subscriptions.clone(), disconnected.clone());
+            forwarding = true;
+        }
+        if !write_line(&writer, &reply) {
+            break;
+        }
+    }
+    disconnected.store(true, Ordering::SeqCst);
+    // Unblocks a write the event thread may be stuck in.
+    let _ = writer.lock().unwrap().shutdown(std::net::Shutdown::Both);
+}
+
+// Writes subscribed events to the client until it disconnects or the bus closes.
+fn forward_events(
+    events: &EventBus,
+    writer: Arc<Mutex<UnixStream>>,
+    subscriptions: Arc<Mutex<HashSet<String>>>,
+    disconnected: Arc<AtomicBool>,
+) {
+    let rx = events.subscribe();
+    events.track(std::thread::spawn(move || {
+        while !disconnected.load(Ordering::SeqCst) {
+            let event = match rx.recv_timeout(DISCONNECT_POLL) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let kind = event.get("type").and_then(|t| t.as_str()).unwrap_or("");
+            if !subscriptions.lock().unwrap().contains(kind) {
+                continue;
+            }
+            if !write_line(&writer, &event) {
+                break;
+            }
+        }
+    }));
+}
+
+fn handle_request(
+    v: &Value,
+    subscriptions: &Mutex<HashSet<String>>,
+    control: &Sender<Control>,
+) -> Value {
+    let kind = control::message_type(v);
+    if !matches!(kind.as_deref(), Some("subscribe" | "unsubscribe")) {
+        return control::dispatch(v, control);
+    }
+    let requested: Vec<String> = match v.get("events") {
+        None => Event::KINDS.iter().map(|k| k.to_string()).collect(),
+        Some(Value::String(s)) if s == "all" => Event::KINDS.iter().map(|k| k.to_string()).collect(),
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|i| i.as_str())
+            .map(str::to_string)
+            .collect(),
+        Some(_) => {
+            return json!({ "type": "error", "message": "\"events\" must be an array or \"all\"" });
+        }
+    };
+    if let Some(unknown) = requested.iter().find(|k| !Event::KINDS.contains(&k.as_str())) {
+        return json!({
+            "type": "error",
+            "message": format!("Unknown event type '{}' (expected one of {})", unknown, Event::KINDS.join(", ")),
+        });
+    }
+    let mut subs = subscriptions.lock().unwrap();
+    if kind.as_deref() == Some("subscribe") {
+        subs.extend(requested);
+    } else {
+        for k in &requested {
+            subs.remove(k);
+        }
+    }
+    let mut current: Vec<&String> = subs.iter().collect();
+    current.sort();
+    json!({ "type": "subscribed", "events": current })
+}
+
+fn write_line(writer: &Mutex<UnixStream>, v: &Value) -> bool {
+    let mut line = v.to_string();
+    line.push('\n');
+    writer.lock().unwrap().write_all(line.as_bytes()).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver};
+
+    struct Client {
+        writer: UnixStream,
+        reader: BufReader<UnixStream>,
+    }
+
+    impl Client {
+        fn request(&mut self, line: &str) -> Value {
+            writeln!(self.writer, "{}", line).unwrap();
+            self.next()
+        }
+
+        fn next(&mut self) -> Value {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+    }
+
+    fn connect() -> (Client, Arc<EventBus>, Receiver<Control>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let events = Arc::new(EventBus::default());
+        let (control, commands) = mpsc::channel();
+        let bus = events.clone();
+        std::thread::spawn(move || serve_client(server, bus, control));
+        let reader = BufReader::new(client.try_clone().unwrap());
+        (Client { writer: client, reader }, events, commands)
+    }
+
+    fn socket_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("irisva-socket-{}-{}.sock", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn forwards_control_requests() {
+        let (mut client, _events, commands) = connect();
+        assert_eq!(client.request(r#"{"type": "pause"}"#), json!({"type": "ack", "command": "pause"}));
+        assert!(matches!(commands.try_recv(), Ok(Control::Pause)));
+        assert_eq!(client.request(r#"{"type": "launch"}"#)["type"], "error");
+        assert!(client.request("{not json").get("message").unwrap().as_str().unwrap().starts_with("Invalid JSON"));
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[test]
+    fn sends_only_subscribed_events() {
+        let (mut client, events, _commands) = connect();
+        let reply = client.request(r#"{"type": "subscribe", "events": ["state", "command"]}"#);
+        assert_eq!(reply, json!({"type": "subscribed", "events": ["command", "state"]}));
+        let reply = client.request(r#"{"type": "unsubscribe", "events": ["command"]}"#);
+        assert_eq!(reply, json!({"type": "subscribed", "events": ["state"]}));
+
+        events.emit(Event::Swap);
+        events.emit(Event::State { state: "listening" });
+        assert_eq!(client.next()["state"], "listening");
+    }
+
+    #[test]
+    fn commands_alone_leave_no_subscriber_behind() {
+        let (mut client, events, _commands) = connect();
+        assert_eq!(client.request(r#"{"type": "pause"}"#)["type"], "ack");
+        assert!(!events.has_subscribers());
+        drop(client);
+
+        // A subscriber's thread ends with its client, and the bus drops it at the next event.
+        let (mut client, events, _commands) = connect();
+        client.request(r#"{"type": "subscribe", "events": ["model"]}"#);
+        assert!(events.has_subscribers());
+        drop(client);
+        std::thread::sleep(DISCONNECT_POLL * 2);
+        events.emit(Event::Swap);
+        assert!(!events.has_subscribers());
+    }
+
+    #[test]
+    fn subscribes_to_everything_by_default() {
+        let (mut client, _events, _commands) = connect();
+        let reply = client.request(r#"{"type": "subscribe"}"#);
+        assert_eq!(reply["events"].as_array().unwrap().len(), Event::KINDS.len());
+        assert_eq!(client.request(r#"{"type": "unsubscribe", "events": "all"}"#)["events"], json!([]));
+    }
+
+    #[test]
+    fn rejects_unknown_event_kinds() {
+        let (mut client, _events, _commands) = connect();
+        let reply = client.request(r#"{"type": "subscribe", "events": ["command", "gossip"]}"#);
+        assert!(reply["message"].as_str().unwrap().starts_with("Unknown event type 'gossip'"));
+        assert_eq!(client.request(r#"{"type": "subscribe", "events": 3}"#)["type"], "error");
+        // Nothing from a rejected request was applied.
+        assert_eq!(client.request(r#"{"type": "unsubscribe", "events": []}"#)["events"], json!([]));
+    }
+
+    #[test]
+    fn leaves_a_running_instance_its_socket() {
+        let path = socket_path("in-use");
+        let (control, _commands) = mpsc::channel();
+        let events = Arc::new(EventBus::default());
+        spawn(&path, events.clone(), control.clone()).unwrap();
+        let err = spawn(&path, events, control).unwrap_err();
+        assert!(err.contains("already in use"), "{}", err);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn replaces_a_stale_socket() {
+        let path = socket_path("stale");
+        drop(UnixListener::bind(&path).unwrap());
+        let (control, _commands) = mpsc::channel();
+        spawn(&path, Arc::new(EventBus::default()), control).unwrap();
+        assert!(UnixStream::connect(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,348 @@
+//! Second look at a wake candidate before it counts: the recognizer's word confidence and
+//! timing for the phrase, and a re-decode of the utterance restricted to the wake phrases.
+
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{Value, json};
+use vosk::{Model, Recognizer};
+
+use crate::{arg_value, contains_wake_word};
+
+// Audio kept for re-decoding; a single utterance is never longer in practice.
+const MAX_UTTERANCE_SECS: usize = 30;
+
+pub struct WakeVerifyConfig {
+    pub grammar: bool,
+    pub min_confidence: Option<f32>,
+    pub min_duration: Option<Duration>,
+}
+
+impl WakeVerifyConfig {
+    /// `--wake-verify-grammar true`, `--wake-min-confidence 0..1`, `--wake-min-duration-ms N`;
+    /// `None` unless at least one is given.
+    pub fn from_args(args: &[(String, String)]) -> Option<Result<Self, String>> {
+        if !["--wake-verify-grammar", "--wake-min-confidence", "--wake-min-duration-ms"]
+            .iter()
+            .any(|k| arg_value(args, k).is_some())
+        {
+            return None;
+        }
+        Some(Self::parse(args))
+    }
+
+    fn parse(args: &[(String, String)]) -> Result<Self, String> {
+        let grammar = match arg_value(args, "--wake-verify-grammar") {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(v) => return Err(format!("Invalid value for --wake-verify-grammar: '{}'", v)),
+        };
+        let min_confidence = match arg_value(args, "--wake-min-confidence") {
+            Some(v) => Some(
+                v.parse::<f32>()
+                    .ok()
+                    .filter(|c| (0.0..=1.0).contains(c))
+                    .ok_or_else(|| format!("Invalid value for --wake-min-confidence: '{}' (expected 0 to 1)", v))?,
+            ),
+            None => None,
+        };
+        let min_duration = match arg_value(args, "--wake-min-duration-ms") {
+            Some(v) => Some(Duration::from_millis(
+                v.parse::<u64>()
+                    .map_err(|_| format!("Invalid value for --wake-min-duration-ms: '{}'", v))?,
+            )),
+            None => None,
+        };
+        Ok(WakeVerifyConfig { grammar, min_confidence, min_duration })
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    candidates: u64,
+    accepted: u64,
+    /// Let through because the grammar recognizer could not be built.
+    unverified: u64,
+    low_confidence: u64,
+    too_short: u64,
+    grammar: u64,
+}
+
+pub struct WakeVerifier {
+    config: WakeVerifyConfig,
+    stats: Mutex<Stats>,
+}
+
+/// What a single recognizer needs to have its wake candidates verified.
+pub struct WakeCheck<'a> {
+    pub verifier: &'a Arc<WakeVerifier>,
+    pub model: &'a Arc<Model>,
+    pub sample_rate: f32,
+    /// Audio fed since the recognizer last finalized.
+    pub utterance: &'a mut Vec<i16>,
+    /// Run the grammar re-decode on a worker thread instead of inline; set for live audio,
+    /// where the caller is a lane worker that would otherwise fall behind its bounded queue,
+    /// and have blocks dropped, while the whole utterance is decoded again.
+    pub background: bool,
+}
+
+pub enum Verdict {
+    Accepted,
+    Rejected(String),
+    /// The re-decode is still running; its answer arrives on the receiver.
+    Pending(Receiver<Result<(), String>>),
+}
+
+impl WakeCheck<'_> {
+    pub fn push(&mut self, pcm: &[i16]) {
+        let cap = MAX_UTTERANCE_SECS * self.sample_rate as usize;
+        if self.utterance.len() + pcm.len() > cap {
+            let excess = (self.utterance.len() + pcm.len()).saturating_sub(cap).min(self.utterance.len());
+            self.utterance.drain(..excess);
+        }
+        self.utterance.extend_from_slice(pcm);
+    }
+
+    /// Whether the candidate in `result_json` holds up, with the reason when it doesn't.
+    pub fn verify(&self, result_json: &str, wake_words: &[&str]) -> Verdict {
+        let verifier = self.verifier;
+        if let Err(rejection) = verifier.check_timing(result_json, wake_words) {
+            return verifier.record(Err(rejection)).into();
+        }
+        if !verifier.config.grammar {
+            return verifier.record(Ok(true)).into();
+        }
+        if !self.background {
+            let outcome = check_grammar(self.model, self.sample_rate, self.utterance, wake_words);
+            return verifier.record(outcome).into();
+        }
+        let (tx, rx) = mpsc::channel();
+        let (verifier, model) = (verifier.clone(), self.model.clone());
+        let (sample_rate, audio) = (self.sample_rate, self.utterance.clone());
+        let wake_words: Vec<String> = wake_words.iter().map(|w| w.to_string()).collect();
+        std::thread::spawn(move || {
+            let wake_words: Vec<&str> = wake_words.iter().map(String::as_str).collect();
+            let outcome = check_grammar(&model, sample_rate, &audio, &wake_words);
+            let _ = tx.send(verifier.record(outcome));
+        });
+        Verdict::Pending(rx)
+    }
+}
+
+impl From<Result<(), String>> for Verdict {
+    fn from(outcome: Result<(), String>) -> Self {
+        match outcome {
+            Ok(()) => Verdict::Accepted,
+            Err(reason) => Verdict::Rejected(reason),
+        }
+    }
+}
+
+impl WakeVerifier {
+    pub fn new(config: WakeVerifyConfig) -> Self {
+        WakeVerifier { config, stats: Mutex::new(Stats::default()) }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut checks = Vec::new();
+        if let Some(c) = self.config.min_confidence {
+            checks.push(format!("confidence >= {:.2}", c));
+        }
+        if let Some(d) = self.config.min_duration {
+            checks.push(format!("duration >= {} ms", d.as_millis()));
+        }
+        if self.config.grammar {
+            checks.push("wake-only grammar re-decode".to_string());
+        }
+        checks.join(", ")
+    }
+
+    pub fn uses_grammar(&self) -> bool {
+        self.config.grammar
+    }
+
+    /// Confidence and duration checks read per-word results.
+    pub fn uses_word_timing(&self) -> bool {
+        self.config.min_confidence.is_some() || self.config.min_duration.is_some()
+    }
+
+    /// Counts a finished candidate. `Ok(false)` means it passed without the grammar re-decode
+    /// having run, because the grammar recognizer could not be built.
+    fn record(&self, outcome: Result<bool, Rejection>) -> Result<(), String> {
+        let mut stats = self.stats.lock().unwrap();
+        stats.candidates += 1;
+        match &outcome {
+            Ok(true) => stats.accepted += 1,
+            Ok(false) => stats.unverified += 1,
+            Err(Rejection::Confidence(_)) => stats.low_confidence += 1,
+            Err(Rejection::Duration(_)) => stats.too_short += 1,
+            Err(Rejection::Grammar) => stats.grammar += 1,
+        }
+        outcome.map(|_| ()).map_err(|r| r.to_string())
+    }
+
+    // The cheap checks, run before any re-decode.
+    fn check_timing(&self, result_json: &str, wake_words: &[&str]) -> Result<(), Rejection> {
+        if !self.uses_word_timing() {
+            return Ok(());
+        }
+        let (confidence, duration) = wake_word_timing(result_json, wake_words).unwrap_or((0.0, 0.0));
+        if let Some(min) = self.config.min_confidence
+            && confidence < min
+        {
+            return Err(Rejection::Confidence(confidence));
+        }
+        if let Some(min) = self.config.min_duration
+            && duration < min.as_secs_f32()
+        {
+            return Err(Rejection::Duration(duration));
+        }
+        Ok(())
+    }
+
+    /// Counts for both stages: candidates from the recognizer and what verification made of them.
+    pub fn stats_json(&self) -> Value {
+        let s = self.stats.lock().unwrap();
+        json!({
+            "candidates": s.candidates,
+            "accepted": s.accepted,
+            "unverified": s.unverified,
+            "rejected": s.candidates - s.accepted - s.unverified,
+            "rejected_low_confidence": s.low_confidence,
+            "rejected_too_short": s.too_short,
+            "rejected_grammar": s.grammar,
+        })
+    }
+}
+
+/// Re-decodes `audio` with only the wake phrases allowed; `Ok(false)` when that can't be done.
+fn check_grammar(model: &Model, sample_rate: f32, audio: &[i16], wake_words: &[&str]) -> Result<bool, Rejection> {
+    let mut grammar: Vec<&str> = wake_words.to_vec();
+    grammar.push("[unk]");
+    let Some(mut recognizer) = Recognizer::new_with_grammar(model, sample_rate, &grammar) else {
+        return Ok(false);
+    };
+    let _ = recognizer.accept_waveform(audio);
+    let json = serde_json::to_string(&recognizer.final_result()).unwrap_or_default();
+    let text = serde_json::from_str::<Value>(&json)
+        .ok()
+        .and_then(|v| v.get("text").and_then(|t| t.as_str()).map(str::to_string))
+        .unwrap_or_default();
+    if contains_wake_word(&text, wake_words).is_none() {
+        return Err(Rejection::Grammar);
+    }
+    Ok(true)
+}
+
+enum Rejection {
+    Confidence(f32),
+    Duration(f32),
+    Grammar,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::Confidence(c) => write!(f, "confidence {:.2} too low", c),
+            Rejection::Duration(d) => write!(f, "spoken in {} ms, too short", (d * 1000.0) as u64),
+            Rejection::Grammar => write!(f, "not heard by the wake-only grammar"),
+        }
+    }
+}
+
+/// Mean confidence and spoken duration (seconds) of the first wake phrase in a result.
+fn wake_word_timing(result_json: &str, wake_words: &[&str]) -> Option<(f32, f32)> {
+    let v: Value = serde_json::from_str(result_json).ok()?;
+    let words = v.get("result")?.as_array()?;
+    let spoken: Vec<&str> = words
+        .iter()
+        .map(|w| w.get("word").and_then(|x| x.as_str()).unwrap_or(""))
+        .collect();
+    wake_words.iter().find_map(|phrase| {
+        let target: Vec<&str> = phrase.split_whitespace().collect();
+        let start = spoken
+            .windows(target.len().max(1))
+            .position(|w| w.iter().zip(&target).all(|(a, b)| a.eq_ignore_ascii_case(b)))?;
+        let matched = &words[start..start + target.len()];
+        let conf = matched
+            .iter()
+            .map(|w| w.get("conf").and_then(|c| c.as_f64()).unwrap_or(0.0))
+            .sum::<f64>()
+            / matched.len() as f64;
+        let begin = matched.first()?.get("start")?.as_f64()?;
+        let end = matched.last()?.get("end")?.as_f64()?;
+        Some((conf as f32, (end - begin) as f32))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "hey iris turn on": the wake phrase spans 0.5 s to 1.1 s.
+    const RESULT: &str = r#"{"text": "hey iris turn on", "result": [
+        {"word": "hey", "conf": 0.9, "start": 0.5, "end": 0.7},
+        {"word": "iris", "conf": 0.7, "start": 0.7, "end": 1.1},
+        {"word": "turn", "conf": 1.0, "start": 1.3, "end": 1.5},
+        {"word": "on", "conf": 1.0, "start": 1.5, "end": 1.6}
+    ]}"#;
+
+    fn verifier(min_confidence: Option<f32>, min_duration_ms: Option<u64>) -> WakeVerifier {
+        WakeVerifier::new(WakeVerifyConfig {
+            grammar: false,
+            min_confidence,
+            min_duration: min_duration_ms.map(Duration::from_millis),
+        })
+    }
+
+    #[test]
+    fn times_the_first_matching_phrase() {
+        let (conf, duration) = wake_word_timing(RESULT, &["ok computer", "HEY IRIS"]).unwrap();
+        assert!((conf - 0.8).abs() < 1e-6);
+        assert!((duration - 0.6).abs() < 1e-6);
+        let (conf, duration) = wake_word_timing(RESULT, &["iris"]).unwrap();
+        assert!((conf - 0.7).abs() < 1e-6);
+        assert!((duration - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn timing_is_missing_without_word_results() {
+        assert!(wake_word_timing(RESULT, &["hello there"]).is_none());
+        assert!(wake_word_timing(r#"{"text": "hey iris"}"#, &["hey iris"]).is_none());
+        assert!(wake_word_timing("not json", &["hey iris"]).is_none());
+    }
+
+    #[test]
+    fn rejects_low_confidence_and_short_phrases() {
+        let wake = ["hey iris"];
+        assert!(verifier(Some(0.75), None).check_timing(RESULT, &wake).is_ok());
+        assert!(matches!(
+            verifier(Some(0.85), None).check_timing(RESULT, &wake),
+            Err(Rejection::Confidence(_))
+        ));
+        assert!(verifier(None, Some(500)).check_timing(RESULT, &wake).is_ok());
+        assert!(matches!(
+            verifier(None, Some(700)).check_timing(RESULT, &wake),
+            Err(Rejection::Duration(_))
+        ));
+        // No word results at all can't pass a threshold.
+        assert!(verifier(Some(0.1), None).check_timing(r#"{"text": "hey iris"}"#, &wake).is_err());
+    }
+
+    #[test]
+    fn counts_each_outcome_separately() {
+        let v = verifier(Some(0.5), None);
+        assert!(v.record(Ok(true)).is_ok());
+        assert!(v.record(Ok(false)).is_ok());
+        assert!(v.record(Err(Rejection::Confidence(0.2))).is_err());
+        assert!(v.record(Err(Rejection::Grammar)).is_err());
+        let stats = v.stats_json();
+        assert_eq!(stats["candidates"], 4);
+        assert_eq!(stats["accepted"], 1);
+        assert_eq!(stats["unverified"], 1);
+        assert_eq!(stats["rejected"], 2);
+        assert_eq!(stats["rejected_low_confidence"], 1);
+        assert_eq!(stats["rejected_grammar"], 1);
+    }
+}
@@ -0,0 +1,259 @@
+//! Wake phrases against the model's vocabulary: Vosk can never output a word that is
+//! missing from `words.txt`, so such a phrase would never fire.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::arg_value;
+use crate::check_model::read_vocabulary;
+
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OovPolicy {
+    Off,
+    Warn,
+    Fail,
+}
+
+impl OovPolicy {
+    /// `--wake-oov off|warn|fail`, defaulting to warn.
+    pub fn from_args(args: &[(String, String)]) -> Result<Self, String> {
+        match arg_value(args, "--wake-oov") {
+            None | Some("warn") => Ok(OovPolicy::Warn),
+            Some("off") => Ok(OovPolicy::Off),
+            Some("fail") => Ok(OovPolicy::Fail),
+            Some(other) => Err(format!("Unknown --wake-oov '{}' (expected off, warn or fail)", other)),
+        }
+    }
+}
+
+pub struct OovWord {
+    pub phrase: String,
+    pub word: String,
+    /// Closest in-vocabulary spellings, best first; may include two-word splits.
+    pub suggestions: Vec<String>,
+}
+
+impl OovWord {
+    pub fn describe(&self, language: &str) -> String {
+        let mut msg = format!(
+            "Wake phrase '{}' ({}): '{}' is not in the model's vocabulary and can never be recognized",
+            self.phrase, language, self.word
+        );
+        if let Some(best) = self.suggestions.first() {
+            let alias = self
+                .phrase
+                .split_whitespace()
+                .map(|w| if w.eq_ignore_ascii_case(&self.word) { best.as_str() } else { w })
+                .collect::<Vec<_>>()
+                .join(" ");
+            msg.push_str(&format!(
+                ". Close words: {} (add e.g. '{}' as an extra wake phrase)",
+                self.suggestions.join(", "),
+                alias
+            ));
+        }
+        msg
+    }
+}
+
+/// The vocabulary of the model at `model_dir`, if it ships a `words.txt`.
+pub fn model_vocabulary(model_dir: &Path) -> Option<HashSet<String>> {
+    [model_dir.join("graph").join("words.txt"), model_dir.join("words.txt")]
+        .iter()
+        .find(|p| p.is_file())
+        .and_then(|p| read_vocabulary(p).ok())
+        .filter(|v| !v.is_empty())
+}
+
+/// Every out-of-vocabulary word in `phrases`.
+pub fn check(phrases: &[String], vocabulary: &HashSet<String>) -> Vec<OovWord> {
+    let mut out = Vec::new();
+    for phrase in phrases {
+        for word in phrase.split_whitespace().map(str::to_lowercase) {
+            if !vocabulary.contains(&word) {
+                out.push(OovWord {
+                    phrase: phrase.clone(),
+                    suggestions: suggest(&word, vocabulary),
+                    word,
+                });
+            }
+        }
+    }
+    out
+}
+
+/// Loads the vocabulary of `model_dir` and checks `phrases`; `None` when the model has no
+/// `words.txt` to check against.
+pub fn check_model(phrases: &[String], model_dir: &Path) -> Option<Vec<OovWord>> {
+    model_vocabulary(model_dir).map(|v| check(phrases, &v))
+}
+
+/// In-vocabulary words that sound or look like `word`, closest first, then ways to
+/// split it into two in-vocabulary words ("iris" -> "i ris").
+pub fn suggest(word: &str, vocabulary: &HashSet<String>) -> Vec<String> {
+    let key = sound_key(word);
+    let len = word.chars().count();
+    let limit = (len / 3).max(1);
+    let mut close: Vec<(usize, usize, &String)> = vocabulary
+        .iter()
+        .filter(|c| c.chars().count().abs_diff(len) <= limit)
+        .filter_map(|c| {
+            let spelled = levenshtein(word, c);
+            let sounded = levenshtein(&key, &sound_key(c));
+            let d = spelled.min(sounded);
+            (d <= limit).then_some((d, spelled, c))
+        })
+        .collect();
+    close.sort();
+    let mut out: Vec<String> = close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, c)| c.clone())
+        .collect();
+
+    let splits = word
+        .char_indices()
+        .skip(1)
+        .map(|(i, _)| word.split_at(i))
+        .filter(|(a, b)| vocabulary.contains(*a) && vocabulary.contains(*b))
+        .filter(|(a, b)| a.len().min(b.len()) > 1 || matches!(*a, "a" | "i"))
+        .map(|(a, b)| format!("{} {}", a, b));
+    for split in splits {
+        if out.len() >= MAX_SUGGESTIONS {
+            break;
+        }
+        out.push(split);
+    }
+    out
+}
+
+// Rough English spelling-to-sound folding, so "fone" and "phone" compare equal.
+fn sound_key(word: &str) -> String {
+    let w = word
+        .to_lowercase()
+        .replace("ph", "f")
+        .replace("ck", "k")
+        .replace("qu", "kw")
+        .replace('x', "ks");
+    let chars: Vec<char> = w.chars().filter(|c| c.is_alphabetic()).collect();
+    let mut out = String::with_capacity(chars.len());
+    for (i, &c) in chars.iter().enumerate() {
+        let next = chars.get(i + 1).copied();
+        let folded = match c {
+            'c' if matches!(next, Some('e' | 'i' | 'y')) => 's',
+            'c' | 'q' => 'k',
+            'z' => 's',
+            'y' => 'i',
+            c => c,
+        };
+        if !out.ends_with(folded) {
+            out.push(folded);
+        }
+    }
+    out
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_args::args;
+
+    fn vocabulary(words: &[&str]) -> HashSet<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn edit_distance() {
+        for (a, b, expected) in [
+            ("", "", 0),
+            ("", "abc", 3),
+            ("kitten", "sitting", 3),
+            ("flaw", "lawn", 2),
+            ("jarvis", "jarvis", 0),
+            ("fone", "phone", 2),
+        ] {
+            assert_eq!(levenshtein(a, b), expected, "{} / {}", a, b);
+            assert_eq!(levenshtein(b, a), expected, "{} / {}", b, a);
+        }
+    }
+
+    #[test]
+    fn sound_keys() {
+        for (word, key) in [
+            ("phone", "fone"),
+            ("Fone", "fone"),
+            ("quick", "kwik"),
+            ("city", "siti"),
+            ("cat", "kat"),
+            ("box", "boks"),
+            ("zoo", "so"),
+            ("hello", "helo"),
+            ("o'clock", "oklok"),
+        ] {
+            assert_eq!(sound_key(word), key, "{}", word);
+        }
+    }
+
+    #[test]
+    fn suggests_sound_alikes_first() {
+        let vocab = vocabulary(&["phone", "bone", "tone", "cat", "telephone"]);
+        assert_eq!(suggest("fone", &vocab), ["phone", "bone", "tone"]);
+    }
+
+    #[test]
+    fn suggests_two_word_splits() {
+        let vocab = vocabulary(&["i", "ris", "irish", "x", "box"]);
+        assert_eq!(suggest("iris", &vocab), ["irish", "ris", "i ris"]);
+        // Single letters other than "a" and "i" make poor wake words.
+        assert!(suggest("xbox", &vocab).iter().all(|s| s != "x box"));
+    }
+
+    #[test]
+    fn suggestions_are_capped() {
+        let vocab = vocabulary(&["bat", "cat", "fat", "hat", "mat", "pat", "rat"]);
+        assert_eq!(suggest("vat", &vocab).len(), MAX_SUGGESTIONS);
+        assert!(suggest("jarvis", &vocab).is_empty());
+    }
+
+    #[test]
+    fn reports_out_of_vocabulary_words() {
+        let vocab = vocabulary(&["hey", "computer", "phone"]);
+        let phrases = vec!["hey computer".to_string(), "Hey Fone".to_string()];
+        let oov = check(&phrases, &vocab);
+        assert_eq!(oov.len(), 1);
+        assert_eq!(oov[0].word, "fone");
+        assert_eq!(oov[0].suggestions, ["phone"]);
+        let message = oov[0].describe("en");
+        assert!(message.contains("'fone' is not in the model's vocabulary"), "{}", message);
+        assert!(message.contains("add e.g. 'Hey phone'"), "{}", message);
+    }
+
+    #[test]
+    fn oov_policy_from_args() {
+        assert!(OovPolicy::from_args(&[]) == Ok(OovPolicy::Warn));
+        assert!(OovPolicy::from_args(&args(&[("--wake-oov", "off")])) == Ok(OovPolicy::Off));
+        assert!(OovPolicy::from_args(&args(&[("--wake-oov", "fail")])) == Ok(OovPolicy::Fail));
+        assert!(OovPolicy::from_args(&args(&[("--wake-oov", "strict")])).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Notices when the microphone stops working: audio callbacks stop arriving (a driver hang),
+//! or keep arriving with a flat signal (a privacy switch or a dead device).
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::arg_value;
+
+const DEFAULT_STALL_SECS: u64 = 3;
+const DEFAULT_DEAD_SECS: u64 = 30;
+// Anything wider than this peak-to-peak is treated as a live signal, so dithered or
+// DC-offset silence from a muted device still counts as dead.
+const FLAT_SPAN: i32 = 2;
+
+pub struct WatchdogConfig {
+    pub stall_after: Duration,
+    pub dead_after: Duration,
+    /// Re-create the input stream once a problem has lasted this long.
+    pub restart_after: Option<Duration>,
+}
+
+impl WatchdogConfig {
+    /// `--mic-stall-secs` (default 3), `--mic-dead-secs` (default 30) and `--mic-restart-secs`
+    /// (off unless given).
+    pub fn from_args(args: &[(String, String)]) -> Result<Self, String> {
+        let secs = |key: &str| -> Result<Option<Duration>, String> {
+            match arg_value(args, key) {
+                Some(v) => v
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .map(|s| Some(Duration::from_secs(s)))
+                    .ok_or_else(|| format!("Invalid value for {}: '{}' (expected seconds)", key, v)),
+                None => Ok(None),
+            }
+        };
+        Ok(WatchdogConfig {
+            stall_after: secs("--mic-stall-secs")?.unwrap_or(Duration::from_secs(DEFAULT_STALL_SECS)),
+            dead_after: secs("--mic-dead-secs")?.unwrap_or(Duration::from_secs(DEFAULT_DEAD_SECS)),
+            restart_after: secs("--mic-restart-secs")?,
+        })
+    }
+}
+
+/// Updated from the audio callback.
+pub struct Activity {
+    last_callback: Mutex<Instant>,
+    last_signal: Mutex<Instant>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        let now = Instant::now();
+        Activity { last_callback: Mutex::new(now), last_signal: Mutex::new(now) }
+    }
+}
+
+impl Activity {
+    pub fn record(&self, pcm: &[i16]) {
+        let now = Instant::now();
+        *self.last_callback.lock().unwrap() = now;
+        let (min, max) = pcm
+            .iter()
+            .fold((i32::MAX, i32::MIN), |(lo, hi), &s| (lo.min(s as i32), hi.max(s as i32)));
+        if max - min > FLAT_SPAN {
+            *self.last_signal.lock().unwrap() = now;
+        }
+    }
+
+    /// Starts both clocks over, e.g. for a new stream.
+    pub fn reset(&self) {
+        let now = Instant::now();
+        *self.last_callback.lock().unwrap() = now;
+        *self.last_signal.lock().unwrap() = now;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Problem {
+    Stalled,
+    Dead,
+}
+
+impl Problem {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Problem::Stalled => "stalled",
+            Problem::Dead => "dead",
+        }
+    }
+}
+
+pub enum Check {
+    /// A problem started, with the seconds it has lasted so far.
+    Raised(Problem, u64),
+    Recovered(Problem),
+    Restart(Problem),
+}
+
+/// Polled from the main loop.
+pub struct Watchdog {
+    config: WatchdogConfig,
+    current: Option<(Problem, Instant)>,
+    last_restart: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Watchdog { config, current: None, last_restart: None }
+    }
+
+    pub fn check(&mut self, activity: &Activity) -> Option<Check> {
+        let since_callback = activity.last_callback.lock().unwrap().elapsed();
+        let since_signal = activity.last_signal.lock().unwrap().elapsed();
+        let problem = if since_callback >= self.config.stall_after {
+            Some((Problem::Stalled, since_callback))
+        } else if since_signal >= self.config.dead_after {
+            Some((Problem::Dead, since_signal))
+        } else {
+            None
+        };
+
+        let Some((problem, lasted)) = problem else {
+            let (previous, _) = self.current.take()?;
+            self.last_restart = None;
+            return Some(Check::Recovered(previous));
+        };
+        match self.current {
+            Some((current, started)) if current == problem => {
+                let restart_after = self.config.restart_after?;
+                // Retry at the same spacing while the problem persists.
+                let due = self.last_restart.unwrap_or(started) + restart_after;
+                if Instant::now() < due {
+                    return None;
+                }
+                self.last_restart = Some(Instant::now());
+                Some(Check::Restart(problem))
+            }
+            _ => {
+                let started = Instant::now().checked_sub(lasted).unwrap_or_else(Instant::now);
+                self.current = Some((problem, started));
+                Some(Check::Raised(problem, lasted.as_secs()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_args::args;
+
+    const LIVE: [i16; 4] = [0, 100, -100, 0];
+    const FLAT: [i16; 4] = [7, 8, 7, 8];
+
+    fn watchdog(restart_after: Option<u64>) -> Watchdog {
+        Watchdog::new(WatchdogConfig {
+            stall_after: Duration::from_secs(3),
+            dead_after: Duration::from_secs(30),
+            restart_after: restart_after.map(Duration::from_secs),
+        })
+    }
+
+    fn ago(secs: u64) -> Instant {
+        Instant::now() - Duration::from_secs(secs)
+    }
+
+    fn backdate(activity: &Activity, callback: u64, signal: u64) {
+        *activity.last_callback.lock().unwrap() = ago(callback);
+        *activity.last_signal.lock().unwrap() = ago(signal);
+    }
+
+    #[test]
+    fn quiet_while_audio_flows() {
+        let activity = Activity::default();
+        let mut dog = watchdog(Some(1));
+        activity.record(&LIVE);
+        assert!(dog.check(&activity).is_none());
+        assert!(dog.check(&activity).is_none());
+    }
+
+    #[test]
+    fn stall_is_raised_once_and_recovers() {
+        let activity = Activity::default();
+        let mut dog = watchdog(None);
+        backdate(&activity, 5, 5);
+        assert!(matches!(dog.check(&activity), Some(Check::Raised(Problem::Stalled, 5))));
+        assert!(dog.check(&activity).is_none());
+        activity.record(&LIVE);
+        assert!(matches!(dog.check(&activity), Some(Check::Recovered(Problem::Stalled))));
+        assert!(dog.check(&activity).is_none());
+    }
+
+    #[test]
+    fn flat_signal_is_dead_until_it_moves() {
+        let activity = Activity::default();
+        let mut dog = watchdog(None);
+        backdate(&activity, 0, 31);
+        activity.record(&FLAT);
+        assert!(matches!(dog.check(&activity), Some(Check::Raised(Problem::Dead, 31))));
+        activity.record(&FLAT);
+        assert!(dog.check(&activity).is_none());
+        activity.record(&LIVE);
+        assert!(matches!(dog.check(&activity), Some(Check::Recovered(Problem::Dead))));
+    }
+
+    #[test]
+    fn stall_outranks_and_replaces_dead() {
+        let activity = Activity::default();
+        let mut dog = watchdog(None);
+        backdate(&activity, 0, 40);
+        assert!(matches!(dog.check(&activity), Some(Check::Raised(Problem::Dead, _))));
+        backdate(&activity, 4, 44);
+        assert!(matches!(dog.check(&activity), Some(Check::Raised(Problem::Stalled, 4))));
+    }
+
+    #[test]
+    fn restarts_are_spaced_while_the_problem_lasts() {
+        let activity = Activity::default();
+        let mut dog = watchdog(Some(2));
+        backdate(&activity, 1, 1);
+        assert!(dog.check(&activity).is_none());
+        backdate(&activity, 3, 3);
+        assert!(matches!(dog.check(&activity), Some(Check::Raised(Problem::Stalled, 3))));
+        // Raised as of when callbacks stopped, so the first restart is already due.
+        assert!(matches!(dog.check(&activity), Some(Check::Restart(Problem::Stalled))));
+        assert!(dog.check(&activity).is_none());
+        dog.last_restart = Some(ago(2));
+        assert!(matches!(dog.check(&activity), Some(Check::Restart(Problem::Stalled))));
+        activity.reset();
+        assert!(matches!(dog.check(&activity), Some(Check::Recovered(Problem::Stalled))));
+        assert!(dog.last_restart.is_none());
+    }
+
+    #[test]
+    fn config_from_args() {
+        let config = WatchdogConfig::from_args(&[]).unwrap();
+        assert_eq!(config.stall_after, Duration::from_secs(DEFAULT_STALL_SECS));
+        assert_eq!(config.dead_after, Duration::from_secs(DEFAULT_DEAD_SECS));
+        assert!(config.restart_after.is_none());
+        let config = WatchdogConfig::from_args(&args(&[("--mic-restart-secs", "10")])).unwrap();
+        assert_eq!(config.restart_after, Some(Duration::from_secs(10)));
+        for bad in ["0", "-1", "soon"] {
+            assert!(WatchdogConfig::from_args(&args(&[("--mic-dead-secs", bad)])).is_err());
+        }
+    }
+}
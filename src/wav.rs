@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Writes 16-bit mono PCM as a canonical RIFF/WAVE file.
+pub fn write_wav_i16(path: &Path, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(b"RIFF")?;
+    w.write_all(&(36 + data_len).to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+    w.write_all(b"fmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?; // PCM
+    w.write_all(&1u16.to_le_bytes())?; // mono
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&(sample_rate * 2).to_le_bytes())?; // byte rate
+    w.write_all(&2u16.to_le_bytes())?; // block align
+    w.write_all(&16u16.to_le_bytes())?; // bits per sample
+    w.write_all(b"data")?;
+    w.write_all(&data_len.to_le_bytes())?;
+    for s in samples {
+        w.write_all(&s.to_le_bytes())?;
+    }
+    w.flush()
+}
+
+pub struct WavAudio {
+    pub sample_rate: u32,
+    /// Downmixed to mono.
+    pub samples: Vec<i16>,
+}
+
+/// Reads a RIFF/WAVE file (integer PCM 8/16/24/32-bit or 32-bit float), downmixing to mono i16.
+pub fn read_wav(path: &Path) -> Result<WavAudio, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_wav(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_wav(bytes: &[u8]) -> Result<WavAudio, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a RIFF/WAVE file".to_string());
+    }
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+    let mut fmt: Option<(u16, u16, u32, u16)> = None;
+    let mut data: Option<&[u8]> = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32_at(bytes, pos + 4) as usize;
+        let body = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let mut format = u16_at(body, 0);
+                // WAVE_FORMAT_EXTENSIBLE keeps the real format in the sub-format GUID.
+                if format == 0xFFFE && body.len() >= 26 {
+                    format = u16_at(body, 24);
+                }
+                fmt = Some((format, u16_at(body, 2), u32_at(body, 4), u16_at(body, 14)));
+            }
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        pos += 8 + len + (len & 1);
+    }
+    let (format, channels, sample_rate, bits) = fmt.ok_or("missing fmt chunk")?;
+    let data = data.ok_or("missing data chunk")?;
+    if channels == 0 {
+        return Err("zero channels".to_string());
+    }
+    if sample_rate == 0 {
+        return Err("zero sample rate".to_string());
+    }
+
+    let width = (bits as usize).div_ceil(8);
+    let to_f32: fn(&[u8]) -> f32 = match (format, bits) {
+        (1, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+        (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+        (1, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0,
+        (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+        (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        _ => return Err(format!("unsupported sample format {} with {} bits", format, bits)),
+    };
+
+    let frame = width * channels as usize;
+    let samples = data
+        .chunks_exact(frame)
+        .map(|f| {
+            let sum: f32 = f.chunks_exact(width).map(to_f32).sum();
+            let avg = sum / channels as f32;
+            (avg * 32768.0).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        })
+        .collect();
+    Ok(WavAudio { sample_rate, samples })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A RIFF/WAVE file with the given fmt fields, optional extra chunks before `data`.
+    fn wav(format: u16, channels: u16, bits: u16, extra: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend(format.to_le_bytes());
+        fmt.extend(channels.to_le_bytes());
+        fmt.extend(16000u32.to_le_bytes());
+        fmt.extend((16000 * channels as u32 * bits as u32 / 8).to_le_bytes());
+        fmt.extend((channels * bits / 8).to_le_bytes());
+        fmt.extend(bits.to_le_bytes());
+        if format == 0xFFFE {
+            fmt.extend(22u16.to_le_bytes());
+            fmt.extend(bits.to_le_bytes());
+            fmt.extend(0u32.to_le_bytes());
+            fmt.extend(1u16.to_le_bytes());
+            fmt.extend([0; 14]);
+        }
+        let mut out = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        out.extend((fmt.len() as u32).to_le_bytes());
+        out.extend(fmt);
+        out.extend(extra);
+        out.extend(b"data");
+        out.extend((data.len() as u32).to_le_bytes());
+        out.extend(data);
+        out
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let path = std::env::temp_dir().join(format!("irisva-wav-{}.wav", std::process::id()));
+        let samples = [0, 1, -1, i16::MAX, i16::MIN, 1234];
+        write_wav_i16(&path, 22050, &samples).unwrap();
+        let audio = read_wav(&path).unwrap();
+        assert_eq!(audio.sample_rate, 22050);
+        assert_eq!(audio.samples, samples);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn converts_sample_formats_to_i16() {
+        let half = 16384i16;
+        let cases: [(u16, u16, Vec<u8>); 5] = [
+            (1, 8, vec![192]),
+            (1, 16, half.to_le_bytes().to_vec()),
+            (1, 24, vec![0, 0, 0x40]),
+            (1, 32, 0x4000_0000i32.to_le_bytes().to_vec()),
+            (3, 32, 0.5f32.to_le_bytes().to_vec()),
+        ];
+        for (format, bits, data) in cases {
+            let audio = parse_wav(&wav(format, 1, bits, &[], &data)).unwrap();
+            assert_eq!(audio.samples, vec![half], "format {} with {} bits", format, bits);
+        }
+        let extensible = parse_wav(&wav(0xFFFE, 1, 16, &[], &half.to_le_bytes())).unwrap();
+        assert_eq!(extensible.samples, vec![half]);
+    }
+
+    #[test]
+    fn downmixes_channels_and_skips_padded_chunks() {
+        let mut data = Vec::new();
+        for (left, right) in [(1000i16, 3000i16), (-2000, 2000)] {
+            data.extend(left.to_le_bytes());
+            data.extend(right.to_le_bytes());
+        }
+        // An odd-length chunk is followed by a pad byte.
+        let list = [&b"LIST"[..], &3u32.to_le_bytes(), b"abc", &[0]].concat();
+        let audio = parse_wav(&wav(1, 2, 16, &list, &data)).unwrap();
+        assert_eq!(audio.sample_rate, 16000);
+        assert_eq!(audio.samples, vec![2000, 0]);
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        parse_wav(bytes).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        assert!(parse_wav(b"RIFF").is_err());
+        assert!(parse_wav(b"RIFF\0\0\0\0AVI LIST").is_err());
+        assert!(error(b"RIFF\0\0\0\0WAVE").contains("fmt"));
+        let no_data = &wav(1, 1, 16, &[], &[])[..36];
+        assert!(error(no_data).contains("data"));
+        assert!(error(&wav(1, 1, 12, &[], &[0, 0])).contains("unsupported"));
+        assert!(parse_wav(&wav(1, 0, 16, &[], &[0, 0])).is_err());
+        let mut no_rate = wav(1, 1, 16, &[], &[0, 0]);
+        no_rate[24..28].fill(0);
+        assert!(error(&no_rate).contains("sample rate"));
+    }
+}
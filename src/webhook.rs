@@ -0,0 +1,495 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::arg_value;
+use crate::events::EventBus;
+
+// How often a non-empty queue is retried while no new commands arrive.
+const QUEUE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+// Oldest entries are dropped beyond this many undelivered commands per URL.
+const MAX_QUEUED: usize = 10_000;
+
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    pub secret: Option<String>,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+    pub queue_dir: Option<PathBuf>,
+}
+
+impl WebhookConfig {
+    /// Returns `None` when no `--webhook-url` is configured.
+    pub fn from_args(args: &[(String, String)]) -> Option<Result<Self, String>> {
+        let urls = arg_value(args, "--webhook-url")?;
+        Some(Self::parse(urls, args))
+    }
+
+    fn parse(urls: &str, args: &[(String, String)]) -> Result<Self, String> {
+        let urls: Vec<String> = urls
+            .split([',', ' '])
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .map(str::to_string)
+            .collect();
+        if let Some(bad) = urls
+            .iter()
+            .find(|u| !u.starts_with("http://") && !u.starts_with("https://"))
+        {
+            return Err(format!("Invalid --webhook-url '{}' (expected http:// or https://)", bad));
+        }
+        let number = |key: &str, default: u64| -> Result<u64, String> {
+            match arg_value(args, key) {
+                Some(v) => v
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid value for {}: '{}'", key, v)),
+                None => Ok(default),
+            }
+        };
+        Ok(WebhookConfig {
+            urls,
+            secret: arg_value(args, "--webhook-secret").map(str::to_string),
+            timeout: Duration::from_millis(number("--webhook-timeout-ms", 3000)?),
+            retries: number("--webhook-retries", 3)? as u32,
+            backoff: Duration::from_millis(number("--webhook-backoff-ms", 500)?),
+            queue_dir: arg_value(args, "--webhook-queue-dir").map(PathBuf::from),
+        })
+    }
+}
+
+/// Starts one delivery worker per URL, each POSTing `command` events as JSON.
+pub fn spawn(config: WebhookConfig, events: &Arc<EventBus>) -> Result<(), String> {
+    let agent = ureq::AgentBuilder::new().timeout(config.timeout).build();
+    let config = Arc::new(config);
+    for url in &config.urls {
+        let queue = Queue::open(config.queue_dir.as_ref().map(|d| d.join(url_key(url))))?;
+        let worker = Worker {
+            url: url.clone(),
+            agent: agent.clone(),
+            config: config.clone(),
+            queue: Arc::new(Mutex::new(queue)),
+        };
+        // Commands are queued (and written to disk) as they arrive, on their own thread, so
+        // ones that come in while the worker sleeps through a backoff are not lost on exit.
+        let (wake, woken) = mpsc::channel();
+        let rx = events.subscribe();
+        let queue = worker.queue.clone();
+        std::thread::spawn(move || receive(rx, &queue, wake));
+        events.track(std::thread::spawn(move || worker.run(woken)));
+    }
+    Ok(())
+}
+
+fn receive(rx: Receiver<Arc<serde_json::Value>>, queue: &Mutex<Queue>, wake: Sender<()>) {
+    for event in rx {
+        if event.get("type").and_then(|t| t.as_str()) != Some("command") {
+            continue;
+        }
+        {
+            let mut queue = queue.lock().unwrap();
+            let id = queue.next_id();
+            queue.push(id, event.to_string());
+        }
+        if wake.send(()).is_err() {
+            break;
+        }
+    }
+}
+
+struct Item {
+    id: String,
+    body: String,
+    file: Option<PathBuf>,
+}
+
+/// Undelivered payloads, mirrored to disk when a queue directory is configured.
+struct Queue {
+    dir: Option<PathBuf>,
+    items: VecDeque<Item>,
+    seq: u64,
+}
+
+impl Queue {
+    fn open(dir: Option<PathBuf>) -> Result<Self, String> {
+        let mut items = VecDeque::new();
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir).map_err(|e| {
+                format!("Failed to create webhook queue directory {}: {}", dir.display(), e)
+            })?;
+            let mut files: Vec<PathBuf> = fs::read_dir(dir)
+                .map_err(|e| format!("{}: {}", dir.display(), e))?
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|x| x == "json"))
+                .collect();
+            // Names are `<unix ms>-<seq>`, zero padded, so this is enqueue order.
+            files.sort();
+            for file in files {
+                if let Ok(body) = fs::read_to_string(&file) {
+                    let id = file.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                    items.push_back(Item { id, body, file: Some(file) });
+                }
+            }
+        }
+        Ok(Queue { dir, items, seq: 0 })
+    }
+
+    fn push(&mut self, id: String, body: String) {
+        let file = self.dir.as_ref().map(|d| d.join(format!("{}.json", id)));
+        if let Some(path) = &file
+            && let Err(e) = fs::write(path, &body)
+        {
+            eprintln!("Failed to persist webhook payload {}: {}[ERR]", path.display(), e);
+        }
+        self.items.push_back(Item { id, body, file });
+        while self.items.len() > MAX_QUEUED {
+            if let Some(dropped) = self.items.pop_front() {
+                eprintln!("Webhook queue full, dropping {}[ERR]", dropped.id);
+                self.remove_file(&dropped);
+            }
+        }
+    }
+
+    fn front(&self) -> Option<(String, String)> {
+        self.items.front().map(|item| (item.id.clone(), item.body.clone()))
+    }
+
+    // By id: the oldest entries may have been dropped while it was being delivered.
+    fn remove(&mut self, id: &str) {
+        if let Some(index) = self.items.iter().position(|item| item.id == id)
+            && let Some(item) = self.items.remove(index)
+        {
+            self.remove_file(&item);
+        }
+    }
+
+    fn remove_file(&self, item: &Item) {
+        if let Some(path) = &item.file {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn next_id(&mut self) -> String {
+        self.seq += 1;
+        let ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        format!("{:013}-{:06}", ms, self.seq)
+    }
+}
+
+enum Delivery {
+    Delivered,
+    /// The endpoint rejected the payload itself (4xx); retrying won't help.
+    Rejected(String),
+    /// Network error, timeout or 5xx; keep it queued.
+    Failed(String),
+}
+
+struct Worker {
+    url: String,
+    agent: ureq::Agent,
+    config: Arc<WebhookConfig>,
+    /// Shared with the thread that queues incoming commands.
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl Worker {
+    /// Drains the queue on start (it may hold payloads from the disk queue), whenever a command
+    /// is queued, and every so often while it's not empty. Exits after a last pass once the
+    /// event bus is closed.
+    fn run(self, woken: Receiver<()>) {
+        self.drain();
+        while !matches!(woken.recv_timeout(QUEUE_RETRY_INTERVAL), Err(RecvTimeoutError::Disconnected)) {
+            // Anything queued meanwhile goes out in this pass.
+            while woken.try_recv().is_ok() {}
+            self.drain();
+        }
+        self.drain();
+    }
+
+    /// Delivers queued payloads in order, stopping at the first one that still fails. The
+    /// queue stays unlocked during delivery.
+    fn drain(&self) {
+        loop {
+            let Some((id, body)) = self.queue.lock().unwrap().front() else {
+                break;
+            };
+            match self.deliver_with_retry(&id, &body) {
+                Delivery::Delivered => self.queue.lock().unwrap().remove(&id),
+                Delivery::Rejected(why) => {
+                    eprintln!("Webhook {} rejected {}: {}[ERR]", self.url, id, why);
+                    self.queue.lock().unwrap().remove(&id);
+                }
+                Delivery::Failed(why) => {
+                    eprintln!(
+                        "Webhook {} unreachable ({}), {} command(s) queued[ERR]",
+                        self.url,
+                        why,
+                        self.queue.lock().unwrap().items.len()
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    fn deliver_with_retry(&self, id: &str, body: &str) -> Delivery {
+        let mut backoff = self.config.backoff;
+        let mut attempt = 0;
+        loop {
+            match self.deliver(id, body) {
+                Delivery::Failed(_) if attempt < self.config.retries => {
+                    attempt += 1;
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                other => return other,
+            }
+        }
+    }
+
+    fn deliver(&self, id: &str, body: &str) -> Delivery {
+        let mut req = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .set("User-Agent", concat!("IrisVA/", env!("CARGO_PKG_VERSION")))
+            .set("X-IrisVA-Delivery", id);
+        if let Some(secret) = &self.config.secret {
+            req = req.set("X-IrisVA-Signature", &format!("sha256={}", sign(secret, body)));
+        }
+        match req.send_string(body) {
+            Ok(_) => Delivery::Delivered,
+            Err(ureq::Error::Status(code, _)) if (400..500).contains(&code) && code != 408 && code != 429 => {
+                Delivery::Rejected(format!("HTTP {}", code))
+            }
+            Err(ureq::Error::Status(code, _)) => Delivery::Failed(format!("HTTP {}", code)),
+            Err(e) => Delivery::Failed(e.to_string()),
+        }
+    }
+}
+
+/// Hex HMAC-SHA256 of the request body, sent as `X-IrisVA-Signature: sha256=<hex>`.
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    hex(&mac.finalize().into_bytes())
+}
+
+// Stable per-URL queue subdirectory name.
+fn url_key(url: &str) -> String {
+    hex(&Sha256::digest(url.as_bytes())[..8])
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dirs::scratch;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    struct Request {
+        body: String,
+        signature: Option<String>,
+    }
+
+    // Answers each request with the next status (the last one repeats) and hands back what
+    // was posted.
+    fn stub_server(statuses: Vec<u16>) -> (String, Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (n, stream) in listener.incoming().enumerate() {
+                let Ok(mut stream) = stream else { break };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let (mut length, mut signature) = (0, None);
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap_or_default();
+                    match name.to_ascii_lowercase().as_str() {
+                        "content-length" => length = value.trim().parse().unwrap_or(0),
+                        "x-irisva-signature" => signature = Some(value.trim().to_string()),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                let _ = reader.read_exact(&mut body);
+                // Recorded before answering, so a request is visible once the client has its reply.
+                let body = String::from_utf8_lossy(&body).into_owned();
+                if tx.send(Request { body, signature }).is_err() {
+                    break;
+                }
+                let status = statuses[n.min(statuses.len() - 1)];
+                let _ = write!(stream, "HTTP/1.1 {} Stub\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+            }
+        });
+        (url, rx)
+    }
+
+    fn config(url: &str, queue_dir: Option<PathBuf>) -> WebhookConfig {
+        WebhookConfig {
+            urls: vec![url.to_string()],
+            secret: Some("secret".to_string()),
+            timeout: Duration::from_secs(2),
+            retries: 2,
+            backoff: Duration::from_millis(20),
+            queue_dir,
+        }
+    }
+
+    fn worker(config: WebhookConfig, queue: Queue) -> Worker {
+        Worker {
+            url: config.urls[0].clone(),
+            agent: ureq::AgentBuilder::new().timeout(config.timeout).build(),
+            config: Arc::new(config),
+            queue: Arc::new(Mutex::new(queue)),
+        }
+    }
+
+    fn queued(worker: &Worker) -> Vec<String> {
+        worker.queue.lock().unwrap().items.iter().map(|item| item.body.clone()).collect()
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(url_key("http://a/"), url_key("http://a/"));
+        assert_ne!(url_key("http://a/"), url_key("http://b/"));
+    }
+
+    #[test]
+    fn delivers_signed_payloads() {
+        let (url, requests) = stub_server(vec![200]);
+        let worker = worker(config(&url, None), Queue::open(None).unwrap());
+        worker.queue.lock().unwrap().push("1".to_string(), r#"{"type":"command"}"#.to_string());
+        worker.drain();
+        let request = requests.recv().unwrap();
+        assert_eq!(request.body, r#"{"type":"command"}"#);
+        assert_eq!(request.signature, Some(format!("sha256={}", sign("secret", &request.body))));
+        assert!(queued(&worker).is_empty());
+    }
+
+    #[test]
+    fn drops_payloads_the_endpoint_rejects() {
+        let (url, requests) = stub_server(vec![422, 200]);
+        let worker = worker(config(&url, None), Queue::open(None).unwrap());
+        for (id, body) in [("1", "bad"), ("2", "good")] {
+            worker.queue.lock().unwrap().push(id.to_string(), body.to_string());
+        }
+        worker.drain();
+        let bodies: Vec<String> = requests.try_iter().map(|r| r.body).collect();
+        assert_eq!(bodies, ["bad", "good"]);
+        assert!(queued(&worker).is_empty());
+    }
+
+    #[test]
+    fn retries_with_backoff_then_keeps_the_payload_for_later() {
+        // Three attempts per drain fail; the fourth request succeeds.
+        let (url, requests) = stub_server(vec![503, 503, 503, 200]);
+        let dir = scratch("webhook", "retry");
+        let worker = worker(config(&url, Some(dir.clone())), Queue::open(Some(dir.clone())).unwrap());
+        worker.queue.lock().unwrap().push("0000000000001-000001".to_string(), "first".to_string());
+        let started = std::time::Instant::now();
+        worker.drain();
+        // 20 ms, then 40 ms between the attempts.
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        assert_eq!(requests.try_iter().count(), 3);
+        assert_eq!(queued(&worker), ["first"]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        worker.drain();
+        assert_eq!(requests.recv().unwrap().body, "first");
+        assert!(queued(&worker).is_empty());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn replays_the_disk_queue_in_order() {
+        let dir = scratch("webhook", "replay");
+        let mut queue = Queue::open(Some(dir.clone())).unwrap();
+        for body in ["one", "two", "three"] {
+            let id = queue.next_id();
+            queue.push(id, body.to_string());
+        }
+        drop(queue);
+        let (url, requests) = stub_server(vec![200]);
+        let worker = worker(config(&url, Some(dir.clone())), Queue::open(Some(dir.clone())).unwrap());
+        assert_eq!(queued(&worker), ["one", "two", "three"]);
+        // Sent on start, without waiting for a new command or the retry interval.
+        let (wake, woken) = mpsc::channel();
+        let running = std::thread::spawn(move || worker.run(woken));
+        let bodies: Vec<String> =
+            (0..3).filter_map(|_| requests.recv_timeout(Duration::from_secs(5)).ok()).map(|r| r.body).collect();
+        assert_eq!(bodies, ["one", "two", "three"]);
+        drop(wake);
+        running.join().unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn persists_commands_that_arrive_during_a_backoff() {
+        let (url, requests) = stub_server(vec![503]);
+        let dir = scratch("webhook", "backoff");
+        let mut config = config(&url, Some(dir.clone()));
+        config.backoff = Duration::from_millis(300);
+        let events = Arc::new(EventBus::default());
+        spawn(config, &events).unwrap();
+        let command = |text: &str| crate::events::Event::Command {
+            text: text.to_string(),
+            normalized: None,
+            intent: None,
+            speaker: None,
+            language: "en".to_string(),
+        };
+        events.emit(command("first"));
+        // The worker is now waiting out its first backoff.
+        requests.recv().unwrap();
+        events.emit(command("second"));
+        events.emit(crate::events::Event::Swap);
+        // The receiver thread writes "second" on its own schedule; wait for it on disk.
+        let queue_dir = dir.join(url_key(&url));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let saved = loop {
+            let mut saved: Vec<String> = fs::read_dir(&queue_dir)
+                .unwrap()
+                .flatten()
+                .map(|e| fs::read_to_string(e.path()).unwrap_or_default())
+                .collect();
+            saved.sort();
+            let done = saved.len() == 2 && saved[0].contains("\"first\"") && saved[1].contains("\"second\"");
+            if done || Instant::now() > deadline {
+                break saved;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(saved.len(), 2);
+        assert!(saved[0].contains("\"first\"") && saved[1].contains("\"second\""));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::{Sender, TryRecvError};
+use std::time::Duration;
+
+use serde_json::{Value, json};
+use tungstenite::{Message, WebSocket};
+
+use crate::control::{self, Control};
+use crate::events::EventBus;
+
+// How long a client read blocks before we go back to forwarding events.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// Pause after a failed accept, e.g. out of descriptors, so a lasting error doesn't spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Serves events to, and accepts control messages from, websocket clients on `127.0.0.1:<port>`.
+pub fn spawn(port: u16, events: Arc<EventBus>, control: Sender<Control>) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .map_err(|e| format!("Failed to bind websocket server on 127.0.0.1:{}: {}", port, e))?;
+    serve(listener, events, control);
+    Ok(())
+}
+
+/// Accepts websocket clients on an already bound listener, e.g. one from socket activation.
+pub fn serve(listener: TcpListener, events: Arc<EventBus>, control: Sender<Control>) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("WebSocket accept failed: {}", e);
+                    std::thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            let control = control.clone();
+            let client = events.clone();
+            events.track(std::thread::spawn(move || serve_client(stream, client, control)));
+        }
+    });
+}
+
+fn serve_client(stream: TcpStream, events: Arc<EventBus>, control: Sender<Control>) {
+    let peer = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    let mut ws = match tungstenite::accept(stream) {
+        Ok(ws) => ws,
+        Err(e) => {
+            eprintln!("WebSocket handshake with {} failed: {}", peer, e);
+            return;
+        }
+    };
+    if ws.get_ref().set_read_timeout(Some(POLL_INTERVAL)).is_err() {
+        return;
+    }
+    let rx = events.subscribe();
+
+    loop {
+        match ws.read() {
+            Ok(Message::Text(text)) => {
+                let reply = handle_message(text.as_str(), &control);
+                if !send_json(&mut ws, &reply) {
+                    break;
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+        loop {
+            match rx.try_recv() {
+                Ok(event) => {
+                    if !send_json(&mut ws, &event) {
+                        return;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                // Shutting down, and every event has been sent.
+                Err(TryRecvError::Disconnected) => {
+                    let _ = ws.close(None);
+                    let _ = ws.flush();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn handle_message(text: &str, control: &Sender<Control>) -> Value {
+    match serde_json::from_str::<Value>(text) {
+        Ok(v) => control::dispatch(&v, control),
+        Err(e) => json!({ "type": "error", "message": format!("Invalid JSON: {}", e) }),
+    }
+}
+
+/// Returns false once the client has gone away.
+fn send_json(ws: &mut WebSocket<TcpStream>, v: &Value) -> bool {
+    ws.send(Message::text(v.to_string())).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use std::sync::mpsc;
+
+    type Client = WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>;
+
+    fn next(client: &mut Client) -> Value {
+        loop {
+            if let Message::Text(text) = client.read().unwrap() {
+                return serde_json::from_str(text.as_str()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn replies_to_invalid_json_with_an_error() {
+        let (tx, _rx) = mpsc::channel();
+        let reply = handle_message("{\"type\": ", &tx);
+        assert_eq!(reply["type"], "error");
+        assert!(reply["message"].as_str().unwrap().starts_with("Invalid JSON"));
+        assert_eq!(handle_message(r#"{"type": "resume"}"#, &tx), json!({"type": "ack", "command": "resume"}));
+    }
+
+    #[test]
+    fn routes_control_messages_and_forwards_events() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let events = Arc::new(EventBus::default());
+        let (control, commands) = mpsc::channel();
+        serve(listener, events.clone(), control);
+        let (mut client, _) = tungstenite::connect(url).unwrap();
+
+        client.send(Message::text(r#"{"type": "listen-now"}"#)).unwrap();
+        assert_eq!(next(&mut client), json!({"type": "ack", "command": "listen-now"}));
+        assert!(matches!(commands.recv_timeout(Duration::from_secs(2)), Ok(Control::ListenNow)));
+
+        // The client has been answered, so it is subscribed by now.
+        events.emit(Event::State { state: "listening" });
+        let event = next(&mut client);
+        assert_eq!((event["type"].as_str(), event["state"].as_str()), (Some("state"), Some("listening")));
+
+        // Closing the bus ends the session once pending events are out.
+        events.emit(Event::Swap);
+        events.close(Duration::from_secs(2));
+        assert_eq!(next(&mut client)["type"], "swap");
+        assert!(matches!(client.read(), Ok(Message::Close(_)) | Err(_)));
+    }
+}